@@ -1,7 +1,7 @@
 //! A counter task gives some idea on the worker performatnce
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use pg_task::{NextStep, Step, StepResult};
+use pg_task::{NextStep, Step, StepContext, StepResult};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 
@@ -36,7 +36,7 @@ pub struct Start {
 }
 #[async_trait]
 impl Step<Count> for Start {
-    async fn step(self, _db: &PgPool) -> StepResult<Count> {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Count> {
         println!("1..{}: start", self.up_to);
         NextStep::now(Proceed {
             up_to: self.up_to,
@@ -54,7 +54,7 @@ pub struct Proceed {
 }
 #[async_trait]
 impl Step<Count> for Proceed {
-    async fn step(self, _db: &PgPool) -> StepResult<Count> {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Count> {
         let Self {
             up_to,
             mut cur,
@@ -81,7 +81,7 @@ pub struct Finish {
 }
 #[async_trait]
 impl Step<Count> for Finish {
-    async fn step(self, _db: &PgPool) -> StepResult<Count> {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Count> {
         let took = Utc::now() - self.started_at;
         let secs = num_seconds(took);
         let per_sec = self.up_to as f64 / secs;
@@ -1,6 +1,6 @@
 //! Scheduling delayed steps
 use async_trait::async_trait;
-use pg_task::{NextStep, Step, StepResult};
+use pg_task::{NextStep, Step, StepContext, StepResult};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 use std::time::Duration;
@@ -32,7 +32,7 @@ async fn main() -> anyhow::Result<()> {
 pub struct Sleep(u64);
 #[async_trait]
 impl Step<Sleeper> for Sleep {
-    async fn step(self, _db: &PgPool) -> StepResult<Sleeper> {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Sleeper> {
         println!("Sleeping for {} sec", self.0);
         NextStep::delay(Wakeup(self.0), Duration::from_secs(self.0))
     }
@@ -42,7 +42,7 @@ impl Step<Sleeper> for Sleep {
 pub struct Wakeup(u64);
 #[async_trait]
 impl Step<Sleeper> for Wakeup {
-    async fn step(self, _db: &PgPool) -> StepResult<Sleeper> {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Sleeper> {
         println!("Woke up after {} sec", self.0);
         NextStep::none()
     }
@@ -1,5 +1,5 @@
 use async_trait::async_trait;
-use pg_task::{NextStep, Step, StepResult};
+use pg_task::{NextStep, Step, StepContext, StepResult};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 
@@ -19,7 +19,7 @@ pub struct ReadName {
 impl Step<Greeter> for ReadName {
     const RETRY_LIMIT: i32 = 5;
 
-    async fn step(self, _db: &PgPool) -> StepResult<Greeter> {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Greeter> {
         let name = std::fs::read_to_string(self.filename)?;
         NextStep::now(SayHello { name })
     }
@@ -31,7 +31,7 @@ pub struct SayHello {
 }
 #[async_trait]
 impl Step<Greeter> for SayHello {
-    async fn step(self, _db: &PgPool) -> StepResult<Greeter> {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Greeter> {
         println!("Hello, {}", self.name);
         NextStep::none()
     }
@@ -10,7 +10,7 @@ pub async fn init() -> Result<PgPool> {
 
 async fn connect() -> Result<PgPool> {
     let db = sqlx::PgPool::connect(&std::env::var("DATABASE_URL")?).await?;
-    sqlx::migrate!().run(&db).await?;
+    pg_task::migrate(&db).await?;
     Ok(db)
 }
 
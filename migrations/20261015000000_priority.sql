@@ -0,0 +1,5 @@
+ALTER TABLE pg_task ADD COLUMN priority INT NOT NULL DEFAULT 0;
+
+CREATE INDEX pg_task_priority_wakeup_at_idx ON pg_task (priority DESC, wakeup_at);
+
+COMMENT ON COLUMN pg_task.priority IS 'Ready tasks with higher priority run first';
@@ -0,0 +1,3 @@
+ALTER TABLE pg_task ADD COLUMN cancelled_at timestamptz;
+
+COMMENT ON COLUMN pg_task.cancelled_at IS 'Time the running task was asked to cancel, it will be removed as soon as the current step is finished';
@@ -0,0 +1,3 @@
+ALTER TABLE pg_task ADD COLUMN is_paused BOOLEAN NOT NULL DEFAULT false;
+
+COMMENT ON COLUMN pg_task.is_paused IS 'Indicates if the task is paused, its next steps would not run until it''s resumed';
@@ -0,0 +1,5 @@
+ALTER TABLE pg_task ADD COLUMN locked_until timestamptz;
+
+ALTER TABLE pg_task DROP COLUMN is_running;
+
+COMMENT ON COLUMN pg_task.locked_until IS 'Indicates that the current step is running, the lock is renewed while the step is alive, so it expires if the worker is crashed';
@@ -0,0 +1,3 @@
+ALTER TABLE pg_task ADD COLUMN locked_by TEXT;
+
+COMMENT ON COLUMN pg_task.locked_by IS 'Id of the worker running the current step';
@@ -0,0 +1,10 @@
+CREATE OR REPLACE FUNCTION pg_task_notify_on_change()
+RETURNS trigger AS $$
+BEGIN
+  PERFORM pg_notify('pg_task_changed', TG_TABLE_SCHEMA);
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+COMMENT ON FUNCTION pg_task_notify_on_change
+IS 'Notifies workers about the tasks changes, the payload is the schema of the table, so workers can ignore changes in other schemas';
@@ -0,0 +1 @@
+ALTER TABLE pg_task ALTER COLUMN step TYPE JSONB USING step::JSONB;
@@ -0,0 +1,3 @@
+ALTER TABLE pg_task ADD COLUMN progress JSONB;
+
+COMMENT ON COLUMN pg_task.progress IS 'Progress of the current step reported by the step itself as `{"done": 37, "total": 120}`';
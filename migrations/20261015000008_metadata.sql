@@ -0,0 +1,5 @@
+ALTER TABLE pg_task ADD COLUMN metadata JSONB NOT NULL DEFAULT '{}';
+
+CREATE INDEX pg_task_metadata_idx ON pg_task USING GIN (metadata);
+
+COMMENT ON COLUMN pg_task.metadata IS 'Arbitrary metadata of the task, e.g. for filtering tasks by tenant';
@@ -0,0 +1,5 @@
+ALTER TABLE pg_task ADD COLUMN unique_key TEXT;
+
+CREATE UNIQUE INDEX pg_task_unique_key_idx ON pg_task (unique_key) WHERE unique_key IS NOT NULL;
+
+COMMENT ON COLUMN pg_task.unique_key IS 'Deduplication key, only one task with the same key could exist at a time';
@@ -0,0 +1,6 @@
+ALTER TABLE pg_task ADD COLUMN done_at TIMESTAMPTZ;
+
+DROP INDEX pg_task_unique_key_idx;
+CREATE UNIQUE INDEX pg_task_unique_key_idx ON pg_task (unique_key) WHERE unique_key IS NOT NULL AND done_at IS NULL;
+
+COMMENT ON COLUMN pg_task.done_at IS 'Time the task was completed, only set for workers keeping done tasks';
@@ -0,0 +1,21 @@
+CREATE TABLE pg_task_log (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    task_id UUID NOT NULL,
+    from_step JSONB NOT NULL,
+    to_step JSONB,
+    attempt INT NOT NULL,
+    error TEXT,
+    started_at timestamptz NOT NULL,
+    finished_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX pg_task_log_task_id_idx ON pg_task_log (task_id, finished_at);
+
+COMMENT ON TABLE pg_task_log IS 'pg_task step transitions, only written with the `audit-log` feature';
+COMMENT ON COLUMN pg_task_log.task_id IS 'Id of the task, the task itself could be already removed';
+COMMENT ON COLUMN pg_task_log.from_step IS 'The step which was run';
+COMMENT ON COLUMN pg_task_log.to_step IS 'The next step, the same step for retries, null if the task is completed or failed';
+COMMENT ON COLUMN pg_task_log.attempt IS 'Number of the attempt to run the step, starting from 1';
+COMMENT ON COLUMN pg_task_log.error IS 'Error of the step if any';
+COMMENT ON COLUMN pg_task_log.started_at IS 'Time the step was started';
+COMMENT ON COLUMN pg_task_log.finished_at IS 'Time the step was finished';
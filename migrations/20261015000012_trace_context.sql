@@ -0,0 +1,3 @@
+ALTER TABLE pg_task ADD COLUMN trace_context JSONB;
+
+COMMENT ON COLUMN pg_task.trace_context IS 'OpenTelemetry trace context of the code which enqueued the task, only stored with the `opentelemetry` feature';
@@ -0,0 +1,10 @@
+ALTER TABLE pg_task ADD COLUMN task_name TEXT NOT NULL DEFAULT '';
+
+UPDATE pg_task
+SET task_name = CASE jsonb_typeof(step)
+    WHEN 'object' THEN (SELECT key FROM jsonb_object_keys(step) AS key LIMIT 1)
+    WHEN 'string' THEN step #>> '{}'
+    ELSE ''
+END;
+
+COMMENT ON COLUMN pg_task.task_name IS 'Name of the task, i.e. the variant of the enum passed to the worker';
@@ -0,0 +1,16 @@
+CREATE TABLE pg_task_worker (
+    id TEXT PRIMARY KEY,
+    hostname TEXT NOT NULL,
+    pid INT NOT NULL,
+    started_at timestamptz NOT NULL DEFAULT now(),
+    heartbeat_at timestamptz NOT NULL DEFAULT now(),
+    alive_until timestamptz NOT NULL
+);
+
+COMMENT ON TABLE pg_task_worker IS 'pg_task workers, running tasks refer to them by pg_task.locked_by';
+COMMENT ON COLUMN pg_task_worker.id IS 'Id of the worker';
+COMMENT ON COLUMN pg_task_worker.hostname IS 'Hostname of the worker';
+COMMENT ON COLUMN pg_task_worker.pid IS 'Process id of the worker';
+COMMENT ON COLUMN pg_task_worker.started_at IS 'Time the worker was started';
+COMMENT ON COLUMN pg_task_worker.heartbeat_at IS 'Time of the last heartbeat of the worker';
+COMMENT ON COLUMN pg_task_worker.alive_until IS 'The worker is considered dead if there are no heartbeats until this time';
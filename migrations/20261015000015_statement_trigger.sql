@@ -0,0 +1,18 @@
+CREATE OR REPLACE FUNCTION pg_task_notify_on_change()
+RETURNS trigger AS $$
+BEGIN
+  PERFORM pg_notify('pg_task_changed', TG_TABLE_SCHEMA);
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER pg_task_changed ON pg_task;
+
+CREATE TRIGGER pg_task_changed
+AFTER INSERT OR UPDATE
+ON pg_task
+FOR EACH STATEMENT
+EXECUTE PROCEDURE pg_task_notify_on_change();
+
+COMMENT ON TRIGGER pg_task_changed ON pg_task
+IS 'Notifies workers once per statement, so bulk inserts and updates don''t cause notification storms';
@@ -0,0 +1,21 @@
+DROP INDEX pg_task_priority_wakeup_at_idx;
+
+CREATE INDEX pg_task_ready_idx
+ON pg_task (priority DESC, GREATEST(wakeup_at, locked_until))
+WHERE error IS NULL AND done_at IS NULL AND cancelled_at IS NULL AND is_paused = false;
+
+CREATE INDEX pg_task_closest_idx
+ON pg_task (GREATEST(wakeup_at, locked_until))
+WHERE error IS NULL AND done_at IS NULL AND cancelled_at IS NULL AND is_paused = false;
+
+CREATE INDEX pg_task_locked_by_idx ON pg_task (locked_by) WHERE locked_by IS NOT NULL;
+
+CREATE INDEX pg_task_failed_idx ON pg_task (updated_at) WHERE error IS NOT NULL;
+
+CREATE INDEX pg_task_done_at_idx ON pg_task (done_at) WHERE done_at IS NOT NULL;
+
+COMMENT ON INDEX pg_task_ready_idx IS 'Fetching the ready tasks by priority';
+COMMENT ON INDEX pg_task_closest_idx IS 'Fetching the closest task to wait for when there are no ready tasks';
+COMMENT ON INDEX pg_task_locked_by_idx IS 'Unlocking the tasks of a restarted worker';
+COMMENT ON INDEX pg_task_failed_idx IS 'Listing the dead tasks';
+COMMENT ON INDEX pg_task_done_at_idx IS 'Cleaning up the done tasks';
@@ -0,0 +1,5 @@
+ALTER TABLE pg_task ADD COLUMN parent_id UUID;
+
+CREATE INDEX pg_task_parent_id_idx ON pg_task (parent_id) WHERE parent_id IS NOT NULL;
+
+COMMENT ON COLUMN pg_task.parent_id IS 'Id of the task which spawned this one as a child';
@@ -0,0 +1,3 @@
+ALTER TABLE pg_task ADD COLUMN is_joining BOOLEAN NOT NULL DEFAULT false;
+
+COMMENT ON COLUMN pg_task.is_joining IS 'Indicates if the task waits for all its children to finish before running the current step';
@@ -0,0 +1,10 @@
+ALTER TABLE pg_task
+    ADD COLUMN is_awaiting_signal BOOLEAN NOT NULL DEFAULT false,
+    ADD COLUMN signal_timeout_step JSONB,
+    ADD COLUMN signal JSONB,
+    ADD COLUMN signaled_at timestamptz;
+
+COMMENT ON COLUMN pg_task.is_awaiting_signal IS 'Indicates if the task waits for a signal before running the current step';
+COMMENT ON COLUMN pg_task.signal_timeout_step IS 'Step to run instead of the current one if no signal is received until `wakeup_at`';
+COMMENT ON COLUMN pg_task.signal IS 'Payload of the last signal received by the task';
+COMMENT ON COLUMN pg_task.signaled_at IS 'Time the last signal was received';
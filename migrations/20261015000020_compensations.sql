@@ -0,0 +1,3 @@
+ALTER TABLE pg_task ADD COLUMN compensations JSONB NOT NULL DEFAULT '[]';
+
+COMMENT ON COLUMN pg_task.compensations IS 'Completed steps to compensate if a later step fails, compensated steps are removed from the end one by one';
@@ -0,0 +1,3 @@
+ALTER TABLE pg_task ADD COLUMN payload_version INT NOT NULL DEFAULT 0;
+
+COMMENT ON COLUMN pg_task.payload_version IS 'Version of the step payload format, see `Scheduler::PAYLOAD_VERSION`';
@@ -0,0 +1,5 @@
+ALTER TABLE pg_task ADD COLUMN is_unparseable BOOLEAN NOT NULL DEFAULT false;
+
+CREATE INDEX pg_task_unparseable_idx ON pg_task (id) WHERE is_unparseable;
+
+COMMENT ON COLUMN pg_task.is_unparseable IS 'Indicates if the step can''t be deserialized, such tasks aren''t run until they are fixed';
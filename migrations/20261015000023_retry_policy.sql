@@ -0,0 +1,6 @@
+ALTER TABLE pg_task
+    ADD COLUMN retry_limit INT,
+    ADD COLUMN retry_delay INTERVAL;
+
+COMMENT ON COLUMN pg_task.retry_limit IS 'Overrides `RETRY_LIMIT` of the task steps if set';
+COMMENT ON COLUMN pg_task.retry_delay IS 'Overrides the retry delay of the task steps if set';
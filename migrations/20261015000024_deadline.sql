@@ -0,0 +1,6 @@
+ALTER TABLE pg_task
+    ADD COLUMN deadline_at timestamptz,
+    ADD COLUMN expired_at timestamptz;
+
+COMMENT ON COLUMN pg_task.deadline_at IS 'Time after which the task steps aren''t run anymore';
+COMMENT ON COLUMN pg_task.expired_at IS 'Time the task was expired as it wasn''t finished before its deadline';
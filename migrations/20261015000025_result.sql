@@ -0,0 +1,3 @@
+ALTER TABLE pg_task ADD COLUMN result JSONB;
+
+COMMENT ON COLUMN pg_task.result IS 'Value returned by the last step of the task using `NextStep::done_with`';
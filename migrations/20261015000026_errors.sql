@@ -0,0 +1,3 @@
+ALTER TABLE pg_task ADD COLUMN errors JSONB NOT NULL DEFAULT '[]';
+
+COMMENT ON COLUMN pg_task.errors IS 'Errors of the task steps, each one is an object with the error `chain`, the `step` name, the `attempt` number and the time it happened `at`';
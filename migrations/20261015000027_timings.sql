@@ -0,0 +1,8 @@
+ALTER TABLE pg_task
+    ADD COLUMN step_started_at timestamptz,
+    ADD COLUMN step_finished_at timestamptz,
+    ADD COLUMN timings JSONB NOT NULL DEFAULT '[]';
+
+COMMENT ON COLUMN pg_task.step_started_at IS 'Time the last run step was started';
+COMMENT ON COLUMN pg_task.step_finished_at IS 'Time the last run step was finished';
+COMMENT ON COLUMN pg_task.timings IS 'Runs of the task steps, each one is an object with the `step` name, the `attempt` number, `started_at` and `finished_at` times';
@@ -0,0 +1,13 @@
+CREATE TABLE pg_task_blob (
+    task_id UUID NOT NULL REFERENCES pg_task (id) ON DELETE CASCADE,
+    key TEXT NOT NULL,
+    data BYTEA NOT NULL,
+    updated_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (task_id, key)
+);
+
+COMMENT ON TABLE pg_task_blob IS 'Large intermediate data of pg_task steps, removed as the task is done, expired or removed';
+COMMENT ON COLUMN pg_task_blob.task_id IS 'Id of the task the data belongs to';
+COMMENT ON COLUMN pg_task_blob.key IS 'Name of the data within the task';
+COMMENT ON COLUMN pg_task_blob.data IS 'The data';
+COMMENT ON COLUMN pg_task_blob.updated_at IS 'Time the data was stored';
@@ -0,0 +1,12 @@
+ALTER TABLE pg_task ADD COLUMN step_name TEXT NOT NULL DEFAULT '';
+
+UPDATE pg_task
+SET step_name = CASE jsonb_typeof(step -> task_name)
+    WHEN 'object' THEN (SELECT key FROM jsonb_object_keys(step -> task_name) AS key LIMIT 1)
+    WHEN 'string' THEN step ->> task_name
+    ELSE ''
+END
+WHERE jsonb_typeof(step) = 'object'
+  AND task_name NOT LIKE 'pg\_task\_%';
+
+COMMENT ON COLUMN pg_task.step_name IS 'Name of the current step, i.e. the variant of the task enum';
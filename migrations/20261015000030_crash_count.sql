@@ -0,0 +1,3 @@
+ALTER TABLE pg_task ADD COLUMN crash_count INT NOT NULL DEFAULT 0;
+
+COMMENT ON COLUMN pg_task.crash_count IS 'Number of consecutive attempts to run the current step interrupted by the worker crash, i.e. by the lock expiration';
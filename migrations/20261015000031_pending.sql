@@ -0,0 +1,4 @@
+CREATE INDEX pg_task_pending_idx ON pg_task (task_name)
+WHERE done_at IS NULL AND error IS NULL AND expired_at IS NULL;
+
+COMMENT ON INDEX pg_task_pending_idx IS 'Counting the pending tasks to enforce `Scheduler::max_pending`';
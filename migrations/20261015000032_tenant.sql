@@ -0,0 +1,11 @@
+ALTER TABLE pg_task ADD COLUMN tenant TEXT;
+
+CREATE INDEX pg_task_tenant_running_idx ON pg_task (tenant)
+WHERE tenant IS NOT NULL AND locked_by IS NOT NULL;
+
+CREATE INDEX pg_task_tenant_pending_idx ON pg_task (tenant)
+WHERE tenant IS NOT NULL AND done_at IS NULL AND error IS NULL AND expired_at IS NULL;
+
+COMMENT ON COLUMN pg_task.tenant IS 'Tenant the task belongs to, children inherit it';
+COMMENT ON INDEX pg_task_tenant_running_idx IS 'Counting the running tasks of tenants to enforce `Worker::with_tenant_concurrency`';
+COMMENT ON INDEX pg_task_tenant_pending_idx IS 'Counting the pending tasks of tenants to enforce `Scheduler::max_pending_per_tenant`';
@@ -0,0 +1,12 @@
+ALTER TABLE pg_task ADD COLUMN group_key TEXT;
+ALTER TABLE pg_task ADD COLUMN group_seq BIGINT;
+
+CREATE SEQUENCE pg_task_group_seq;
+
+CREATE INDEX pg_task_group_idx ON pg_task (group_key, group_seq)
+WHERE group_key IS NOT NULL AND done_at IS NULL AND error IS NULL AND expired_at IS NULL;
+
+COMMENT ON COLUMN pg_task.group_key IS 'Tasks sharing the key run one at a time in the enqueue order';
+COMMENT ON COLUMN pg_task.group_seq IS 'The enqueue order of the tasks of a group';
+COMMENT ON SEQUENCE pg_task_group_seq IS 'Ordering the tasks of groups';
+COMMENT ON INDEX pg_task_group_idx IS 'Finding the unfinished tasks enqueued earlier in the same group';
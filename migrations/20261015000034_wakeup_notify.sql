@@ -0,0 +1,47 @@
+CREATE OR REPLACE FUNCTION pg_task_notify_wakeup()
+RETURNS trigger AS $$
+DECLARE
+  wakeup_at TIMESTAMPTZ;
+BEGIN
+  SELECT MIN(
+    CASE
+      WHEN done_at IS NOT NULL
+        OR error IS NOT NULL
+        OR expired_at IS NOT NULL
+        OR cancelled_at IS NOT NULL
+      THEN now()
+      ELSE GREATEST(changed.wakeup_at, locked_until)
+    END
+  )
+  INTO wakeup_at
+  FROM changed;
+  IF wakeup_at IS NOT NULL THEN
+    PERFORM pg_notify(
+      'pg_task_wakeup',
+      TG_TABLE_SCHEMA || ' ' || (extract(epoch FROM wakeup_at) * 1000000)::BIGINT
+    );
+  END IF;
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER pg_task_wakeup_on_insert
+AFTER INSERT
+ON pg_task
+REFERENCING NEW TABLE AS changed
+FOR EACH STATEMENT
+EXECUTE PROCEDURE pg_task_notify_wakeup();
+
+CREATE TRIGGER pg_task_wakeup_on_update
+AFTER UPDATE
+ON pg_task
+REFERENCING NEW TABLE AS changed
+FOR EACH STATEMENT
+EXECUTE PROCEDURE pg_task_notify_wakeup();
+
+COMMENT ON FUNCTION pg_task_notify_wakeup
+IS 'Notifies workers about the earliest wakeup time of the changed tasks, the payload is the schema of the table and the time in microseconds since the epoch. Finished tasks could unblock others, so they wake workers up right away.';
+COMMENT ON TRIGGER pg_task_wakeup_on_insert ON pg_task
+IS 'Lets workers sleep until the earliest wakeup of the tasks instead of checking the table on each change';
+COMMENT ON TRIGGER pg_task_wakeup_on_update ON pg_task
+IS 'Lets workers sleep until the earliest wakeup of the tasks instead of checking the table on each change';
@@ -0,0 +1,11 @@
+CREATE TABLE pg_task_control (
+    id BOOL PRIMARY KEY DEFAULT true CHECK (id),
+    is_paused BOOL NOT NULL DEFAULT false,
+    paused_at TIMESTAMPTZ
+);
+
+INSERT INTO pg_task_control DEFAULT VALUES;
+
+COMMENT ON TABLE pg_task_control IS 'The queue-wide switches, it has a single row';
+COMMENT ON COLUMN pg_task_control.is_paused IS 'Workers don''t claim tasks while it''s set';
+COMMENT ON COLUMN pg_task_control.paused_at IS 'When the queue was paused';
@@ -0,0 +1,10 @@
+CREATE TABLE pg_task_rate_limit (
+    step_type TEXT PRIMARY KEY,
+    tokens FLOAT8 NOT NULL,
+    refilled_at TIMESTAMPTZ NOT NULL
+);
+
+COMMENT ON TABLE pg_task_rate_limit IS 'Token buckets of the steps rate limited across all the workers';
+COMMENT ON COLUMN pg_task_rate_limit.step_type IS 'Type name of the rate limited step';
+COMMENT ON COLUMN pg_task_rate_limit.tokens IS 'Tokens left at the refill time, negative while steps wait for the tokens they''ve taken ahead';
+COMMENT ON COLUMN pg_task_rate_limit.refilled_at IS 'When the tokens were last refilled';
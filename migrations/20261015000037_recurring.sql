@@ -0,0 +1,25 @@
+CREATE TABLE pg_task_recurring (
+    name TEXT PRIMARY KEY,
+    step JSONB NOT NULL,
+    task_name TEXT NOT NULL,
+    step_name TEXT NOT NULL,
+    payload_version INT NOT NULL,
+    cron TEXT NOT NULL,
+    timezone TEXT NOT NULL,
+    next_run_at TIMESTAMPTZ,
+    last_run_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX pg_task_recurring_next_run_at_idx ON pg_task_recurring (next_run_at);
+
+COMMENT ON TABLE pg_task_recurring IS 'Cron schedules the workers enqueue tasks by';
+COMMENT ON COLUMN pg_task_recurring.name IS 'Unique name of the schedule';
+COMMENT ON COLUMN pg_task_recurring.step IS 'The first step of the enqueued tasks';
+COMMENT ON COLUMN pg_task_recurring.task_name IS 'Name of the enqueued task';
+COMMENT ON COLUMN pg_task_recurring.step_name IS 'Name of the first step of the enqueued tasks';
+COMMENT ON COLUMN pg_task_recurring.payload_version IS 'Payload version of the step';
+COMMENT ON COLUMN pg_task_recurring.cron IS 'Cron expression of minute, hour, day of month, month and day of week';
+COMMENT ON COLUMN pg_task_recurring.timezone IS 'IANA time zone the cron expression is evaluated in, e.g. Europe/Berlin';
+COMMENT ON COLUMN pg_task_recurring.next_run_at IS 'When the next task is enqueued, NULL if the expression never matches';
+COMMENT ON COLUMN pg_task_recurring.last_run_at IS 'When the last task was enqueued';
@@ -0,0 +1,5 @@
+ALTER TABLE pg_task_recurring
+ADD COLUMN catch_up TEXT NOT NULL DEFAULT 'run_once'
+CHECK (catch_up IN ('run_once', 'run_all', 'skip'));
+
+COMMENT ON COLUMN pg_task_recurring.catch_up IS 'What to do with the runs missed while no worker was running: run_once, run_all or skip';
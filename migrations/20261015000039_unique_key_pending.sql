@@ -0,0 +1,5 @@
+DROP INDEX pg_task_unique_key_idx;
+CREATE UNIQUE INDEX pg_task_unique_key_idx ON pg_task (unique_key)
+WHERE unique_key IS NOT NULL AND done_at IS NULL AND error IS NULL AND expired_at IS NULL;
+
+COMMENT ON INDEX pg_task_unique_key_idx IS 'Deduplicates the pending tasks, the key is released once the task is done, failed or expired';
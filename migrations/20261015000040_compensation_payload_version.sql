@@ -0,0 +1,18 @@
+ALTER TABLE pg_task ADD COLUMN signal_timeout_payload_version INT;
+
+UPDATE pg_task
+SET signal_timeout_payload_version = payload_version
+WHERE signal_timeout_step IS NOT NULL;
+
+UPDATE pg_task
+SET compensations = (
+    SELECT jsonb_agg(
+        jsonb_build_object('step', entry.step, 'payload_version', pg_task.payload_version)
+        ORDER BY entry.position
+    )
+    FROM jsonb_array_elements(compensations) WITH ORDINALITY AS entry(step, position)
+)
+WHERE compensations <> '[]';
+
+COMMENT ON COLUMN pg_task.signal_timeout_payload_version IS 'Version of the `signal_timeout_step` payload';
+COMMENT ON COLUMN pg_task.compensations IS 'Completed steps to compensate if a later step fails, as `{"step": ..., "payload_version": ...}` objects, compensated steps are removed from the end one by one';
@@ -0,0 +1,19 @@
+UPDATE pg_task
+SET wakeup_at = deadline_at
+WHERE is_awaiting_signal
+  AND signal_timeout_step IS NULL
+  AND deadline_at IS NOT NULL;
+
+DROP INDEX pg_task_ready_idx;
+DROP INDEX pg_task_closest_idx;
+
+CREATE INDEX pg_task_ready_idx
+ON pg_task (priority DESC, GREATEST(LEAST(wakeup_at, deadline_at), locked_until))
+WHERE error IS NULL AND done_at IS NULL AND cancelled_at IS NULL AND is_paused = false;
+
+CREATE INDEX pg_task_closest_idx
+ON pg_task (GREATEST(LEAST(wakeup_at, deadline_at), locked_until))
+WHERE error IS NULL AND done_at IS NULL AND cancelled_at IS NULL AND is_paused = false;
+
+COMMENT ON INDEX pg_task_ready_idx IS 'Fetching the ready tasks by priority, tasks with a deadline are ready by the deadline';
+COMMENT ON INDEX pg_task_closest_idx IS 'Fetching the closest task to wait for when there are no ready tasks';
@@ -0,0 +1,3 @@
+ALTER TABLE pg_task ADD COLUMN is_signal_pending BOOLEAN NOT NULL DEFAULT false;
+
+COMMENT ON COLUMN pg_task.is_signal_pending IS 'Indicates if a signal is received while the task isn''t waiting for it, the next wait for a signal consumes it instead of waiting';
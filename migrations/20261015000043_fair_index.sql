@@ -0,0 +1,5 @@
+CREATE INDEX pg_task_fair_idx
+ON pg_task (task_name, priority DESC, GREATEST(LEAST(wakeup_at, deadline_at), locked_until))
+WHERE error IS NULL AND done_at IS NULL AND cancelled_at IS NULL AND is_paused = false;
+
+COMMENT ON INDEX pg_task_fair_idx IS 'Walking the names of the pending tasks and fetching the ready ones of each name for the fair scheduling';
@@ -0,0 +1,10 @@
+ALTER TABLE pg_task
+    ADD COLUMN step_bytes BYTEA,
+    ADD COLUMN signal_timeout_step_bytes BYTEA;
+
+ALTER TABLE pg_task_recurring ADD COLUMN step_bytes BYTEA;
+
+COMMENT ON COLUMN pg_task.step_bytes IS 'The current step encoded with a binary codec, `step` keeps only the codec tag as `{"pg_task_<codec>": null}` then';
+COMMENT ON COLUMN pg_task.signal_timeout_step_bytes IS 'The `signal_timeout_step` encoded with a binary codec';
+COMMENT ON COLUMN pg_task_recurring.step_bytes IS 'The step encoded with a binary codec';
+COMMENT ON COLUMN pg_task.compensations IS 'Completed steps to compensate if a later step fails, as `{"step": ..., "step_bytes": "<hex>", "payload_version": ...}` objects, compensated steps are removed from the end one by one';
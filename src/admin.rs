@@ -0,0 +1,190 @@
+//! Helpers for building operational tools, e.g. an internal tasks page
+
+use crate::{payload, util::db_error, Result, Scheduler, TaskInfo};
+use chrono::{DateTime, Utc};
+use sqlx::{types::Uuid, PgExecutor};
+
+/// Filters tasks, the `None` fields are ignored
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    /// Name of the task or the step, i.e. the outer or the inner variant of
+    /// the serialized step
+    pub step_name: Option<String>,
+    /// Only errored or not errored tasks
+    pub errored: Option<bool>,
+    /// Only running or not running tasks
+    pub running: Option<bool>,
+    /// Only paused or not paused tasks
+    pub paused: Option<bool>,
+    /// Only done or not done tasks, see
+    /// [`Worker::with_keep_done`](crate::Worker::with_keep_done)
+    pub done: Option<bool>,
+    /// Only tasks scheduled to wake up before the time
+    pub scheduled_before: Option<DateTime<Utc>>,
+    /// Only tasks scheduled to wake up after the time
+    pub scheduled_after: Option<DateTime<Utc>>,
+    /// Only children of the task, see
+    /// [`StepContext::spawn_child`](crate::StepContext::spawn_child)
+    pub parent_id: Option<Uuid>,
+    /// Only tasks which step can or can't be deserialized, see
+    /// [`fix_unparseable`]
+    pub unparseable: Option<bool>,
+    /// Only tasks of the tenant, see
+    /// [`Scheduler::enqueue_for_tenant`](crate::Scheduler::enqueue_for_tenant)
+    pub tenant: Option<String>,
+}
+
+/// A page of a list
+#[derive(Clone, Copy, Debug)]
+pub struct Page {
+    /// The maximum number of items on the page
+    pub limit: i64,
+    /// Number of items to skip
+    pub offset: i64,
+}
+
+impl Default for Page {
+    fn default() -> Self {
+        Self {
+            limit: 50,
+            offset: 0,
+        }
+    }
+}
+
+impl Page {
+    /// Returns the next page
+    pub fn next(self) -> Self {
+        Self {
+            offset: self.offset + self.limit,
+            ..self
+        }
+    }
+}
+
+/// Lists tasks matching the filter ordered by their wakeup time
+pub async fn list_tasks<'e>(
+    db: impl PgExecutor<'e>,
+    filter: Filter,
+    page: Page,
+) -> Result<Vec<TaskInfo>> {
+    sqlx::query_as!(
+        TaskInfo,
+        r#"
+        SELECT
+            id,
+            task_name,
+            step_name,
+            step,
+            step_bytes,
+            tried,
+            crash_count,
+            error,
+            wakeup_at,
+            COALESCE(locked_until > now(), false) AS "is_running!",
+            is_paused,
+            is_awaiting_signal,
+            is_unparseable,
+            priority,
+            metadata,
+            tenant,
+            progress,
+            created_at,
+            updated_at,
+            done_at,
+            parent_id,
+            deadline_at,
+            expired_at,
+            result,
+            errors
+        FROM pg_task
+        WHERE (
+            $1::TEXT IS NULL
+            OR step ? $1
+            OR EXISTS (
+                SELECT 1
+                FROM jsonb_each(step) AS task
+                WHERE jsonb_typeof(task.value) = 'object'
+                  AND task.value ? $1
+            )
+        )
+          AND ($2::BOOL IS NULL OR (error IS NOT NULL) = $2)
+          AND ($3::BOOL IS NULL OR COALESCE(locked_until > now(), false) = $3)
+          AND ($4::BOOL IS NULL OR is_paused = $4)
+          AND ($5::BOOL IS NULL OR (done_at IS NOT NULL) = $5)
+          AND ($6::TIMESTAMPTZ IS NULL OR wakeup_at < $6)
+          AND ($7::TIMESTAMPTZ IS NULL OR wakeup_at > $7)
+          AND ($10::UUID IS NULL OR parent_id = $10)
+          AND ($11::BOOL IS NULL OR is_unparseable = $11)
+          AND ($12::TEXT IS NULL OR tenant = $12)
+        ORDER BY wakeup_at, id
+        LIMIT $8
+        OFFSET $9
+        "#,
+        filter.step_name,
+        filter.errored,
+        filter.running,
+        filter.paused,
+        filter.done,
+        filter.scheduled_before,
+        filter.scheduled_after,
+        page.limit,
+        page.offset,
+        filter.parent_id,
+        filter.unparseable,
+        filter.tenant,
+    )
+    .fetch_all(db)
+    .await
+    .map_err(db_error!())
+}
+
+/// Replaces the step of an unparseable task, i.e. the one which step failed to
+/// deserialize, with the given one and wakes the task up. `T` is the enum
+/// passed to [`scheduler!`](crate::scheduler). Returns `false` if there's no
+/// such unparseable task.
+pub async fn fix_unparseable<'e, T: Scheduler>(
+    db: impl PgExecutor<'e>,
+    id: Uuid,
+    step: &T,
+) -> Result<bool> {
+    let step = payload::serialize_with_names(step, T::CODEC)?;
+    let fixed = sqlx::query!(
+        "
+        UPDATE pg_task
+        SET step = $2,
+            step_bytes = $6,
+            task_name = $3,
+            step_name = $5,
+            payload_version = $4,
+            is_unparseable = false,
+            error = NULL,
+            -- The key could be taken by a newer task while this one was failed
+            unique_key = CASE
+                WHEN EXISTS (
+                    SELECT 1
+                    FROM pg_task pending
+                    WHERE pending.unique_key = pg_task.unique_key
+                      AND pending.done_at IS NULL
+                      AND pending.error IS NULL
+                      AND pending.expired_at IS NULL
+                ) THEN NULL
+                ELSE unique_key
+            END,
+            wakeup_at = now()
+        WHERE id = $1
+          AND is_unparseable
+        ",
+        id,
+        step.value,
+        step.task_name,
+        T::PAYLOAD_VERSION,
+        step.step_name,
+        step.bytes,
+    )
+    .execute(db)
+    .await
+    .map_err(db_error!())?
+    .rows_affected();
+    Ok(fixed > 0)
+}
@@ -0,0 +1,267 @@
+use crate::{Error, Result, RetryStrategy, Scheduler, Step, StepContext, StepError, StepResult};
+use async_trait::async_trait;
+use serde::{de, de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use sqlx::PgPool;
+use std::{collections::HashMap, fmt, sync::OnceLock, time::Duration};
+
+static TYPES: OnceLock<TaskTypes> = OnceLock::new();
+
+/// A task enum run by a `Worker<AnyTask>` along with other independent task
+/// enums instead of being combined with them by
+/// [`scheduler!`](crate::scheduler). It's registered by its name with
+/// [`TaskTypes::register`]. The steps of the registered types don't take an
+/// application context, as the types are set for the process rather than the
+/// worker, so a `Worker<AnyTask>` can't be built with
+/// [`Worker::with_context`](crate::Worker::with_context).
+pub trait TaskType: Step<Self> + Serialize + DeserializeOwned + Sync + 'static {
+    /// Name of the task type stored in the `task_name` column, it should be
+    /// unique among the registered types
+    const NAME: &'static str;
+}
+
+/// The task types run by a single worker, so an app with several small state
+/// machines doesn't need a worker loop, a listener and a semaphore for each of
+/// them. The types are set once for the process with [`set_task_types`] at
+/// startup, then the tasks are run by a `Worker<AnyTask>`.
+#[derive(Default)]
+pub struct TaskTypes {
+    deserializers: HashMap<&'static str, DeserializeFn>,
+}
+
+/// Deserializes a task of a registered type
+type DeserializeFn = fn(Value) -> serde_json::Result<Box<dyn ErasedTask>>;
+
+impl TaskTypes {
+    /// Creates an empty set of types
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the task type by its [`TaskType::NAME`]
+    pub fn register<T: TaskType>(mut self) -> Self {
+        self.deserializers.insert(T::NAME, |task| {
+            Ok(Box::new(T::deserialize(task)?) as Box<dyn ErasedTask>)
+        });
+        self
+    }
+}
+
+/// Sets the task types run by `Worker<AnyTask>` for the process. It should be
+/// called once before scheduling or running any of the tasks.
+pub fn set_task_types(types: TaskTypes) -> Result<()> {
+    TYPES.set(types).map_err(|_| Error::TaskTypesAlreadySet)
+}
+
+/// A task of any of the registered [`TaskType`]s, stored as the name of its
+/// type and the task enum. A task enum is converted into it with `into()`.
+pub struct AnyTask(Box<dyn ErasedTask>);
+
+impl AnyTask {
+    /// Wraps the task
+    pub fn new(task: impl TaskType) -> Self {
+        Self(Box::new(task))
+    }
+
+    /// Returns the name of the task type
+    pub fn name(&self) -> &'static str {
+        self.0.name()
+    }
+}
+
+impl<T: TaskType> From<T> for AnyTask {
+    fn from(task: T) -> Self {
+        Self::new(task)
+    }
+}
+
+impl fmt::Debug for AnyTask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Serialize for AnyTask {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let task = self.0.to_value().map_err(serde::ser::Error::custom)?;
+        let mut wrapped = serde_json::Map::new();
+        wrapped.insert(self.name().into(), task);
+        wrapped.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for AnyTask {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let Value::Object(wrapped) = Value::deserialize(deserializer)? else {
+            return Err(de::Error::custom("a task should be an object"));
+        };
+        let Some((name, task)) = wrapped.into_iter().next() else {
+            return Err(de::Error::custom("missing task type"));
+        };
+        let Some(types) = TYPES.get() else {
+            return Err(de::Error::custom("the task types aren't set"));
+        };
+        let Some(deserialize) = types.deserializers.get(name.as_str()) else {
+            return Err(de::Error::custom(format!(
+                "unregistered task type `{name}`"
+            )));
+        };
+        deserialize(task).map(Self).map_err(de::Error::custom)
+    }
+}
+
+#[async_trait]
+impl Step<AnyTask> for AnyTask {
+    async fn step(self, db: &PgPool, ctx: &StepContext) -> StepResult<AnyTask> {
+        self.0.step(db, ctx).await
+    }
+
+    async fn run_compensation(
+        self,
+        db: &PgPool,
+        ctx: &StepContext,
+    ) -> std::result::Result<(), StepError> {
+        self.0.run_compensation(db, ctx).await
+    }
+
+    fn retry_limit(&self) -> i32 {
+        self.0.retry_limit()
+    }
+
+    fn retry_delay(&self) -> Duration {
+        self.0.retry_delay()
+    }
+
+    fn retry_strategy(&self) -> RetryStrategy {
+        self.0.retry_strategy()
+    }
+
+    fn retry_jitter(&self) -> f32 {
+        self.0.retry_jitter()
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.0.timeout()
+    }
+
+    fn max_concurrency(&self) -> Option<usize> {
+        self.0.max_concurrency()
+    }
+
+    fn is_singleton(&self) -> bool {
+        self.0.is_singleton()
+    }
+
+    fn rate_limit(&self) -> Option<f64> {
+        self.0.rate_limit()
+    }
+
+    fn has_compensation(&self) -> bool {
+        self.0.has_compensation()
+    }
+
+    fn step_type(&self) -> &'static str {
+        self.0.step_type()
+    }
+}
+
+impl Scheduler for AnyTask {
+    /// Returns the names of the registered types, so the worker doesn't claim
+    /// the tasks of workers running other enums in the same table
+    fn task_names() -> Option<Vec<String>> {
+        let types = TYPES.get().map(|types| &types.deserializers);
+        Some(
+            types
+                .into_iter()
+                .flatten()
+                .map(|(name, _)| name.to_string())
+                .collect(),
+        )
+    }
+}
+
+/// An object safe [`Step`] of the registered task types
+#[async_trait]
+trait ErasedTask: fmt::Debug + Send + Sync {
+    fn name(&self) -> &'static str;
+    fn to_value(&self) -> serde_json::Result<Value>;
+    async fn step(self: Box<Self>, db: &PgPool, ctx: &StepContext) -> StepResult<AnyTask>;
+    async fn run_compensation(
+        self: Box<Self>,
+        db: &PgPool,
+        ctx: &StepContext,
+    ) -> std::result::Result<(), StepError>;
+    fn retry_limit(&self) -> i32;
+    fn retry_delay(&self) -> Duration;
+    fn retry_strategy(&self) -> RetryStrategy;
+    fn retry_jitter(&self) -> f32;
+    fn timeout(&self) -> Option<Duration>;
+    fn max_concurrency(&self) -> Option<usize>;
+    fn is_singleton(&self) -> bool;
+    fn rate_limit(&self) -> Option<f64>;
+    fn has_compensation(&self) -> bool;
+    fn step_type(&self) -> &'static str;
+}
+
+#[async_trait]
+impl<T: TaskType> ErasedTask for T {
+    fn name(&self) -> &'static str {
+        T::NAME
+    }
+
+    fn to_value(&self) -> serde_json::Result<Value> {
+        serde_json::to_value(self)
+    }
+
+    async fn step(self: Box<Self>, db: &PgPool, ctx: &StepContext) -> StepResult<AnyTask> {
+        Ok(Step::step(*self, db, ctx).await?.map(AnyTask::new))
+    }
+
+    async fn run_compensation(
+        self: Box<Self>,
+        db: &PgPool,
+        ctx: &StepContext,
+    ) -> std::result::Result<(), StepError> {
+        Step::run_compensation(*self, db, ctx).await
+    }
+
+    fn retry_limit(&self) -> i32 {
+        Step::retry_limit(self)
+    }
+
+    fn retry_delay(&self) -> Duration {
+        Step::retry_delay(self)
+    }
+
+    fn retry_strategy(&self) -> RetryStrategy {
+        Step::retry_strategy(self)
+    }
+
+    fn retry_jitter(&self) -> f32 {
+        Step::retry_jitter(self)
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        Step::timeout(self)
+    }
+
+    fn max_concurrency(&self) -> Option<usize> {
+        Step::max_concurrency(self)
+    }
+
+    fn is_singleton(&self) -> bool {
+        Step::is_singleton(self)
+    }
+
+    fn rate_limit(&self) -> Option<f64> {
+        Step::rate_limit(self)
+    }
+
+    fn has_compensation(&self) -> bool {
+        Step::has_compensation(self)
+    }
+
+    fn step_type(&self) -> &'static str {
+        Step::step_type(self)
+    }
+}
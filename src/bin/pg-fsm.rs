@@ -0,0 +1,253 @@
+//! Command line tool to manage `pg_task` tasks
+
+use clap::{Parser, Subcommand};
+use pg_task::{
+    admin::{self, Filter, Page},
+    TaskInfo,
+};
+use sqlx::{types::Uuid, PgPool};
+use std::{error::Error, time::Duration};
+
+#[derive(Parser)]
+#[command(version, about = "Manages pg_task tasks")]
+struct Cli {
+    /// Postgres connection url
+    #[arg(long, env = "DATABASE_URL")]
+    database_url: String,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Lists tasks ordered by their wakeup time
+    List {
+        /// Only tasks with the name
+        #[arg(long)]
+        name: Option<String>,
+        /// Only failed tasks
+        #[arg(long)]
+        failed: bool,
+        /// Only running tasks
+        #[arg(long)]
+        running: bool,
+        /// Only paused tasks
+        #[arg(long)]
+        paused: bool,
+        /// Only tasks which step can't be deserialized
+        #[arg(long)]
+        unparseable: bool,
+        /// Only done tasks
+        #[arg(long)]
+        done: bool,
+        /// Only children of the task
+        #[arg(long)]
+        parent: Option<Uuid>,
+        /// Only tasks of the tenant
+        #[arg(long)]
+        tenant: Option<String>,
+        /// The maximum number of tasks to show
+        #[arg(long, default_value_t = 50)]
+        limit: i64,
+        /// Number of tasks to skip
+        #[arg(long, default_value_t = 0)]
+        offset: i64,
+    },
+    /// Shows a task
+    Show {
+        /// Task id
+        id: Uuid,
+    },
+    /// Wakes up a task right away clearing its error
+    Retry {
+        /// Task id
+        id: Uuid,
+        /// Resets the tries counter, so all the retry attempts are available
+        /// again
+        #[arg(long)]
+        reset_tries: bool,
+    },
+    /// Cancels a task along with its children
+    Cancel {
+        /// Task id
+        id: Uuid,
+    },
+    /// Stops claiming tasks by all the workers until `resume-all`
+    PauseAll,
+    /// Resumes claiming tasks paused by `pause-all`
+    ResumeAll,
+    /// Shows the number of tasks in each state
+    Stats,
+    /// Removes done tasks
+    Cleanup {
+        /// Removes the tasks done earlier than this time ago, e.g. `30m`, `12h`
+        /// or `7d`
+        #[arg(long, value_parser = parse_duration)]
+        older_than: Duration,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+    let db = PgPool::connect(&cli.database_url).await?;
+    match cli.command {
+        Command::List {
+            name,
+            failed,
+            running,
+            paused,
+            unparseable,
+            done,
+            parent,
+            tenant,
+            limit,
+            offset,
+        } => {
+            let filter = Filter {
+                step_name: name,
+                errored: failed.then_some(true),
+                running: running.then_some(true),
+                paused: paused.then_some(true),
+                done: done.then_some(true),
+                parent_id: parent,
+                unparseable: unparseable.then_some(true),
+                tenant,
+                ..Filter::default()
+            };
+            let tasks = admin::list_tasks(&db, filter, Page { limit, offset }).await?;
+            for task in tasks {
+                println!(
+                    "{id}  {state:<11}  {wakeup_at}  {name}",
+                    id = task.id,
+                    state = state(&task),
+                    wakeup_at = task.wakeup_at.format("%Y-%m-%d %H:%M:%S"),
+                    name = task.task_name,
+                );
+            }
+        }
+        Command::Show { id } => {
+            let Some(task) = pg_task::find(&db, id).await? else {
+                return Err(format!("task {id} isn't found").into());
+            };
+            println!("id:         {}", task.id);
+            println!("name:       {}", task.task_name);
+            println!("step:       {}", task.step_name);
+            println!("state:      {}", state(&task));
+            println!("tried:      {}", task.tried);
+            println!("priority:   {}", task.priority);
+            println!("wakeup at:  {}", task.wakeup_at);
+            println!("created at: {}", task.created_at);
+            println!("updated at: {}", task.updated_at);
+            if let Some(parent_id) = task.parent_id {
+                println!("parent:     {parent_id}");
+            }
+            if let Some(tenant) = &task.tenant {
+                println!("tenant:     {tenant}");
+            }
+            if let Some(done_at) = task.done_at {
+                println!("done at:    {done_at}");
+            }
+            if let Some(deadline_at) = task.deadline_at {
+                println!("deadline:   {deadline_at}");
+            }
+            if let Some(expired_at) = task.expired_at {
+                println!("expired at: {expired_at}");
+            }
+            if let Some(error) = &task.error {
+                println!("error:      {error}");
+            }
+            if let Some(progress) = &task.progress {
+                println!("progress:   {progress}");
+            }
+            if let Some(result) = &task.result {
+                println!("result:     {result}");
+            }
+            println!("metadata:   {}", task.metadata);
+            println!("step:\n{}", serde_json::to_string_pretty(&task.step)?);
+        }
+        Command::Retry { id, reset_tries } => {
+            if !pg_task::retry_now(&db, id, reset_tries).await? {
+                return Err(format!("task {id} isn't found, running or done").into());
+            }
+            println!("Task {id} is woken up");
+        }
+        Command::Cancel { id } => {
+            let Some(cancelled) = pg_task::cancel_tree(&db, id).await? else {
+                return Err(format!("task {id} isn't found or done").into());
+            };
+            println!(
+                "Task {id} is cancelled: {} tasks removed, {} running tasks asked to cancel",
+                cancelled.removed, cancelled.running
+            );
+        }
+        Command::PauseAll => {
+            pg_task::pause_all(&db).await?;
+            println!("The queue is paused");
+        }
+        Command::ResumeAll => {
+            pg_task::resume_all(&db).await?;
+            println!("The queue is resumed");
+        }
+        Command::Stats => {
+            let stats = pg_task::stats(&db).await?;
+            let total = stats.total();
+            println!("ready:     {}", total.ready);
+            if let Some(age) = total.oldest_ready_age {
+                println!("oldest:    {}s", age.as_secs());
+            }
+            println!("scheduled: {}", total.scheduled);
+            println!("running:   {}", total.running);
+            println!("paused:    {}", total.paused);
+            println!("failed:    {}", total.failed);
+            println!("expired:   {}", total.expired);
+            println!("done:      {}", stats.done);
+        }
+        Command::Cleanup { older_than } => {
+            let removed = pg_task::cleanup(&db, older_than).await?;
+            println!("Removed {removed} done tasks");
+        }
+    }
+    Ok(())
+}
+
+/// Returns a short description of the task state
+fn state(task: &TaskInfo) -> &'static str {
+    if task.done_at.is_some() {
+        "done"
+    } else if task.is_unparseable {
+        "unparseable"
+    } else if task.expired_at.is_some() {
+        "expired"
+    } else if task.error.is_some() {
+        "failed"
+    } else if task.is_running {
+        "running"
+    } else if task.is_paused {
+        "paused"
+    } else if task.is_awaiting_signal {
+        "waiting"
+    } else if task.wakeup_at <= chrono::Utc::now() {
+        "ready"
+    } else {
+        "scheduled"
+    }
+}
+
+/// Parses a duration like `45s`, `30m`, `12h` or `7d`
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (number, seconds) = match s.char_indices().last() {
+        Some((i, 's')) => (&s[..i], 1),
+        Some((i, 'm')) => (&s[..i], 60),
+        Some((i, 'h')) => (&s[..i], 60 * 60),
+        Some((i, 'd')) => (&s[..i], 24 * 60 * 60),
+        _ => return Err("the duration should end with one of s, m, h or d".into()),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|e| format!("invalid duration number: {e}"))?;
+    number
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| "the duration is too long".into())
+}
@@ -0,0 +1,110 @@
+use crate::{
+    util::db_error,
+    wait::{self, Outcome},
+    Result,
+};
+use sqlx::{types::Uuid, Acquire, Postgres};
+
+/// Cancels the task along with all its children spawned by
+/// [`StepContext::spawn_child`](crate::StepContext::spawn_child). Pending
+/// and done tasks are removed right away. Running tasks are removed as soon as
+/// their current step is finished, the step can observe the cancellation using
+/// [`StepContext::is_cancelled`](crate::StepContext::is_cancelled) or
+/// [`StepContext::cancellation_token`](crate::StepContext::cancellation_token).
+/// Returns `false` if there's no such task or it's already done.
+pub async fn cancel<'a>(db: impl Acquire<'a, Database = Postgres>, id: Uuid) -> Result<bool> {
+    Ok(cancel_tree(db, id).await?.is_some())
+}
+
+/// The number of tasks of a tree cancelled by [`cancel_tree`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CancelledTree {
+    /// Pending and done tasks removed right away
+    pub removed: usize,
+    /// Running tasks asked to cancel, they are removed as soon as their
+    /// current steps are finished
+    pub running: usize,
+}
+
+/// Cancels the task with all its descendants in a single transaction like
+/// [`cancel`], e.g. to abort a large fan-out workflow, and returns the number
+/// of the cancelled tasks. Returns `None` if there's no such task or it's
+/// already done.
+pub async fn cancel_tree<'a>(
+    db: impl Acquire<'a, Database = Postgres>,
+    id: Uuid,
+) -> Result<Option<CancelledTree>> {
+    let mut tx = db.begin().await.map_err(db_error!("begin"))?;
+
+    let tasks = sqlx::query!(
+        r#"
+        WITH RECURSIVE tree AS (
+            SELECT id FROM pg_task WHERE id = $1
+            UNION
+            SELECT child.id
+            FROM pg_task child
+            JOIN tree ON child.parent_id = tree.id
+        )
+        SELECT
+            id,
+            parent_id,
+            step,
+            done_at IS NULL AND COALESCE(locked_until > now(), false) AS "is_running!",
+            done_at IS NOT NULL AS "is_done!"
+        FROM pg_task
+        WHERE id IN (SELECT id FROM tree)
+        FOR UPDATE
+        "#,
+        id
+    )
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(db_error!("lock"))?;
+    let Some(root) = tasks.iter().find(|t| t.id == id && !t.is_done) else {
+        return Ok(None);
+    };
+    // The parent waiting for its children should notice the removed child
+    let parent_to_wake = if root.is_running {
+        None
+    } else {
+        root.parent_id
+    };
+
+    let (running, pending): (Vec<_>, Vec<_>) = tasks.into_iter().partition(|t| t.is_running);
+    let running: Vec<_> = running.into_iter().map(|t| t.id).collect();
+    for task in pending.iter().filter(|t| !t.is_done) {
+        wait::notify_finished(&mut *tx, task.id, &Outcome::Cancelled, &task.step).await;
+    }
+    let pending: Vec<_> = pending.into_iter().map(|t| t.id).collect();
+
+    if !running.is_empty() {
+        sqlx::query!(
+            "UPDATE pg_task SET cancelled_at = now() WHERE id = ANY($1)",
+            &running
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error!("mark cancelled"))?;
+    }
+    if !pending.is_empty() {
+        sqlx::query!("DELETE FROM pg_task WHERE id = ANY($1)", &pending)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error!("delete"))?;
+    }
+    if let Some(parent_id) = parent_to_wake {
+        sqlx::query!(
+            "UPDATE pg_task SET wakeup_at = now() WHERE id = $1 AND is_joining",
+            parent_id
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error!("wake up parent"))?;
+    }
+
+    tx.commit().await.map_err(db_error!("commit"))?;
+    Ok(Some(CancelledTree {
+        removed: pending.len(),
+        running: running.len(),
+    }))
+}
@@ -0,0 +1,76 @@
+use crate::{util::db_error, Result};
+use sqlx::{postgres::PgTransaction, PgPool};
+
+/// Key of the advisory lock taken to claim tasks with
+/// [`ClaimStrategy::AdvisoryLock`], it's prefixed with the current schema, so
+/// workers of the tasks in other schemas don't wait for it
+const CLAIM_LOCK_KEY: &str = "pg_task:claim";
+
+/// How workers make sure a task is claimed by only one of them, see
+/// [`Worker::with_claim_strategy`](crate::Worker::with_claim_strategy)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClaimStrategy {
+    /// A worker locks the rows of the tasks it claims and skips the rows
+    /// locked by other workers (`FOR UPDATE SKIP LOCKED`), so workers claim
+    /// tasks concurrently
+    #[default]
+    SkipLocked,
+    /// Workers claim tasks one at a time holding a transaction-level advisory
+    /// lock, so each of them gets the very next task instead of skipping the
+    /// ones being claimed by the others. It keeps the order of the tasks
+    /// strict at the cost of the claiming throughput.
+    AdvisoryLock,
+}
+
+/// Isolation level of the transaction claiming tasks, see
+/// [`Worker::with_isolation_level`](crate::Worker::with_isolation_level)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IsolationLevel {
+    /// `READ COMMITTED`
+    ReadCommitted,
+    /// `REPEATABLE READ`
+    RepeatableRead,
+    /// `SERIALIZABLE`
+    Serializable,
+}
+
+impl IsolationLevel {
+    /// Returns the statement setting the isolation level of a transaction
+    fn set_transaction(self) -> &'static str {
+        match self {
+            Self::ReadCommitted => "SET TRANSACTION ISOLATION LEVEL READ COMMITTED",
+            Self::RepeatableRead => "SET TRANSACTION ISOLATION LEVEL REPEATABLE READ",
+            Self::Serializable => "SET TRANSACTION ISOLATION LEVEL SERIALIZABLE",
+        }
+    }
+}
+
+/// Begins a transaction to claim tasks with the isolation level, the db
+/// default is used if it's `None`
+pub(crate) async fn begin(
+    db: &PgPool,
+    isolation: Option<IsolationLevel>,
+    strategy: ClaimStrategy,
+) -> Result<PgTransaction<'static>> {
+    let mut tx = db.begin().await.map_err(db_error!("begin"))?;
+    if let Some(isolation) = isolation {
+        sqlx::query(isolation.set_transaction())
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error!("isolation"))?;
+    }
+    if strategy == ClaimStrategy::AdvisoryLock {
+        sqlx::query(
+            "
+            SELECT pg_advisory_xact_lock(
+                hashtextextended(COALESCE(current_schema(), '') || ':' || $1, 0)
+            )
+            ",
+        )
+        .bind(CLAIM_LOCK_KEY)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error!("lock"))?;
+    }
+    Ok(tx)
+}
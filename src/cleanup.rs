@@ -0,0 +1,21 @@
+use crate::{
+    util::{db_error, std_duration_to_pg_interval},
+    Result,
+};
+use sqlx::PgExecutor;
+use std::time::Duration;
+
+/// Removes the tasks completed more than `older_than` ago, they're only kept
+/// by workers with [`Worker::with_keep_done`](crate::Worker::with_keep_done).
+/// Returns the number of removed tasks.
+pub async fn cleanup<'e>(db: impl PgExecutor<'e>, older_than: Duration) -> Result<u64> {
+    let removed = sqlx::query!(
+        "DELETE FROM pg_task WHERE done_at < now() - $1::INTERVAL",
+        std_duration_to_pg_interval(older_than),
+    )
+    .execute(db)
+    .await
+    .map_err(db_error!())?
+    .rows_affected();
+    Ok(removed)
+}
@@ -0,0 +1,9 @@
+use chrono::{DateTime, Utc};
+
+/// Source of the current time used by a worker to schedule steps, see
+/// [`Worker::with_clock`](crate::Worker::with_clock). Without a clock, workers
+/// rely on the db time.
+pub trait Clock: Send + Sync + 'static {
+    /// Returns the current time
+    fn now(&self) -> DateTime<Utc>;
+}
@@ -0,0 +1,180 @@
+use crate::StepError;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde_json::Value;
+
+/// Prefix of the key wrapping an encoded step
+const KEY_PREFIX: &str = "pg_task_";
+
+/// Serialization format of the steps, selected for a scheduler with
+/// [`Scheduler::CODEC`](crate::Scheduler::CODEC). Steps are stored in the
+/// `step` column as is with the default [`Json`] codec. Others encode steps
+/// into bytes stored in the `step_bytes` column, while `step` keeps the codec
+/// tag as `{"pg_task_<name>": null}`, so the rows stored with another codec
+/// stay readable.
+pub trait Codec: Send + Sync + 'static {
+    /// Name of the codec tagging the steps it encoded
+    fn name(&self) -> &'static str;
+
+    /// Encodes a serialized step into bytes
+    fn encode(&self, step: &Value) -> Result<Vec<u8>, StepError>;
+
+    /// Decodes the bytes returned by [`Self::encode`] back into the
+    /// serialized step
+    fn decode(&self, bytes: &[u8]) -> Result<Value, StepError>;
+}
+
+/// The default codec storing steps as JSON
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Json;
+
+impl Json {
+    const NAME: &'static str = "json";
+}
+
+impl Codec for Json {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn encode(&self, step: &Value) -> Result<Vec<u8>, StepError> {
+        Ok(serde_json::to_vec(step)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Value, StepError> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// Stores steps as [CBOR](https://cbor.io), enabled by the `cbor` feature
+#[cfg(feature = "cbor")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Codec for Cbor {
+    fn name(&self) -> &'static str {
+        "cbor"
+    }
+
+    fn encode(&self, step: &Value) -> Result<Vec<u8>, StepError> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(step, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Value, StepError> {
+        Ok(ciborium::from_reader(bytes)?)
+    }
+}
+
+/// Stores steps as [MessagePack](https://msgpack.org), enabled by the
+/// `msgpack` feature
+#[cfg(feature = "msgpack")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePack {
+    fn name(&self) -> &'static str {
+        "msgpack"
+    }
+
+    fn encode(&self, step: &Value) -> Result<Vec<u8>, StepError> {
+        Ok(rmp_serde::to_vec(step)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Value, StepError> {
+        Ok(rmp_serde::from_slice(bytes)?)
+    }
+}
+
+/// Returns true if the codec stores steps as is
+pub(crate) fn is_json(codec: &dyn Codec) -> bool {
+    codec.name() == Json::NAME
+}
+
+/// Encodes the serialized step with the codec into the `step` and
+/// `step_bytes` column values
+pub(crate) fn wrap(step: Value, codec: &dyn Codec) -> Result<(Value, Option<Vec<u8>>), StepError> {
+    if is_json(codec) {
+        return Ok((step, None));
+    }
+    let bytes = codec.encode(&step)?;
+    Ok((tag(codec, Value::Null), Some(bytes)))
+}
+
+/// Encodes the serialized step with the codec and wraps it into a JSON value
+/// with the bytes base64 encoded, e.g. for a [`PayloadCodec`] to transform
+///
+/// [`PayloadCodec`]: crate::PayloadCodec
+pub(crate) fn wrap_json(step: Value, codec: &dyn Codec) -> Result<Value, StepError> {
+    if is_json(codec) {
+        return Ok(step);
+    }
+    let bytes = codec.encode(&step)?;
+    Ok(tag(codec, BASE64.encode(bytes).into()))
+}
+
+/// Unwraps and decodes the step encoded with the codec or any of the enabled
+/// built-in ones, returns other values as is
+pub(crate) fn unwrap(value: Value, codec: &dyn Codec) -> Result<Value, StepError> {
+    let Some((name, Value::String(encoded))) = tagged(&value) else {
+        return Ok(value);
+    };
+    match find(name, codec) {
+        Some(codec) => codec.decode(&BASE64.decode(encoded)?),
+        None => Ok(value),
+    }
+}
+
+/// Decodes the `step_bytes` column value with the codec tagged in the `step`
+/// one
+pub(crate) fn unwrap_bytes(
+    value: &Value,
+    bytes: &[u8],
+    codec: &dyn Codec,
+) -> Result<Value, StepError> {
+    let Some((name, Value::Null)) = tagged(value) else {
+        return Err(format!("no codec tag in the step {value}").into());
+    };
+    match find(name, codec) {
+        Some(codec) => codec.decode(bytes),
+        None => Err(format!("unknown codec `{name}`").into()),
+    }
+}
+
+/// Wraps the value under the key tagging the codec
+fn tag(codec: &dyn Codec, value: Value) -> Value {
+    let mut wrapped = serde_json::Map::new();
+    wrapped.insert(format!("{KEY_PREFIX}{}", codec.name()), value);
+    wrapped.into()
+}
+
+/// Returns the codec name and the wrapped value of a tagged one
+fn tagged(value: &Value) -> Option<(&str, &Value)> {
+    value
+        .as_object()
+        .filter(|o| o.len() == 1)
+        .and_then(|o| o.iter().next())
+        .and_then(|(k, v)| Some((k.strip_prefix(KEY_PREFIX)?, v)))
+}
+
+/// Returns the given binary codec or the enabled built-in one by its name
+fn find<'a>(name: &str, codec: &'a dyn Codec) -> Option<&'a dyn Codec> {
+    if !is_json(codec) && codec.name() == name {
+        Some(codec)
+    } else {
+        builtin(name)
+    }
+}
+
+/// Returns the enabled built-in binary codec by its name
+fn builtin(name: &str) -> Option<&'static dyn Codec> {
+    match name {
+        #[cfg(feature = "cbor")]
+        "cbor" => Some(&Cbor),
+        #[cfg(feature = "msgpack")]
+        "msgpack" => Some(&MessagePack),
+        _ => None,
+    }
+}
@@ -0,0 +1,402 @@
+use crate::{
+    info,
+    lock::AdvisoryLock,
+    payload,
+    traits::{insert, InsertOptions},
+    util::db_error,
+    Clock, Codec, Error, Json, Result, Scheduler, Step, StepError, TaskInfo,
+};
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use sqlx::{postgres::PgTransaction, types::Uuid, PgPool};
+use std::{fmt, future::Future, result::Result as StdResult, sync::Arc};
+use tokio::{
+    sync::{watch, Mutex, OwnedMappedMutexGuard, OwnedMutexGuard},
+    time::{sleep_until, Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+/// The context of a running step, `C` is the application context passed to
+/// the worker with [`Worker::with_context`](crate::Worker::with_context)
+#[derive(Clone, Debug)]
+pub struct StepContext<C = ()> {
+    /// Id of the task
+    pub task_id: Uuid,
+    /// Number of the current attempt to run the step, starting from 1
+    pub attempt: i32,
+    /// Time the task was enqueued
+    pub enqueued_at: DateTime<Utc>,
+    /// Time the current attempt to run the step was started
+    pub started_at: DateTime<Utc>,
+    /// Cancelled on the worker shutdown or the task cancellation, so
+    /// long-running steps could checkpoint and finish early, e.g. by selecting
+    /// on `ctx.cancellation_token.cancelled()`. The task cancellation is
+    /// observed on the lock renewal, i.e. within a third of the worker lease.
+    pub cancellation_token: CancellationToken,
+    db: PgPool,
+    codec: StepCodec,
+    clock: StepClock,
+    step: StepNames,
+    heartbeats: Heartbeats,
+    transaction: StepTransaction,
+    app_context: C,
+}
+
+/// The time of the last [`StepContext::heartbeat`] of the step, it's the step
+/// start time until the first one
+#[derive(Clone, Debug)]
+pub(crate) struct Heartbeats(Arc<watch::Sender<Instant>>);
+
+impl Heartbeats {
+    fn new() -> Self {
+        Self(Arc::new(watch::Sender::new(Instant::now())))
+    }
+
+    /// Resolves when there are no heartbeats for the `timeout`
+    pub async fn timed_out(&self, timeout: Duration) {
+        let mut beats = self.0.subscribe();
+        loop {
+            let deadline = *beats.borrow_and_update() + timeout;
+            tokio::select! {
+                () = sleep_until(deadline) => return,
+                Ok(()) = beats.changed() => (),
+            }
+        }
+    }
+}
+
+/// The codec of the task steps to save the step state with
+#[derive(Clone, Copy)]
+struct StepCodec(&'static dyn Codec);
+
+impl fmt::Debug for StepCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0.name())
+    }
+}
+
+/// The worker clock to schedule the spawned tasks with
+#[derive(Clone, Default)]
+struct StepClock(Option<Arc<dyn Clock>>);
+
+impl StepClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0.as_ref().map_or_else(Utc::now, |clock| clock.now())
+    }
+}
+
+impl fmt::Debug for StepClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.0.is_some() { "Clock" } else { "Utc" })
+    }
+}
+
+/// The running step known by the worker to save its state with
+#[derive(Clone, Debug, Default)]
+pub(crate) struct StepNames {
+    /// Name of the task, i.e. the variant of the outer enum
+    pub task_name: String,
+    /// Name of the step, i.e. the variant of the task enum
+    pub step_name: String,
+    /// Type name of the step
+    pub step_type: &'static str,
+    /// Id of the worker holding the task lock
+    pub locked_by: Option<String>,
+}
+
+/// The transaction opened by the step with [`StepContext::transaction`]
+#[derive(Clone, Default)]
+pub(crate) struct StepTransaction(Arc<Mutex<Option<PgTransaction<'static>>>>);
+
+impl StepTransaction {
+    /// Takes the transaction out once the step is finished. A transaction
+    /// still borrowed, e.g. by a task spawned by the step, isn't taken.
+    pub fn take(&self) -> Option<PgTransaction<'static>> {
+        self.0.try_lock().ok()?.take()
+    }
+}
+
+impl fmt::Debug for StepTransaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StepTransaction")
+    }
+}
+
+impl<C> StepContext<C> {
+    pub(crate) fn new(
+        db: PgPool,
+        task_id: Uuid,
+        attempt: i32,
+        enqueued_at: DateTime<Utc>,
+        cancellation_token: CancellationToken,
+        transaction: StepTransaction,
+        app_context: C,
+    ) -> Self {
+        Self {
+            task_id,
+            attempt,
+            enqueued_at,
+            started_at: Utc::now(),
+            cancellation_token,
+            db,
+            codec: StepCodec(&Json),
+            clock: StepClock::default(),
+            step: StepNames::default(),
+            heartbeats: Heartbeats::new(),
+            transaction,
+            app_context,
+        }
+    }
+
+    /// Returns the heartbeats of the step to restart its timeout on them
+    pub(crate) fn heartbeats(&self) -> Heartbeats {
+        self.heartbeats.clone()
+    }
+
+    /// Sets the codec of the task steps, it's used to save the step state
+    pub(crate) fn with_codec(mut self, codec: &'static dyn Codec) -> Self {
+        self.codec = StepCodec(codec);
+        self
+    }
+
+    /// Sets the worker clock, the step start time and spawned children are
+    /// scheduled with it
+    pub(crate) fn with_clock(mut self, clock: Option<Arc<dyn Clock>>) -> Self {
+        self.clock = StepClock(clock);
+        self.started_at = self.clock.now();
+        self
+    }
+
+    /// Sets the running step, it's used to save the step state
+    pub(crate) fn with_step(mut self, step: StepNames) -> Self {
+        self.step = step;
+        self
+    }
+
+    /// Returns the application context passed to the worker with
+    /// [`Worker::with_context`](crate::Worker::with_context), e.g. HTTP
+    /// clients and config
+    pub fn app_context(&self) -> &C {
+        &self.app_context
+    }
+
+    /// Returns the transaction the transition to the next step is committed
+    /// in, it's begun on the first call. Writes of the step made in it are
+    /// committed atomically with the transition, giving exactly-once effects
+    /// in the same db. If the step fails, they're rolled back, as well as if
+    /// the returned guard outlives the step, e.g. moved into a spawned task.
+    ///
+    /// ```rust,ignore
+    /// let mut tx = ctx.transaction().await?;
+    /// sqlx::query!("UPDATE account SET balance = balance - $2 WHERE id = $1", id, amount)
+    ///     .execute(&mut **tx)
+    ///     .await?;
+    /// ```
+    pub async fn transaction(
+        &self,
+    ) -> Result<OwnedMappedMutexGuard<Option<PgTransaction<'static>>, PgTransaction<'static>>> {
+        let mut slot = self.transaction.0.clone().lock_owned().await;
+        let tx = match slot.take() {
+            Some(tx) => tx,
+            None => self.db.begin().await.map_err(db_error!("begin"))?,
+        };
+        Ok(OwnedMutexGuard::map(slot, |slot| slot.insert(tx)))
+    }
+
+    /// Returns true if the task is asked to cancel. It's intended to be checked
+    /// by long-running steps to finish early.
+    pub async fn is_cancelled(&self) -> Result<bool> {
+        let cancelled = sqlx::query_scalar!(
+            r#"SELECT cancelled_at IS NOT NULL AS "cancelled!" FROM pg_task WHERE id = $1"#,
+            self.task_id
+        )
+        .fetch_optional(&self.db)
+        .await
+        .map_err(db_error!())?;
+        Ok(cancelled.unwrap_or(true))
+    }
+
+    /// Runs the closure holding a Postgres advisory lock of the key, e.g.
+    /// "account:42", so steps of different tasks using the same resource don't
+    /// run it at once. The lock is released once the closure is finished, even
+    /// with an error, or if the step is aborted. Other code could share the
+    /// lock with `pg_advisory_lock(hashtextextended(key, 0))`.
+    ///
+    /// ```rust,ignore
+    /// let balance = ctx
+    ///     .with_lock(&format!("account:{}", self.account_id), || async {
+    ///         charge(&db, self.account_id, self.amount).await
+    ///     })
+    ///     .await?;
+    /// ```
+    pub async fn with_lock<F, Fut, T>(&self, key: &str, f: F) -> StdResult<T, StepError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = StdResult<T, StepError>>,
+    {
+        let lock = AdvisoryLock::acquire(&self.db, key).await?;
+        let result = f().await;
+        if let Err(e) = lock.release().await {
+            warn!(
+                "[{}] can't release the lock of {key}: {}",
+                self.task_id,
+                source_chain::to_string(&e)
+            );
+        }
+        result
+    }
+
+    /// Enqueues a child task to be run immediately, its `parent_id` is set to
+    /// the current task. Cancelling the task cancels all its children too.
+    pub async fn spawn_child(&self, task: &impl Scheduler) -> Result<Uuid> {
+        let options = InsertOptions {
+            parent_id: Some(self.task_id),
+            ..InsertOptions::new(self.clock.now())
+        };
+        insert(&self.db, task, options).await
+    }
+
+    /// Returns the children of the task, e.g. to aggregate their outcomes after
+    /// [`NextStep::join`](crate::NextStep::join). Completed children are kept
+    /// marked done until the task is completed, failed ones keep their errors
+    /// and cancelled ones are removed.
+    pub async fn children(&self) -> Result<Vec<TaskInfo>> {
+        info::children(&self.db, self.task_id).await
+    }
+
+    /// Returns the payload of the last [`signal`](crate::signal) received by
+    /// the task, e.g. in the step run after
+    /// [`NextStep::wait_signal`](crate::NextStep::wait_signal). Returns `None`
+    /// if the task has never been signaled.
+    pub async fn signal<T: DeserializeOwned>(&self) -> Result<Option<T>> {
+        let signal = sqlx::query_scalar!("SELECT signal FROM pg_task WHERE id = $1", self.task_id)
+            .fetch_optional(&self.db)
+            .await
+            .map_err(db_error!())?
+            .flatten();
+        signal
+            .map(serde_json::from_value)
+            .transpose()
+            .map_err(Error::DeserializeSignal)
+    }
+
+    /// Reports the progress of the step, e.g. for dashboards showing "37/120
+    /// items processed". It's stored in the `progress` column as JSON
+    /// with `done` and `total` fields and cleared on moving to the next step.
+    pub async fn report_progress(&self, done: i64, total: i64) -> Result<()> {
+        sqlx::query!(
+            "UPDATE pg_task SET progress = $2 WHERE id = $1",
+            self.task_id,
+            serde_json::json!({ "done": done, "total": total }),
+        )
+        .execute(&self.db)
+        .await
+        .map_err(db_error!())?;
+        Ok(())
+    }
+
+    /// Tells that the step is still making progress, restarting its
+    /// [`Step::TIMEOUT`](crate::Step::TIMEOUT). A step of an unpredictable
+    /// duration, e.g. streaming a large export, could set a short timeout and
+    /// beat on each chunk of work, so it's only aborted if it gets stuck. The
+    /// lock of the task is renewed in the background while the step runs
+    /// anyway, so the heartbeats don't touch the db and are cheap to send
+    /// often.
+    pub fn heartbeat(&self) {
+        self.heartbeats.0.send_replace(Instant::now());
+    }
+
+    /// Saves the updated state of the running step, e.g. the cursor of the
+    /// processed rows, in place of the state the step was started with. If
+    /// the step fails or the worker crashes, the retry resumes from the saved
+    /// state instead of starting over. The state is saved outside of
+    /// [`Self::transaction`], so save it after the work it reflects is
+    /// committed. The state should be of the type of the running step,
+    /// otherwise [`Error::StepTypeMismatch`] is returned.
+    pub async fn save_state<S, T>(&self, step: &S) -> Result<()>
+    where
+        C: Clone + Send + Sync + 'static,
+        S: Step<T, C>,
+    {
+        let step_type = std::any::type_name::<S>();
+        if step_type != self.step.step_type {
+            return Err(Error::StepTypeMismatch(self.step.step_type, step_type));
+        }
+        let mut value =
+            serde_json::to_value(step).map_err(|e| Error::SerializeStep(e, format!("{step:?}")))?;
+        for name in [&self.step.step_name, &self.step.task_name] {
+            if !name.is_empty() {
+                value = json!({ name: value });
+            }
+        }
+        let (value, bytes) = payload::encode(value, self.codec.0)?;
+        let updated = sqlx::query!(
+            "UPDATE pg_task SET step = $2, step_bytes = $4 WHERE id = $1 AND locked_by = $3",
+            self.task_id,
+            value,
+            self.step.locked_by,
+            bytes,
+        )
+        .execute(&self.db)
+        .await
+        .map_err(db_error!("save"))?
+        .rows_affected();
+        if updated == 0 {
+            return Err(Error::LockLost(self.task_id));
+        }
+        Ok(())
+    }
+
+    /// Stores large intermediate data of the task under the key, replacing
+    /// the previously stored one, so it isn't rewritten with the `step` column
+    /// on every transition. The data is kept in the `pg_task_blob` table until
+    /// the task is done, expired or removed.
+    pub async fn put_blob(&self, key: &str, data: &[u8]) -> Result<()> {
+        sqlx::query!(
+            "
+            INSERT INTO pg_task_blob (task_id, key, data)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (task_id, key) DO UPDATE
+            SET data = EXCLUDED.data,
+                updated_at = now()
+            ",
+            self.task_id,
+            key,
+            data,
+        )
+        .execute(&self.db)
+        .await
+        .map_err(db_error!())?;
+        Ok(())
+    }
+
+    /// Returns the data stored with [`Self::put_blob`] under the key, `None`
+    /// if there's no such data
+    pub async fn blob(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        sqlx::query_scalar!(
+            "SELECT data FROM pg_task_blob WHERE task_id = $1 AND key = $2",
+            self.task_id,
+            key,
+        )
+        .fetch_optional(&self.db)
+        .await
+        .map_err(db_error!())
+    }
+
+    /// Removes the data stored under the key when it's no longer needed by
+    /// the next steps. Returns `false` if there's no such data.
+    pub async fn delete_blob(&self, key: &str) -> Result<bool> {
+        let deleted = sqlx::query!(
+            "DELETE FROM pg_task_blob WHERE task_id = $1 AND key = $2",
+            self.task_id,
+            key,
+        )
+        .execute(&self.db)
+        .await
+        .map_err(db_error!())?
+        .rows_affected();
+        Ok(deleted > 0)
+    }
+}
@@ -0,0 +1,293 @@
+//! An embeddable web dashboard, enabled by the `dashboard` feature
+
+use crate::{
+    admin::{self, Filter, Page},
+    util::db_error,
+};
+use axum::{
+    extract::{OriginalUri, Path, State},
+    http::{header, HeaderMap, StatusCode, Uri},
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Router,
+};
+use sqlx::{types::Uuid, PgPool};
+use std::fmt::Write;
+
+/// Number of failed tasks shown on the dashboard
+const FAILED_TASKS_LIMIT: i64 = 100;
+
+/// Returns the dashboard router, it could be nested under any path of an app:
+///
+/// ```rust,ignore
+/// let app = Router::new().nest("/admin/tasks", pg_task::dashboard::router(db));
+/// ```
+///
+/// The dashboard doesn't have any authentication, so it should be protected
+/// by the app. Its retry / cancel forms are only accepted from the dashboard's
+/// own origin, so other sites can't submit them on behalf of a signed in user.
+pub fn router(db: PgPool) -> Router {
+    Router::new()
+        .route("/", get(index))
+        .route("/tasks/{id}/retry", post(retry))
+        .route("/tasks/{id}/cancel", post(cancel))
+        .with_state(db)
+}
+
+/// Dashboard handlers errors
+enum Error {
+    /// The task id in the path isn't a valid uuid
+    BadId,
+    /// A form is submitted from another origin
+    CrossOrigin,
+    /// Any of the crate errors, rendered as internal server errors
+    Crate(crate::Error),
+}
+
+impl From<crate::Error> for Error {
+    fn from(e: crate::Error) -> Self {
+        Self::Crate(e)
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        match self {
+            Self::BadId => (StatusCode::BAD_REQUEST, "invalid task id").into_response(),
+            Self::CrossOrigin => (StatusCode::FORBIDDEN, "cross-origin request").into_response(),
+            Self::Crate(e) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                source_chain::to_string(&e),
+            )
+                .into_response(),
+        }
+    }
+}
+
+struct StepLatency {
+    task_name: String,
+    step_name: String,
+    runs: i64,
+    avg_secs: f64,
+    p95_secs: f64,
+}
+
+async fn index(
+    State(db): State<PgPool>,
+    OriginalUri(original_uri): OriginalUri,
+    uri: Uri,
+) -> Result<Html<String>, Error> {
+    let base = base_path(&original_uri, &uri);
+
+    let stats = crate::stats(&db).await?;
+
+    let latencies = sqlx::query_as!(
+        StepLatency,
+        r#"
+        SELECT
+            COALESCE(
+                jsonb_path_query_first(from_step, '$.keyvalue().key', '{}', true) #>> '{}',
+                ''
+            ) AS "task_name!",
+            COALESCE(
+                jsonb_path_query_first(from_step, '$.*.keyvalue().key', '{}', true) #>> '{}',
+                ''
+            ) AS "step_name!",
+            COUNT(*) AS "runs!",
+            AVG(EXTRACT(EPOCH FROM finished_at - started_at))::FLOAT8 AS "avg_secs!",
+            percentile_cont(0.95) WITHIN GROUP (
+                ORDER BY EXTRACT(EPOCH FROM finished_at - started_at)
+            ) AS "p95_secs!"
+        FROM pg_task_log
+        WHERE finished_at > now() - INTERVAL '1 day'
+        GROUP BY 1, 2
+        ORDER BY 5 DESC
+        "#
+    )
+    .fetch_all(&db)
+    .await
+    .map_err(db_error!("latency"))?;
+
+    let failed = admin::list_tasks(
+        &db,
+        Filter {
+            errored: Some(true),
+            ..Filter::default()
+        },
+        Page {
+            limit: FAILED_TASKS_LIMIT,
+            offset: 0,
+        },
+    )
+    .await?;
+
+    let mut html = String::from(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Tasks</title><style>\
+         body{font-family:sans-serif;margin:2em}\
+         table{border-collapse:collapse;margin-bottom:2em}\
+         th,td{border:1px solid #ccc;padding:.3em .6em;text-align:left;vertical-align:top}\
+         form{display:inline}\
+         </style></head><body>",
+    );
+
+    html.push_str(
+        "<h2>Queue depth</h2><table><tr><th>Task</th><th>Step</th><th>Ready</th>\
+         <th>Oldest ready, s</th><th>Scheduled</th><th>Running</th><th>Paused</th><th>Failed</th>\
+         <th>Expired</th></tr>",
+    );
+    for d in stats.steps {
+        let _ = write!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td>\
+             <td>{}</td><td>{}</td></tr>",
+            escape(&d.task_name),
+            escape(&d.step_name),
+            d.ready,
+            d.oldest_ready_age
+                .map(|age| age.as_secs().to_string())
+                .unwrap_or_default(),
+            d.scheduled,
+            d.running,
+            d.paused,
+            d.failed,
+            d.expired
+        );
+    }
+    html.push_str("</table>");
+
+    html.push_str(
+        "<h2>Step latency for the last day</h2><p>It's collected with the <code>audit-log</code> \
+         feature.</p><table><tr><th>Task</th><th>Step</th><th>Runs</th><th>Average, s</th>\
+         <th>95th percentile, s</th></tr>",
+    );
+    for l in latencies {
+        let _ = write!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{:.3}</td><td>{:.3}</td></tr>",
+            escape(&l.task_name),
+            escape(&l.step_name),
+            l.runs,
+            l.avg_secs,
+            l.p95_secs
+        );
+    }
+    html.push_str("</table>");
+
+    html.push_str(
+        "<h2>Failed tasks</h2><table><tr><th>Id</th><th>Task</th><th>Step</th><th>Error</th>\
+         <th>Failed at</th><th></th></tr>",
+    );
+    for task in failed {
+        let _ = write!(
+            html,
+            "<tr><td>{id}</td><td>{name}</td><td><code>{step}</code></td><td>{error}</td>\
+             <td>{failed_at}</td><td>\
+             <form method=\"post\" action=\"{base}/tasks/{id}/retry\"><button>Retry</button></form> \
+             <form method=\"post\" action=\"{base}/tasks/{id}/cancel\"><button>Cancel</button></form>\
+             </td></tr>",
+            id = task.id,
+            name = escape(&task.task_name),
+            step = escape(&task.step.to_string()),
+            error = escape(task.error.as_deref().unwrap_or_default()),
+            failed_at = task.updated_at.format("%Y-%m-%d %H:%M:%S"),
+            base = escape(&base),
+        );
+    }
+    html.push_str("</table></body></html>");
+
+    Ok(Html(html))
+}
+
+async fn retry(
+    State(db): State<PgPool>,
+    OriginalUri(original_uri): OriginalUri,
+    uri: Uri,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Redirect, Error> {
+    check_origin(&headers)?;
+    crate::retry_now(&db, parse_id(&id)?, true).await?;
+    Ok(index_redirect(&original_uri, &uri))
+}
+
+async fn cancel(
+    State(db): State<PgPool>,
+    OriginalUri(original_uri): OriginalUri,
+    uri: Uri,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Redirect, Error> {
+    check_origin(&headers)?;
+    crate::cancel(&db, parse_id(&id)?).await?;
+    Ok(index_redirect(&original_uri, &uri))
+}
+
+/// Redirects back to the dashboard index page
+fn index_redirect(original_uri: &Uri, uri: &Uri) -> Redirect {
+    let base = base_path(original_uri, uri);
+    if base.is_empty() {
+        Redirect::to("/")
+    } else {
+        Redirect::to(&base)
+    }
+}
+
+/// Rejects form submissions from other origins
+///
+/// Browsers tell the origin of a request in `Sec-Fetch-Site`, the older ones
+/// only send `Origin` or `Referer`, which should point to the `Host`. Requests
+/// without any of them are rejected too.
+fn check_origin(headers: &HeaderMap) -> Result<(), Error> {
+    let get = |name| headers.get(name).and_then(|v| v.to_str().ok());
+
+    if let Some(site) = get(header::HeaderName::from_static("sec-fetch-site")) {
+        return if site == "same-origin" {
+            Ok(())
+        } else {
+            Err(Error::CrossOrigin)
+        };
+    }
+
+    let source = get(header::ORIGIN)
+        .filter(|origin| *origin != "null")
+        .or_else(|| get(header::REFERER))
+        .and_then(|source| source.parse::<Uri>().ok());
+    let source_host = source.as_ref().and_then(|uri| uri.authority());
+    match (source_host, get(header::HOST)) {
+        (Some(source), Some(host)) if source.as_str().eq_ignore_ascii_case(host) => Ok(()),
+        _ => Err(Error::CrossOrigin),
+    }
+}
+
+/// Parses a task id from the path
+fn parse_id(id: &str) -> Result<Uuid, Error> {
+    Uuid::parse_str(id).map_err(|_| Error::BadId)
+}
+
+/// Returns the path the dashboard router is nested under
+fn base_path(original_uri: &Uri, uri: &Uri) -> String {
+    let original = original_uri.path();
+    let nested = uri.path().trim_end_matches('/');
+    original
+        .strip_suffix(uri.path())
+        .or_else(|| original.trim_end_matches('/').strip_suffix(nested))
+        .unwrap_or_default()
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Escapes HTML special characters
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,124 @@
+use crate::{util::db_error, Result};
+use chrono::{DateTime, Utc};
+use sqlx::{types::Uuid, PgExecutor};
+
+/// A task which step resulted in an error after all the retry attempts
+#[derive(Debug)]
+pub struct DeadTask {
+    /// Task id
+    pub id: Uuid,
+    /// Serialized failed step
+    pub step: serde_json::Value,
+    /// Number of times the step was tried
+    pub tried: i32,
+    /// The error message
+    pub error: String,
+    /// Time the task was created
+    pub created_at: DateTime<Utc>,
+    /// Time the task has errored
+    pub failed_at: DateTime<Utc>,
+}
+
+/// Returns up to `limit` dead tasks, the most recently failed first
+pub async fn dead_letters<'e>(db: impl PgExecutor<'e>, limit: i64) -> Result<Vec<DeadTask>> {
+    sqlx::query_as!(
+        DeadTask,
+        r#"
+        SELECT
+            id,
+            step,
+            tried,
+            error AS "error!",
+            created_at,
+            updated_at AS failed_at
+        FROM pg_task
+        WHERE error IS NOT NULL
+          AND NOT is_unparseable
+        ORDER BY updated_at DESC
+        LIMIT $1
+        "#,
+        limit,
+    )
+    .fetch_all(db)
+    .await
+    .map_err(db_error!())
+}
+
+/// Clears the error of a dead task and reruns the failed step with all the
+/// retry attempts available again. Returns `false` if there's no such dead
+/// task.
+pub async fn requeue_dead<'e>(db: impl PgExecutor<'e>, id: Uuid) -> Result<bool> {
+    let requeued = sqlx::query!(
+        "
+        UPDATE pg_task
+        SET error = NULL,
+            -- The key could be taken by a newer task while this one was failed
+            unique_key = CASE
+                WHEN EXISTS (
+                    SELECT 1
+                    FROM pg_task pending
+                    WHERE pending.unique_key = pg_task.unique_key
+                      AND pending.done_at IS NULL
+                      AND pending.error IS NULL
+                      AND pending.expired_at IS NULL
+                ) THEN NULL
+                ELSE unique_key
+            END,
+            tried = 0,
+            crash_count = 0,
+            wakeup_at = now()
+        WHERE id = $1
+          AND error IS NOT NULL
+          AND NOT is_unparseable
+        ",
+        id,
+    )
+    .execute(db)
+    .await
+    .map_err(db_error!())?
+    .rows_affected();
+    Ok(requeued > 0)
+}
+
+/// Wakes a task up right away clearing its error if any. Unlike
+/// [`requeue_dead`] it also works for tasks waiting for a retry or for their
+/// scheduled time. The tries counter is kept unless `reset_tries` is set, so
+/// a kept counter leaves the task only the remaining retry attempts. An
+/// unparseable task is retried as well, e.g. after deploying the code able to
+/// deserialize its step. Returns `false` if there's no such task, it's done,
+/// expired or running at the moment.
+pub async fn retry_now<'e>(db: impl PgExecutor<'e>, id: Uuid, reset_tries: bool) -> Result<bool> {
+    let woken = sqlx::query!(
+        "
+        UPDATE pg_task
+        SET error = NULL,
+            -- The key could be taken by a newer task while this one was failed
+            unique_key = CASE
+                WHEN EXISTS (
+                    SELECT 1
+                    FROM pg_task pending
+                    WHERE pending.unique_key = pg_task.unique_key
+                      AND pending.done_at IS NULL
+                      AND pending.error IS NULL
+                      AND pending.expired_at IS NULL
+                ) THEN NULL
+                ELSE unique_key
+            END,
+            is_unparseable = false,
+            tried = CASE WHEN $2 THEN 0 ELSE tried END,
+            crash_count = 0,
+            wakeup_at = now()
+        WHERE id = $1
+          AND done_at IS NULL
+          AND expired_at IS NULL
+          AND (locked_until IS NULL OR locked_until <= now())
+        ",
+        id,
+        reset_tries,
+    )
+    .execute(db)
+    .await
+    .map_err(db_error!())?
+    .rows_affected();
+    Ok(woken > 0)
+}
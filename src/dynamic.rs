@@ -0,0 +1,300 @@
+use crate::{
+    Compensate, Error, Result, RetryStrategy, Scheduler, Step, StepContext, StepError, StepResult,
+};
+use async_trait::async_trait;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use sqlx::PgPool;
+use std::{
+    any::Any, collections::HashMap, fmt, future::Future, pin::Pin, sync::OnceLock, time::Duration,
+};
+
+static REGISTRY: OnceLock<StepRegistry> = OnceLock::new();
+
+/// The task name of the dynamic steps, i.e. the outer key of their `step`
+/// column values
+const TASK_NAME: &str = "Dynamic";
+
+/// A step which could be run as a [`DynStep`] without a central enum, e.g.
+/// by a plugin. It's registered by its name with [`StepRegistry::register`].
+pub trait DynamicStep: Step<DynStep> + Sync + 'static {
+    /// Name of the step stored in the `step_name` column, it should be unique
+    /// among the registered steps
+    const NAME: &'static str;
+}
+
+/// Handlers of the dynamic steps by their names, an alternative to the task
+/// enums for plugin architectures. The registry is set once for the process
+/// with [`set_step_registry`] at startup, then the steps are run by a
+/// `Worker<DynStep>`.
+#[derive(Default)]
+pub struct StepRegistry {
+    deserializers: HashMap<&'static str, DeserializeFn>,
+    compensations: HashMap<&'static str, CompensateFn>,
+}
+
+/// Deserializes a registered step from its payload
+type DeserializeFn = fn(Value) -> serde_json::Result<Box<dyn ErasedStep>>;
+
+/// Runs [`Compensate::compensate`] of a registered step
+type CompensateFn = for<'a> fn(
+    Box<dyn Any + Send>,
+    &'a PgPool,
+    &'a StepContext,
+) -> Pin<
+    Box<dyn Future<Output = std::result::Result<(), StepError>> + Send + 'a>,
+>;
+
+impl StepRegistry {
+    /// Creates an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the step by its [`DynamicStep::NAME`]
+    pub fn register<T: DynamicStep>(mut self) -> Self {
+        self.deserializers.insert(T::NAME, |payload| {
+            Ok(Box::new(T::deserialize(payload)?) as Box<dyn ErasedStep>)
+        });
+        self
+    }
+
+    /// Registers the step implementing [`Compensate`], so it's recorded on
+    /// completion to be compensated if a later step of the task fails
+    pub fn register_compensated<T: DynamicStep + Compensate<DynStep>>(mut self) -> Self {
+        self.compensations.insert(T::NAME, |step, db, ctx| {
+            let step = step
+                .downcast::<T>()
+                .expect("the compensated step is registered by its type");
+            Compensate::compensate(*step, db, ctx)
+        });
+        self.register::<T>()
+    }
+}
+
+/// Sets the registry of the dynamic steps for the process. It should be
+/// called once before scheduling or running any dynamic tasks.
+pub fn set_step_registry(registry: StepRegistry) -> Result<()> {
+    REGISTRY
+        .set(registry)
+        .map_err(|_| Error::StepRegistryAlreadySet)
+}
+
+/// A step of a dynamically dispatched task, stored as its name and payload. A
+/// [`DynamicStep`] is converted into it with `into()`, so its next steps could
+/// be any of the registered ones.
+pub struct DynStep(Box<dyn ErasedStep>);
+
+impl DynStep {
+    /// Wraps the step
+    pub fn new(step: impl DynamicStep) -> Self {
+        Self(Box::new(step))
+    }
+
+    /// Returns the name of the step
+    pub fn name(&self) -> &'static str {
+        self.0.name()
+    }
+}
+
+impl<T: DynamicStep> From<T> for DynStep {
+    fn from(step: T) -> Self {
+        Self::new(step)
+    }
+}
+
+impl fmt::Debug for DynStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Serialize for DynStep {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let payload = self.0.to_value().map_err(serde::ser::Error::custom)?;
+        let mut step = serde_json::Map::new();
+        step.insert(self.name().into(), payload);
+        let mut task = serde_json::Map::new();
+        task.insert(TASK_NAME.into(), step.into());
+        task.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for DynStep {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let Value::Object(mut task) = Value::deserialize(deserializer)? else {
+            return Err(de::Error::custom("a dynamic step should be an object"));
+        };
+        let Some(Value::Object(step)) = task.remove(TASK_NAME) else {
+            return Err(de::Error::custom(format!("missing `{TASK_NAME}` key")));
+        };
+        let Some((name, payload)) = step.into_iter().next() else {
+            return Err(de::Error::custom("missing step name"));
+        };
+        let Some(registry) = REGISTRY.get() else {
+            return Err(de::Error::custom("the step registry isn't set"));
+        };
+        let Some(deserialize) = registry.deserializers.get(name.as_str()) else {
+            return Err(de::Error::custom(format!("unregistered step `{name}`")));
+        };
+        deserialize(payload).map(Self).map_err(de::Error::custom)
+    }
+}
+
+#[async_trait]
+impl Step<DynStep> for DynStep {
+    async fn step(self, db: &PgPool, ctx: &StepContext) -> StepResult<DynStep> {
+        self.0.step(db, ctx).await
+    }
+
+    async fn run_compensation(
+        self,
+        db: &PgPool,
+        ctx: &StepContext,
+    ) -> std::result::Result<(), StepError> {
+        self.0.run_compensation(db, ctx).await
+    }
+
+    fn retry_limit(&self) -> i32 {
+        self.0.retry_limit()
+    }
+
+    fn retry_delay(&self) -> Duration {
+        self.0.retry_delay()
+    }
+
+    fn retry_strategy(&self) -> RetryStrategy {
+        self.0.retry_strategy()
+    }
+
+    fn retry_jitter(&self) -> f32 {
+        self.0.retry_jitter()
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.0.timeout()
+    }
+
+    fn max_concurrency(&self) -> Option<usize> {
+        self.0.max_concurrency()
+    }
+
+    fn is_singleton(&self) -> bool {
+        self.0.is_singleton()
+    }
+
+    fn rate_limit(&self) -> Option<f64> {
+        self.0.rate_limit()
+    }
+
+    fn has_compensation(&self) -> bool {
+        self.0.has_compensation()
+    }
+
+    fn step_type(&self) -> &'static str {
+        self.0.step_type()
+    }
+}
+
+impl Scheduler for DynStep {
+    /// Returns the dynamic task name, so the worker only claims the dynamic
+    /// tasks, while workers of the task enums skip them
+    fn task_names() -> Option<Vec<String>> {
+        Some(vec![TASK_NAME.into()])
+    }
+}
+
+/// An object safe [`Step`] of the dynamic tasks
+#[async_trait]
+trait ErasedStep: fmt::Debug + Send + Sync {
+    fn name(&self) -> &'static str;
+    fn to_value(&self) -> serde_json::Result<Value>;
+    async fn step(self: Box<Self>, db: &PgPool, ctx: &StepContext) -> StepResult<DynStep>;
+    async fn run_compensation(
+        self: Box<Self>,
+        db: &PgPool,
+        ctx: &StepContext,
+    ) -> std::result::Result<(), StepError>;
+    fn retry_limit(&self) -> i32;
+    fn retry_delay(&self) -> Duration;
+    fn retry_strategy(&self) -> RetryStrategy;
+    fn retry_jitter(&self) -> f32;
+    fn timeout(&self) -> Option<Duration>;
+    fn max_concurrency(&self) -> Option<usize>;
+    fn is_singleton(&self) -> bool;
+    fn rate_limit(&self) -> Option<f64>;
+    fn has_compensation(&self) -> bool;
+    fn step_type(&self) -> &'static str;
+}
+
+#[async_trait]
+impl<T: DynamicStep> ErasedStep for T {
+    fn name(&self) -> &'static str {
+        T::NAME
+    }
+
+    fn to_value(&self) -> serde_json::Result<Value> {
+        serde_json::to_value(self)
+    }
+
+    async fn step(self: Box<Self>, db: &PgPool, ctx: &StepContext) -> StepResult<DynStep> {
+        Step::step(*self, db, ctx).await
+    }
+
+    async fn run_compensation(
+        self: Box<Self>,
+        db: &PgPool,
+        ctx: &StepContext,
+    ) -> std::result::Result<(), StepError> {
+        match compensation(T::NAME) {
+            Some(compensate) => compensate(self, db, ctx).await,
+            None => Step::run_compensation(*self, db, ctx).await,
+        }
+    }
+
+    fn retry_limit(&self) -> i32 {
+        Step::retry_limit(self)
+    }
+
+    fn retry_delay(&self) -> Duration {
+        Step::retry_delay(self)
+    }
+
+    fn retry_strategy(&self) -> RetryStrategy {
+        Step::retry_strategy(self)
+    }
+
+    fn retry_jitter(&self) -> f32 {
+        Step::retry_jitter(self)
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        Step::timeout(self)
+    }
+
+    fn max_concurrency(&self) -> Option<usize> {
+        Step::max_concurrency(self)
+    }
+
+    fn is_singleton(&self) -> bool {
+        Step::is_singleton(self)
+    }
+
+    fn rate_limit(&self) -> Option<f64> {
+        Step::rate_limit(self)
+    }
+
+    fn has_compensation(&self) -> bool {
+        compensation(T::NAME).is_some()
+    }
+
+    fn step_type(&self) -> &'static str {
+        Step::step_type(self)
+    }
+}
+
+/// Returns the compensation of the step registered with
+/// [`StepRegistry::register_compensated`]
+fn compensation(name: &str) -> Option<CompensateFn> {
+    REGISTRY.get()?.compensations.get(name).copied()
+}
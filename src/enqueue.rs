@@ -0,0 +1,113 @@
+use crate::{traits::InsertOptions, util::add_delay, RetryPolicy};
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
+/// Options of enqueueing a task with
+/// [`Scheduler::enqueue_with`](crate::Scheduler::enqueue_with), so the time,
+/// priority, deduplication key and the other settings of a task could be
+/// combined. The default options run the task immediately.
+///
+/// ```rust,ignore
+/// let options = EnqueueOptions::new()
+///     .with_delay(Duration::from_secs(60))
+///     .with_priority(10)
+///     .with_unique_key("sync-user-123")
+///     .with_retry(RetryPolicy { limit: Some(5), delay: None });
+/// pg_task::enqueue_with(&db, &task, options).await?;
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct EnqueueOptions {
+    at: Option<DateTime<Utc>>,
+    delay: Duration,
+    priority: i32,
+    metadata: Option<serde_json::Value>,
+    unique_key: Option<String>,
+    retry: RetryPolicy,
+    deadline: Option<DateTime<Utc>>,
+    tenant: Option<String>,
+    group_key: Option<String>,
+}
+
+impl EnqueueOptions {
+    /// Creates the options to run the task immediately
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Schedules the task to run at the time instead of now
+    pub fn with_at(mut self, at: DateTime<Utc>) -> Self {
+        self.at = Some(at);
+        self
+    }
+
+    /// Delays the task, the delay is counted from the time set by
+    /// [`Self::with_at`] if any
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Sets the priority, ready tasks with higher priority are run first, the
+    /// default priority is 0
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Sets the metadata stored in the `metadata` column, e.g.
+    /// `json!({"tenant": 42})`
+    pub fn with_metadata(mut self, metadata: serde_json::Value) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    /// Sets the deduplication key, the task isn't enqueued if there's already
+    /// a pending task with the same key, see
+    /// [`Scheduler::enqueue_unique`](crate::Scheduler::enqueue_unique)
+    pub fn with_unique_key(mut self, key: impl Into<String>) -> Self {
+        self.unique_key = Some(key.into());
+        self
+    }
+
+    /// Overrides the retry settings of the task steps
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Sets the deadline, the task is expired if it isn't finished by it, see
+    /// [`Scheduler::schedule_with_deadline`](crate::Scheduler::schedule_with_deadline)
+    pub fn with_deadline(mut self, deadline: DateTime<Utc>) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Sets the tenant of the task, see
+    /// [`Scheduler::enqueue_for_tenant`](crate::Scheduler::enqueue_for_tenant)
+    pub fn with_tenant(mut self, tenant: impl Into<String>) -> Self {
+        self.tenant = Some(tenant.into());
+        self
+    }
+
+    /// Puts the task into the group of the key, see
+    /// [`Scheduler::enqueue_in_group`](crate::Scheduler::enqueue_in_group)
+    pub fn with_group(mut self, key: impl Into<String>) -> Self {
+        self.group_key = Some(key.into());
+        self
+    }
+
+    /// Converts the options into the column values of the new task
+    pub(crate) fn into_insert(self) -> InsertOptions {
+        let defaults = InsertOptions::new(add_delay(self.at.unwrap_or_else(Utc::now), self.delay));
+        InsertOptions {
+            priority: self.priority,
+            metadata: self.metadata.unwrap_or(defaults.metadata),
+            unique_key: self.unique_key,
+            retry: self.retry,
+            deadline_at: self.deadline,
+            tenant: self.tenant,
+            group_key: self.group_key,
+            ..defaults
+        }
+    }
+}
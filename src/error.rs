@@ -1,11 +1,15 @@
 use crate::NextStep;
-use std::{error::Error as StdError, result::Result as StdResult};
+use std::{error::Error as StdError, fmt, result::Result as StdResult, time::Duration};
 
 /// The crate error
 #[derive(Debug, displaydoc::Display, thiserror::Error)]
 pub enum Error {
     /// can't add task
     AddTask(#[source] sqlx::Error),
+    /// the queue of {0} tasks is full
+    QueueFull(String),
+    /// the queue of tenant {0} is full
+    TenantQueueFull(String),
     /// can't serialize step: {1}
     SerializeStep(#[source] serde_json::Error, String),
     /**
@@ -13,6 +17,28 @@ pub enum Error {
     scheduling and running of the step): {1}
     */
     DeserializeStep(#[source] serde_json::Error, String),
+    /// can't encode step payload
+    EncodePayload(#[source] StepError),
+    /// can't decode step payload
+    DecodePayload(#[source] StepError),
+    /// payload codec is already set
+    PayloadCodecAlreadySet,
+    /// step registry is already set
+    StepRegistryAlreadySet,
+    /// task types are already set
+    TaskTypesAlreadySet,
+    /// invalid cron expression `{0}`: {1}
+    InvalidCron(String, String),
+    /// unknown time zone `{0}`
+    UnknownTimezone(String),
+    /// can't serialize task result
+    SerializeResult(#[source] serde_json::Error),
+    /// can't deserialize task result
+    DeserializeResult(#[source] serde_json::Error),
+    /// can't serialize signal payload
+    SerializeSignal(#[source] serde_json::Error),
+    /// can't deserialize signal payload
+    DeserializeSignal(#[source] serde_json::Error),
     /// can't unlock stale tasks
     UnlockStaleTasks(#[source] sqlx::Error),
     /// waiter can't connect to the db
@@ -21,6 +47,18 @@ pub enum Error {
     ListenerListen(#[source] sqlx::Error),
     /// unreachable: worker semaphore is closed
     UnreachableWorkerSemaphoreClosed(#[source] tokio::sync::AcquireError),
+    /// the lock of task {0} is lost, it could be run by another worker
+    LockLost(sqlx::types::Uuid),
+    /// can't save the state of {1} as the running step is {0}
+    StepTypeMismatch(&'static str, &'static str),
+    /// the step is timed out after {0:?}
+    StepTimeout(std::time::Duration),
+    /// the step panicked: {0}
+    StepPanicked(String),
+    /// the task isn't finished in {0:?}
+    WaitTimeout(std::time::Duration),
+    /// can't apply migration {1}
+    Migrate(#[source] sqlx::Error, i64),
     /// db error: {1}
     Db(#[source] sqlx::Error, String),
 }
@@ -33,3 +71,80 @@ pub type StepError = Box<dyn StdError + 'static + Send + Sync>;
 
 /// Result returning from task steps
 pub type StepResult<T> = StdResult<NextStep<T>, StepError>;
+
+/// Marks a step error as non-retryable, e.g. a validation failure or a 4xx
+/// response, so the task fails right away without using the rest of the retry
+/// attempts:
+///
+/// ```rust,ignore
+/// return Err(FatalError::new("invalid email").into());
+/// ```
+#[derive(Debug)]
+pub struct FatalError(pub StepError);
+
+impl FatalError {
+    /// Wraps the error
+    pub fn new(err: impl Into<StepError>) -> Self {
+        Self(err.into())
+    }
+
+    /// Checks if the step error is fatal
+    pub(crate) fn is_fatal(err: &StepError) -> bool {
+        err.is::<Self>()
+    }
+}
+
+impl fmt::Display for FatalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl StdError for FatalError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.0.source()
+    }
+}
+
+/// Makes the step retry after the given delay instead of the one of its retry
+/// strategy, e.g. from the `Retry-After` header of a 429 response. It's still
+/// counted as a retry attempt. Delays longer than 100 years are clamped, so a
+/// bogus header value can't overflow the time:
+///
+/// ```rust,ignore
+/// return Err(RetryAfter::new(Duration::from_secs(30), "rate limited").into());
+/// ```
+#[derive(Debug)]
+pub struct RetryAfter {
+    /// The delay before the retry
+    pub delay: Duration,
+    /// The wrapped error
+    pub error: StepError,
+}
+
+impl RetryAfter {
+    /// Wraps the error
+    pub fn new(delay: Duration, err: impl Into<StepError>) -> Self {
+        Self {
+            delay,
+            error: err.into(),
+        }
+    }
+
+    /// Returns the retry delay if the step error has it
+    pub(crate) fn delay_of(err: &StepError) -> Option<Duration> {
+        err.downcast_ref::<Self>().map(|e| e.delay)
+    }
+}
+
+impl fmt::Display for RetryAfter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl StdError for RetryAfter {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.error.source()
+    }
+}
@@ -0,0 +1,99 @@
+use crate::{notifier, FailureNotifier, TaskFailure};
+use sqlx::types::Uuid;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::broadcast;
+
+/// Number of events buffered for each subscriber, a lagging subscriber misses
+/// the older events
+pub(crate) const EVENTS_CAPACITY: usize = 1024;
+
+/// A task lifecycle event emitted by the worker, see
+/// [`Worker::events`](crate::Worker::events)
+#[derive(Clone, Debug)]
+pub enum TaskEvent {
+    /// The task is claimed by the worker to run its current step
+    Claimed {
+        /// Task id
+        id: Uuid,
+        /// Name of the task
+        task_name: String,
+    },
+    /// The step is started
+    StepStarted {
+        /// Task id
+        id: Uuid,
+        /// The step as it's stored in the table
+        step: serde_json::Value,
+        /// Number of the attempt to run the step, starting from 1
+        attempt: i32,
+    },
+    /// The step is finished successfully or with an error
+    StepFinished {
+        /// Task id
+        id: Uuid,
+        /// The step as it's stored in the table
+        step: serde_json::Value,
+        /// Number of the attempt to run the step, starting from 1
+        attempt: i32,
+        /// The error message if the step has failed
+        error: Option<String>,
+    },
+    /// The failed step is scheduled to retry
+    Retried {
+        /// Task id
+        id: Uuid,
+        /// The step as it's stored in the table
+        step: serde_json::Value,
+        /// Number of the failed attempt
+        attempt: i32,
+        /// The delay before the next attempt
+        delay: Duration,
+        /// The error message
+        error: String,
+    },
+    /// The task has failed after all the retry attempts
+    Failed {
+        /// Task id
+        id: Uuid,
+        /// The failed step as it's stored in the table
+        step: serde_json::Value,
+        /// The error message
+        error: String,
+    },
+    /// The task is successfully completed
+    Done {
+        /// Task id
+        id: Uuid,
+    },
+}
+
+/// Receivers of the task lifecycle events and failures
+#[derive(Clone)]
+pub(crate) struct Observers {
+    pub events: broadcast::Sender<TaskEvent>,
+    pub failure_notifiers: Vec<Arc<dyn FailureNotifier>>,
+}
+
+impl Observers {
+    /// Sends the event to the subscribers if there are any, the event is only
+    /// built for them
+    pub fn emit(&self, event: impl FnOnce() -> TaskEvent) {
+        if self.events.receiver_count() > 0 {
+            self.events.send(event()).ok();
+        }
+    }
+
+    /// Notifies about the failed task
+    pub fn notify_failure(&self, failure: TaskFailure) {
+        notifier::notify_failure(&self.failure_notifiers, failure);
+    }
+}
+
+impl Default for Observers {
+    fn default() -> Self {
+        Self {
+            events: broadcast::channel(EVENTS_CAPACITY).0,
+            failure_notifiers: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use crate::{util::db_error, wait_for, Outcome, Result, Scheduler, TaskInfo};
+use chrono::{DateTime, Utc};
+use sqlx::{types::Uuid, Acquire, PgExecutor, PgPool, Postgres};
+use std::{fmt, marker::PhantomData, time::Duration};
+
+/// A handle of an enqueued task returned by [`enqueue`](crate::enqueue) and
+/// the other enqueueing helpers, wraps the task id to manage the task without
+/// pairing the id with the free functions. Get a handle of a task known by its
+/// id with [`TaskHandle::new`].
+pub struct TaskHandle<T> {
+    id: Uuid,
+    scheduler: PhantomData<fn() -> T>,
+}
+
+impl<T: Scheduler> TaskHandle<T> {
+    /// Creates a handle of the task with the id
+    pub fn new(id: Uuid) -> Self {
+        Self {
+            id,
+            scheduler: PhantomData,
+        }
+    }
+
+    /// Returns the task id
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// Returns the current state of the task, see [`find`](crate::find)
+    pub async fn status<'e>(&self, db: impl PgExecutor<'e>) -> Result<Option<TaskInfo>> {
+        crate::find(db, self.id).await
+    }
+
+    /// Returns the current step of the task deserialized, `None` if there's
+    /// no such task
+    pub async fn step<'e>(&self, db: impl PgExecutor<'e>) -> Result<Option<T>> {
+        self.status(db).await?.map(|info| info.step()).transpose()
+    }
+
+    /// Cancels the task along with its children, see
+    /// [`cancel`](crate::cancel)
+    pub async fn cancel<'a>(&self, db: impl Acquire<'a, Database = Postgres>) -> Result<bool> {
+        crate::cancel(db, self.id).await
+    }
+
+    /// Waits for the task to reach a terminal state, see
+    /// [`wait_for`](crate::wait_for)
+    pub async fn await_done(&self, db: &PgPool, timeout: Duration) -> Result<Outcome> {
+        wait_for(db, self.id, timeout).await
+    }
+
+    /// Moves the current step of a waiting task to run at the time. Returns
+    /// `false` if there's no such task or it's running, failed or finished.
+    pub async fn reschedule<'e>(&self, db: impl PgExecutor<'e>, at: DateTime<Utc>) -> Result<bool> {
+        let updated = sqlx::query!(
+            r#"
+            UPDATE pg_task
+            SET wakeup_at = $2
+            WHERE id = $1
+              AND done_at IS NULL
+              AND error IS NULL
+              AND expired_at IS NULL
+              AND locked_by IS NULL
+            "#,
+            self.id,
+            at
+        )
+        .execute(db)
+        .await
+        .map_err(db_error!())?
+        .rows_affected();
+        Ok(updated > 0)
+    }
+}
+
+impl<T> Clone for TaskHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for TaskHandle<T> {}
+
+impl<T> fmt::Debug for TaskHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("TaskHandle").field(&self.id).finish()
+    }
+}
+
+impl<T> fmt::Display for TaskHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.id.fmt(f)
+    }
+}
+
+impl<T> From<TaskHandle<T>> for Uuid {
+    fn from(handle: TaskHandle<T>) -> Self {
+        handle.id
+    }
+}
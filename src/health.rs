@@ -0,0 +1,65 @@
+use chrono::{DateTime, Utc};
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Mutex,
+};
+
+/// The state of a worker returned by [`Worker::health`](crate::Worker::health),
+/// e.g. for Kubernetes liveness and readiness probes
+#[derive(Clone, Debug)]
+pub struct WorkerHealth {
+    /// The worker is processing tasks, i.e. it's started and isn't stopped
+    pub is_running: bool,
+    /// The worker is connected to the db to listen to the tasks table changes,
+    /// it's always true for a running worker with
+    /// [`Worker::with_polling`](crate::Worker::with_polling)
+    pub is_listening: bool,
+    /// Time the worker has claimed a task last time. An idle worker doesn't
+    /// claim tasks, so it's only a sign of a wedged worker while there are
+    /// ready tasks, see [`stats`](crate::stats).
+    pub last_claimed_at: Option<DateTime<Utc>>,
+    /// Number of tasks which steps are running by the worker
+    pub in_flight: usize,
+    /// The maximum number of concurrent tasks of the worker
+    pub concurrency: usize,
+}
+
+impl WorkerHealth {
+    /// Returns the share of the worker concurrency in use, from 0 to 1. A
+    /// saturated worker doesn't claim new tasks until a running step is
+    /// finished.
+    pub fn saturation(&self) -> f64 {
+        if self.concurrency == 0 {
+            return 1.0;
+        }
+        self.in_flight as f64 / self.concurrency as f64
+    }
+}
+
+/// The health state updated by a running worker
+#[derive(Default)]
+pub(crate) struct HealthState {
+    pub is_running: AtomicBool,
+    pub last_claimed_at: Mutex<Option<DateTime<Utc>>>,
+    pub in_flight: AtomicUsize,
+}
+
+impl HealthState {
+    /// Records claiming of tasks
+    pub fn claimed(&self) {
+        if let Ok(mut last_claimed_at) = self.last_claimed_at.lock() {
+            *last_claimed_at = Some(Utc::now());
+        }
+    }
+
+    /// Returns the current worker health
+    pub fn report(&self, is_listening: bool, concurrency: usize) -> WorkerHealth {
+        WorkerHealth {
+            is_running: self.is_running.load(Ordering::Relaxed),
+            is_listening,
+            last_claimed_at: self.last_claimed_at.lock().ok().and_then(|at| *at),
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            concurrency,
+        }
+    }
+}
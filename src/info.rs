@@ -0,0 +1,183 @@
+use crate::{payload, util::db_error, Error, Result, Scheduler};
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use sqlx::{types::Uuid, PgExecutor};
+
+/// The current state of a task
+#[derive(Debug)]
+pub struct TaskInfo {
+    /// Task id
+    pub id: Uuid,
+    /// Name of the task, i.e. the variant of the enum passed to the worker
+    pub task_name: String,
+    /// Name of the current step, i.e. the variant of the task enum
+    pub step_name: String,
+    /// The current step as it's stored in the table
+    pub step: serde_json::Value,
+    /// The current step encoded with a binary [`Codec`](crate::Codec)
+    pub step_bytes: Option<Vec<u8>>,
+    /// Number of times the current step has resulted in an error
+    pub tried: i32,
+    /// Number of consecutive attempts to run the current step interrupted by
+    /// a worker crash, see
+    /// [`Worker::with_crash_limit`](crate::Worker::with_crash_limit)
+    pub crash_count: i32,
+    /// The error message if the step has failed after all the retries
+    pub error: Option<String>,
+    /// Scheduled time to run the current step
+    pub wakeup_at: DateTime<Utc>,
+    /// Indicates if the current step is running right now
+    pub is_running: bool,
+    /// Indicates if the task is paused
+    pub is_paused: bool,
+    /// Indicates if the task waits for a [`signal`](crate::signal)
+    pub is_awaiting_signal: bool,
+    /// Indicates if the step can't be deserialized, see
+    /// [`admin::fix_unparseable`](crate::admin::fix_unparseable)
+    pub is_unparseable: bool,
+    /// Priority of the task
+    pub priority: i32,
+    /// Metadata of the task
+    pub metadata: serde_json::Value,
+    /// Tenant of the task, see
+    /// [`Scheduler::enqueue_for_tenant`](crate::Scheduler::enqueue_for_tenant)
+    pub tenant: Option<String>,
+    /// Progress of the current step reported by the step
+    pub progress: Option<serde_json::Value>,
+    /// Time the task was created
+    pub created_at: DateTime<Utc>,
+    /// Time the task was updated
+    pub updated_at: DateTime<Utc>,
+    /// Time the task was completed, it's only kept by workers with
+    /// [`Worker::with_keep_done`](crate::Worker::with_keep_done)
+    pub done_at: Option<DateTime<Utc>>,
+    /// Id of the task which spawned this one using
+    /// [`StepContext::spawn_child`](crate::StepContext::spawn_child)
+    pub parent_id: Option<Uuid>,
+    /// Time after which the task steps aren't run anymore, see
+    /// [`schedule_with_deadline`](crate::schedule_with_deadline)
+    pub deadline_at: Option<DateTime<Utc>>,
+    /// Time the task was expired as it wasn't finished before its deadline
+    pub expired_at: Option<DateTime<Utc>>,
+    /// The result of the done task, see
+    /// [`NextStep::done_with`](crate::NextStep::done_with)
+    pub result: Option<serde_json::Value>,
+    /// Errors of the task steps including the retried ones, each one is an
+    /// object with the error `chain`, the `step` name, e.g. `Task::Step`, the
+    /// `attempt` number and the time it happened `at`
+    pub errors: serde_json::Value,
+}
+
+impl TaskInfo {
+    /// Deserializes the current step, `T` is the enum passed to
+    /// [`scheduler!`](crate::scheduler)
+    pub fn step<T: Scheduler>(&self) -> Result<T> {
+        payload::deserialize(&self.step, self.step_bytes.as_deref(), T::CODEC)
+    }
+}
+
+/// Returns the current state of the task, `None` if the task doesn't exist or
+/// is finished and removed
+pub async fn find<'e>(db: impl PgExecutor<'e>, id: Uuid) -> Result<Option<TaskInfo>> {
+    sqlx::query_as!(
+        TaskInfo,
+        r#"
+        SELECT
+            id,
+            task_name,
+            step_name,
+            step,
+            step_bytes,
+            tried,
+            crash_count,
+            error,
+            wakeup_at,
+            COALESCE(locked_until > now(), false) AS "is_running!",
+            is_paused,
+            is_awaiting_signal,
+            is_unparseable,
+            priority,
+            metadata,
+            tenant,
+            progress,
+            created_at,
+            updated_at,
+            done_at,
+            parent_id,
+            deadline_at,
+            expired_at,
+            result,
+            errors
+        FROM pg_task
+        WHERE id = $1
+        "#,
+        id,
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(db_error!())
+}
+
+/// Returns the children of the task ordered by their creation time
+pub(crate) async fn children<'e>(
+    db: impl PgExecutor<'e>,
+    parent_id: Uuid,
+) -> Result<Vec<TaskInfo>> {
+    sqlx::query_as!(
+        TaskInfo,
+        r#"
+        SELECT
+            id,
+            task_name,
+            step_name,
+            step,
+            step_bytes,
+            tried,
+            crash_count,
+            error,
+            wakeup_at,
+            COALESCE(locked_until > now(), false) AS "is_running!",
+            is_paused,
+            is_awaiting_signal,
+            is_unparseable,
+            priority,
+            metadata,
+            tenant,
+            progress,
+            created_at,
+            updated_at,
+            done_at,
+            parent_id,
+            deadline_at,
+            expired_at,
+            result,
+            errors
+        FROM pg_task
+        WHERE parent_id = $1
+        ORDER BY created_at, id
+        "#,
+        parent_id,
+    )
+    .fetch_all(db)
+    .await
+    .map_err(db_error!())
+}
+
+/// Returns the result of the task done with
+/// [`NextStep::done_with`](crate::NextStep::done_with), `None` if the task
+/// doesn't exist, isn't done yet or is done without a result
+pub async fn result<'e, R: DeserializeOwned>(
+    db: impl PgExecutor<'e>,
+    id: Uuid,
+) -> Result<Option<R>> {
+    sqlx::query_scalar!(
+        "SELECT result FROM pg_task WHERE id = $1 AND done_at IS NOT NULL",
+        id
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(db_error!())?
+    .flatten()
+    .map(|result| serde_json::from_value(result).map_err(Error::DeserializeResult))
+    .transpose()
+}
@@ -2,45 +2,190 @@
 #![forbid(unsafe_code)]
 #![warn(clippy::all, missing_docs, nonstandard_style, future_incompatible)]
 
+pub mod admin;
+mod any_task;
+mod cancel;
+mod claim;
+mod cleanup;
+mod clock;
+mod codec;
+mod context;
+#[cfg(feature = "dashboard")]
+pub mod dashboard;
+mod dead_letter;
+mod dynamic;
+mod enqueue;
 mod error;
+mod events;
+mod handle;
+mod health;
+mod info;
 mod listener;
+mod lock;
 mod macros;
+mod maintenance;
+mod middleware;
+mod migrate;
 mod next_step;
+mod notifier;
+mod pause;
+mod payload;
+mod rate_limit;
+mod recurring;
+mod registry;
+mod retry;
+mod schema;
+mod signal;
+mod stats;
 mod task;
+pub mod test;
+mod trace;
 mod traits;
 mod util;
+mod wait;
 mod worker;
 
-pub use error::{Error, Result, StepError, StepResult};
-pub use next_step::NextStep;
-pub use traits::{Scheduler, Step};
+pub use any_task::{set_task_types, AnyTask, TaskType, TaskTypes};
+pub use cancel::{cancel, cancel_tree, CancelledTree};
+pub use claim::{ClaimStrategy, IsolationLevel};
+pub use cleanup::cleanup;
+pub use clock::Clock;
+#[cfg(feature = "cbor")]
+pub use codec::Cbor;
+#[cfg(feature = "msgpack")]
+pub use codec::MessagePack;
+pub use codec::{Codec, Json};
+pub use context::StepContext;
+pub use dead_letter::{dead_letters, requeue_dead, retry_now, DeadTask};
+pub use dynamic::{set_step_registry, DynStep, DynamicStep, StepRegistry};
+pub use enqueue::EnqueueOptions;
+pub use error::{Error, FatalError, Result, RetryAfter, StepError, StepResult};
+pub use events::TaskEvent;
+pub use handle::TaskHandle;
+pub use health::WorkerHealth;
+pub use info::{find, result, TaskInfo};
+pub use listener::Listener;
+pub use maintenance::MaintenanceWindow;
+pub use middleware::StepMiddleware;
+pub use migrate::migrate;
+pub use next_step::{NextStep, SubTask};
+#[cfg(feature = "webhook")]
+pub use notifier::WebhookNotifier;
+pub use notifier::{FailureNotifier, TaskFailure, TracingNotifier};
+pub use pause::{pause, pause_all, resume, resume_all};
+pub use payload::{set_payload_codec, PayloadCodec};
+pub use recurring::{schedule_recurring, unschedule_recurring, CatchUp, CronSchedule};
+pub use registry::{workers, WorkerInfo};
+pub use retry::{ReconnectPolicy, RetryPolicy, RetryStrategy};
+pub use schema::in_schema;
+pub use signal::signal;
+pub use stats::{stats, QueueStats, StepStats};
+pub use tokio_util::sync::CancellationToken;
+#[doc(hidden)]
+pub use traits::{run_blocking, run_chunk, CompensationProbe, ViaCompensate, ViaStep};
+pub use traits::{
+    BlockingStep, ChainStep, ChunkProgress, ChunkedStep, Compensate, Scheduler, Step,
+};
+pub use wait::{wait_for, Outcome};
 pub use worker::Worker;
 
 use chrono::{DateTime, Utc};
-use sqlx::{types::Uuid, PgExecutor};
+use sqlx::PgExecutor;
 use std::time::Duration;
 
-const LOST_CONNECTION_SLEEP: Duration = Duration::from_secs(1);
-
-/// Enqueues the task to be run immediately
-pub async fn enqueue<'e>(db: impl PgExecutor<'e>, task: &impl Scheduler) -> Result<Uuid> {
+/// Enqueues the task to be run immediately, returns its handle
+pub async fn enqueue<'e, T: Scheduler>(db: impl PgExecutor<'e>, task: &T) -> Result<TaskHandle<T>> {
     task.enqueue(db).await
 }
 
+/// Enqueues the task with the options, see [`EnqueueOptions`]
+pub async fn enqueue_with<'e, T: Scheduler>(
+    db: impl PgExecutor<'e>,
+    task: &T,
+    options: EnqueueOptions,
+) -> Result<TaskHandle<T>> {
+    task.enqueue_with(db, options).await
+}
+
+/// Enqueues the task to be run immediately with the given priority
+pub async fn enqueue_with_priority<'e, T: Scheduler>(
+    db: impl PgExecutor<'e>,
+    task: &T,
+    priority: i32,
+) -> Result<TaskHandle<T>> {
+    task.enqueue_with_priority(db, priority).await
+}
+
+/// Enqueues the task to be run immediately with the given metadata
+pub async fn enqueue_with_meta<'e, T: Scheduler>(
+    db: impl PgExecutor<'e>,
+    task: &T,
+    metadata: serde_json::Value,
+) -> Result<TaskHandle<T>> {
+    task.enqueue_with_meta(db, metadata).await
+}
+
+/// Enqueues the task unless there's already a task with the same key
+pub async fn enqueue_unique<'e, T: Scheduler>(
+    db: impl PgExecutor<'e>,
+    task: &T,
+    key: &str,
+) -> Result<TaskHandle<T>> {
+    task.enqueue_unique(db, key).await
+}
+
+/// Enqueues the task of the tenant to be run immediately
+pub async fn enqueue_for_tenant<'e, T: Scheduler>(
+    db: impl PgExecutor<'e>,
+    task: &T,
+    tenant: &str,
+) -> Result<TaskHandle<T>> {
+    task.enqueue_for_tenant(db, tenant).await
+}
+
+/// Enqueues the task to be run immediately in the group of the key, tasks of
+/// a group run one at a time in the enqueue order
+pub async fn enqueue_in_group<'e, T: Scheduler>(
+    db: impl PgExecutor<'e>,
+    task: &T,
+    key: &str,
+) -> Result<TaskHandle<T>> {
+    task.enqueue_in_group(db, key).await
+}
+
+/// Enqueues many tasks to be run immediately using a single insert
+pub async fn enqueue_many<'e, S: Scheduler + Send>(
+    db: impl PgExecutor<'e>,
+    tasks: impl IntoIterator<Item = S> + Send,
+) -> Result<Vec<TaskHandle<S>>> {
+    S::enqueue_many(db, tasks).await
+}
+
 /// Schedules a task to be run after a specified delay
-pub async fn delay<'e>(
+pub async fn delay<'e, T: Scheduler>(
     db: impl PgExecutor<'e>,
-    task: &impl Scheduler,
+    task: &T,
     delay: Duration,
-) -> Result<Uuid> {
+) -> Result<TaskHandle<T>> {
     task.delay(db, delay).await
 }
 
 /// Schedules a task to run at a specified time in the future
-pub async fn schedule<'e>(
+pub async fn schedule<'e, T: Scheduler>(
     db: impl PgExecutor<'e>,
-    task: &impl Scheduler,
+    task: &T,
     at: DateTime<Utc>,
-) -> Result<Uuid> {
+) -> Result<TaskHandle<T>> {
     task.schedule(db, at).await
 }
+
+/// Schedules a task to run at a specified time, unless it isn't finished by the
+/// deadline
+pub async fn schedule_with_deadline<'e, T: Scheduler>(
+    db: impl PgExecutor<'e>,
+    task: &T,
+    at: DateTime<Utc>,
+    deadline: DateTime<Utc>,
+) -> Result<TaskHandle<T>> {
+    task.schedule_with_deadline(db, at, deadline).await
+}
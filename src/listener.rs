@@ -1,101 +1,367 @@
-use crate::{util, LOST_CONNECTION_SLEEP};
-use sqlx::{postgres::PgListener, PgPool};
+use crate::{util, ReconnectPolicy};
+use chrono::{DateTime, Utc};
+use sqlx::{
+    postgres::{PgConnectOptions, PgListener, PgPoolOptions},
+    PgPool,
+};
 use std::{
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        Arc, Mutex, Weak,
     },
     time::Duration,
 };
 use tokio::{
-    sync::{futures::Notified, Notify},
-    time::{sleep, timeout},
+    sync::{futures::Notified, Notify, OnceCell},
+    time::{sleep, sleep_until, timeout, Instant},
 };
+use tokio_util::sync::CancellationToken;
 use tracing::{trace, warn};
 
 const NOTIFICATION_CHANNEL: &str = "pg_task_changed";
 const STOP_WORKER_NOTIFICATION: &str = "stop_worker";
+/// The channel of the earliest wakeup times of the changed tasks
+const WAKEUP_CHANNEL: &str = "pg_task_wakeup";
+/// The minimal interval between waking up the waiters on the table changes
+const COALESCE_WINDOW: Duration = Duration::from_millis(20);
 
-/// Waits for tasks table to change
+/// Listens to the tasks table changes for workers. Each worker creates its
+/// own listener by default, workers of different task enums in one process
+/// could share one with
+/// [`Worker::with_listener`](crate::Worker::with_listener) to use a single
+/// `LISTEN` connection. The connection is established by the first of the
+/// workers to run, with its pool and reconnect policy.
+#[derive(Clone, Default)]
 pub struct Listener {
-    notify: Arc<Notify>,
-    stop_worker: Arc<AtomicBool>,
+    poll_interval: Option<Duration>,
+    connect_options: Option<PgConnectOptions>,
+    state: Arc<ListenerState>,
+}
+
+/// The state shared by the clones of a [`Listener`]
+#[derive(Default)]
+struct ListenerState {
+    notify: Notify,
+    stop_worker: CancellationToken,
+    is_connected: AtomicBool,
+    is_listening: OnceCell<()>,
+    /// The wakeups of the workers using the listener
+    wakeups: Mutex<Vec<Weak<Wakeups>>>,
+}
+
+/// A worker handle of a [`Listener`], which could be shared with other
+/// workers. Stopping the worker doesn't stop the others, while the stop
+/// notification stops all of them.
+pub(crate) struct WorkerListener {
+    listener: Listener,
+    stop_worker: CancellationToken,
+    wakeups: Arc<Wakeups>,
+}
+
+/// The upcoming wakeups of the tasks received from the db, so a worker could
+/// sleep until the earliest of them instead of checking the tasks table on
+/// each change. Only the earliest one is kept, as the worker checks the table
+/// after it anyway and finds the later ones there.
+#[derive(Default)]
+struct Wakeups {
+    earliest: Mutex<Option<DateTime<Utc>>>,
+    /// Notified when a wakeup earlier than the others is scheduled
+    earlier: Notify,
 }
 
 /// Subscription to the [`Listener`] notifications
-pub struct Subscription<'a>(Notified<'a>);
+pub(crate) struct Subscription<'a> {
+    notified: Notified<'a>,
+    stop_worker: &'a CancellationToken,
+    poll_interval: Option<Duration>,
+}
 
 impl Listener {
-    /// Creates a waiter
+    /// Creates a listener
     pub fn new() -> Self {
-        let notify = Arc::new(Notify::new());
-        let stop_worker = Arc::new(AtomicBool::new(false));
+        Self::default()
+    }
+
+    /// Creates a listener which doesn't listen to the db notifications, the
+    /// waiting just ends every `interval` instead
+    pub fn polling(interval: Duration) -> Self {
         Self {
-            notify,
-            stop_worker,
+            poll_interval: Some(interval),
+            ..Self::new()
         }
     }
 
+    /// Sets the options of the listening connection, by default it's
+    /// acquired from the pool of the worker, see
+    /// [`Worker::with_listener_connect_options`](crate::Worker::with_listener_connect_options)
+    pub fn with_connect_options(mut self, options: PgConnectOptions) -> Self {
+        self.connect_options = Some(options);
+        self
+    }
+
+    /// Connects to the db and starts to listen to tasks table changes unless
+    /// it's started already
+    async fn listen(&self, db: PgPool, reconnect: ReconnectPolicy) -> crate::Result<()> {
+        self.state
+            .is_listening
+            .get_or_try_init(|| self.connect(db, reconnect))
+            .await?;
+        Ok(())
+    }
+
     /// Connects to the db and starts to listen to tasks table changes
-    pub async fn listen(&self, db: PgPool) -> crate::Result<()> {
+    async fn connect(&self, db: PgPool, reconnect: ReconnectPolicy) -> crate::Result<()> {
+        if let Some(interval) = self.poll_interval {
+            trace!("Polling the tasks table every {interval:?} instead of listening");
+            self.state.is_connected.store(true, Ordering::Relaxed);
+            return Ok(());
+        }
+        let schema = sqlx::query_scalar!("SELECT current_schema()")
+            .fetch_one(&db)
+            .await
+            .map_err(crate::Error::ListenerConnect)?;
+        let db = match &self.connect_options {
+            Some(options) => PgPoolOptions::new()
+                .max_connections(1)
+                .connect_lazy_with(options.clone()),
+            None => db,
+        };
         let mut listener = PgListener::connect_with(&db)
             .await
             .map_err(crate::Error::ListenerConnect)?;
         listener
-            .listen(NOTIFICATION_CHANNEL)
+            .listen_all([NOTIFICATION_CHANNEL, WAKEUP_CHANNEL])
             .await
             .map_err(crate::Error::ListenerListen)?;
+        self.state.is_connected.store(true, Ordering::Relaxed);
+
+        // Notifications received within the window after waking up the waiters
+        // are coalesced into a single wakeup at the end of the window, so
+        // notification storms, e.g. during bulk enqueueing, don't wake up the
+        // waiters on each of them
+        let changed = Arc::new(Notify::new());
+        let state = self.state.clone();
+        let changed_waker = changed.clone();
+        tokio::spawn(async move {
+            loop {
+                changed_waker.notified().await;
+                state.notify.notify_waiters();
+                sleep(COALESCE_WINDOW).await;
+            }
+        });
 
-        let notify = self.notify.clone();
-        let stop_worker = self.stop_worker.clone();
+        let state = self.state.clone();
         tokio::spawn(async move {
             loop {
                 match listener.recv().await {
                     Ok(msg) => {
-                        if msg.payload() == STOP_WORKER_NOTIFICATION {
+                        if msg.channel() == WAKEUP_CHANNEL {
+                            let Some((msg_schema, at)) = msg.payload().rsplit_once(' ') else {
+                                continue;
+                            };
+                            if schema.as_deref().is_some_and(|s| s != msg_schema) {
+                                continue;
+                            }
+                            if let Some(at) =
+                                at.parse().ok().and_then(DateTime::from_timestamp_micros)
+                            {
+                                state.schedule_wakeup(at);
+                            }
+                            continue;
+                        } else if msg.payload() == STOP_WORKER_NOTIFICATION {
                             trace!("Got stop-worker notification");
-                            stop_worker.store(true, Ordering::SeqCst);
+                            state.stop_worker.cancel();
+                            continue;
+                        } else if schema.as_deref().is_some_and(|s| s != msg.payload()) {
+                            // The table in another schema is changed
+                            continue;
                         }
                     }
                     Err(e) => {
                         warn!("Listening for the tasks table changes is interrupted (probably due to db connection loss):\n{}", source_chain::to_string(&e));
-                        sleep(LOST_CONNECTION_SLEEP).await;
-                        util::wait_for_reconnection(&db, LOST_CONNECTION_SLEEP).await;
+                        state.is_connected.store(false, Ordering::Relaxed);
+                        util::wait_for_reconnection(&db, &reconnect).await;
+                        state.is_connected.store(true, Ordering::Relaxed);
+                        // The wakeups could be missed while disconnected
+                        state.schedule_wakeup(Utc::now());
                         warn!("Listening for the tasks table changes is probably restored");
                     }
                 };
-                notify.notify_waiters();
+                changed.notify_one();
             }
         });
         Ok(())
     }
+}
+
+impl ListenerState {
+    /// Adds the wakeup to the wakeups of all the workers using the listener
+    fn schedule_wakeup(&self, at: DateTime<Utc>) {
+        let Ok(mut wakeups) = self.wakeups.lock() else {
+            return;
+        };
+        wakeups.retain(|wakeups| match wakeups.upgrade() {
+            Some(wakeups) => {
+                wakeups.schedule(at);
+                true
+            }
+            None => false,
+        });
+    }
+}
+
+impl WorkerListener {
+    /// Creates a handle of the listener for a worker
+    pub fn new(listener: Listener) -> Self {
+        let wakeups = Arc::<Wakeups>::default();
+        if let Ok(mut all) = listener.state.wakeups.lock() {
+            all.push(Arc::downgrade(&wakeups));
+        }
+        Self {
+            stop_worker: listener.state.stop_worker.child_token(),
+            listener,
+            wakeups,
+        }
+    }
+
+    /// Replaces the listener with a new one connecting with the options,
+    /// polling is kept if it's set
+    pub fn with_connect_options(self, options: PgConnectOptions) -> Self {
+        Self::new(Listener {
+            poll_interval: self.listener.poll_interval,
+            ..Listener::new().with_connect_options(options)
+        })
+    }
+
+    /// Connects to the db and starts to listen to tasks table changes unless
+    /// another worker sharing the listener has started it already
+    pub async fn listen(&self, db: PgPool, reconnect: ReconnectPolicy) -> crate::Result<()> {
+        self.listener.listen(db, reconnect).await
+    }
 
     /// Subscribes for notifications.
     ///
     /// Awaiting on the result ends on the first notification after the
     /// subscription, even if it happens between the subscription and awaiting.
     pub fn subscribe(&self) -> Subscription<'_> {
-        Subscription(self.notify.notified())
+        Subscription {
+            notified: self.listener.state.notify.notified(),
+            stop_worker: &self.stop_worker,
+            poll_interval: self.listener.poll_interval,
+        }
+    }
+
+    /// Waits until the earliest wakeup of the tasks received from the db, but
+    /// no longer than `period`. The due wakeups are consumed, so each of them
+    /// ends the waiting once.
+    pub async fn wait_for_wakeup(&self, period: Duration) {
+        let period = match self.listener.poll_interval {
+            Some(interval) => period.min(interval),
+            None => period,
+        };
+        let deadline = Instant::now() + period;
+        trace!("⌛Waiting for the next wakeup for up to {period:?}");
+        loop {
+            // Subscribing before the check to not miss an earlier wakeup
+            let earlier = self.wakeups.earlier.notified();
+            tokio::pin!(earlier);
+            earlier.as_mut().enable();
+            let now = Utc::now();
+            if self.wakeups.take_due(now) {
+                trace!("⚡A task is due to wake up");
+                return;
+            }
+            let until = match self.wakeups.earliest() {
+                Some(at) => deadline.min(Instant::now() + util::chrono_duration_to_std(at - now)),
+                None => deadline,
+            };
+            tokio::select! {
+                _ = earlier => (),
+                _ = self.stop_worker.cancelled() => return,
+                _ = sleep_until(until) => if until >= deadline {
+                    trace!("⏰The waiting timeout has expired");
+                    return;
+                },
+            }
+        }
+    }
+
+    /// Returns true if the listener is connected to the db, or it's polling
+    pub fn is_connected(&self) -> bool {
+        self.listener.state.is_connected.load(Ordering::Relaxed)
     }
 
-    /// Returns true if notification to stop worker is received
+    /// Returns true if the worker is stopped or the stop notification is
+    /// received
     pub fn time_to_stop_worker(&self) -> bool {
-        self.stop_worker.load(Ordering::SeqCst)
+        self.stop_worker.is_cancelled()
+    }
+
+    /// Makes the worker stop as if the stop notification is received, other
+    /// workers sharing the listener aren't affected
+    pub fn stop_worker(&self) {
+        self.stop_worker.cancel();
+    }
+
+    /// Returns a token which is cancelled when the worker is asked to stop
+    pub fn stop_token(&self) -> CancellationToken {
+        self.stop_worker.clone()
+    }
+}
+
+impl Wakeups {
+    /// Adds a wakeup if it's earlier than the pending one, the waiters are
+    /// notified then. Later wakeups are dropped.
+    fn schedule(&self, at: DateTime<Utc>) {
+        let Ok(mut earliest) = self.earliest.lock() else {
+            return;
+        };
+        if earliest.is_some_and(|earliest| earliest <= at) {
+            return;
+        }
+        *earliest = Some(at);
+        drop(earliest);
+        self.earlier.notify_waiters();
+    }
+
+    /// Removes the wakeup if it's due at the time, returns false otherwise
+    fn take_due(&self, now: DateTime<Utc>) -> bool {
+        let Ok(mut earliest) = self.earliest.lock() else {
+            return false;
+        };
+        earliest.take_if(|at| *at <= now).is_some()
+    }
+
+    /// Returns the time of the earliest wakeup
+    fn earliest(&self) -> Option<DateTime<Utc>> {
+        *self.earliest.lock().ok()?
     }
 }
 
 impl<'a> Subscription<'a> {
     pub async fn wait_for(self, period: Duration) {
+        let period = match self.poll_interval {
+            Some(interval) => period.min(interval),
+            None => period,
+        };
         trace!("⌛Waiting for the tasks table to change for {period:?}");
-        match timeout(period, self.0).await {
-            Ok(_) => trace!("⚡The tasks table has changed"),
-            Err(_) => trace!("⏰The waiting timeout has expired"),
+        tokio::select! {
+            result = timeout(period, self.notified) => match result {
+                Ok(_) => trace!("⚡The tasks table has changed"),
+                Err(_) => trace!("⏰The waiting timeout has expired"),
+            },
+            _ = self.stop_worker.cancelled() => (),
         }
     }
 
     pub async fn wait_forever(self) {
+        if let Some(interval) = self.poll_interval {
+            return self.wait_for(interval).await;
+        }
         trace!("⌛Waiting for the tasks table to change");
-        self.0.await;
-        trace!("⚡The tasks table has changed");
+        tokio::select! {
+            _ = self.notified => trace!("⚡The tasks table has changed"),
+            _ = self.stop_worker.cancelled() => (),
+        }
     }
 }
@@ -0,0 +1,78 @@
+use crate::{util::db_error, Result};
+use sqlx::{pool::PoolConnection, PgPool, Postgres};
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// Interval to retry taking a lock held by someone else
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A session-level advisory lock held on a dedicated pool connection. If the
+/// lock isn't released explicitly, e.g. the future holding it is dropped, the
+/// connection is closed instead of being returned to the pool, so the lock
+/// can't leak to other users of the pool.
+pub(crate) struct AdvisoryLock {
+    con: PoolConnection<Postgres>,
+    released: bool,
+}
+
+impl AdvisoryLock {
+    /// Waits for the lock of the key. It's polled instead of waiting on a db
+    /// connection, so waiting doesn't occupy the pool.
+    pub async fn acquire(db: &PgPool, key: &str) -> Result<Self> {
+        Self::acquire_with(
+            db,
+            key,
+            "SELECT pg_try_advisory_lock(hashtextextended($1, 0))",
+        )
+        .await
+    }
+
+    /// Waits for the lock of the key prefixed with the current schema, so the
+    /// internal locks of the tasks in other schemas don't interfere
+    pub async fn acquire_in_schema(db: &PgPool, key: &str) -> Result<Self> {
+        let query = "
+            SELECT pg_try_advisory_lock(
+                hashtextextended(COALESCE(current_schema(), '') || ':' || $1, 0)
+            )
+            ";
+        Self::acquire_with(db, key, query).await
+    }
+
+    async fn acquire_with(db: &PgPool, key: &str, query: &'static str) -> Result<Self> {
+        loop {
+            let mut lock = Self {
+                con: db.acquire().await.map_err(db_error!("acquire"))?,
+                released: false,
+            };
+            let locked: bool = sqlx::query_scalar(query)
+                .bind(key)
+                .fetch_one(&mut *lock.con)
+                .await
+                .map_err(db_error!("lock"))?;
+            if locked {
+                return Ok(lock);
+            }
+            lock.released = true;
+            drop(lock);
+            sleep(LOCK_RETRY_INTERVAL).await;
+        }
+    }
+
+    /// Releases the lock returning the connection to the pool
+    pub async fn release(mut self) -> Result<()> {
+        sqlx::query("SELECT pg_advisory_unlock_all()")
+            .execute(&mut *self.con)
+            .await
+            .map_err(db_error!("unlock"))?;
+        self.released = true;
+        Ok(())
+    }
+}
+
+impl Drop for AdvisoryLock {
+    fn drop(&mut self) {
+        if !self.released {
+            self.con.close_on_drop();
+        }
+    }
+}
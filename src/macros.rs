@@ -1,7 +1,16 @@
-/// Implements enum wrapper for a single task containing all it's steps
+/// Implements enum wrapper for a single task containing all it's steps. The
+/// steps implementing [`Step`](crate::Step) with an application context are
+/// wrapped with its type:
+///
+/// ```rust,ignore
+/// pg_task::task!(Checkout { Charge, Ship }, context: App);
+/// ```
 #[macro_export]
 macro_rules! task {
     ($enum:ident { $($variant:ident),* $(,)? }) => {
+        $crate::task!($enum { $($variant),* }, context: ());
+    };
+    ($enum:ident { $($variant:ident),* $(,)? }, context: $ctx:ty) => {
         #[derive(Debug, serde::Deserialize, serde::Serialize)]
         pub enum $enum {
             $($variant($variant),)*
@@ -16,19 +25,48 @@ macro_rules! task {
         )*
 
         #[async_trait::async_trait]
-        impl $crate::Step<$enum> for $enum {
-            async fn step(self, db: &sqlx::PgPool) -> $crate::StepResult<$enum> {
+        impl $crate::Step<$enum, $ctx> for $enum {
+            async fn step(
+                self,
+                db: &sqlx::PgPool,
+                ctx: &$crate::StepContext<$ctx>,
+            ) -> $crate::StepResult<$enum> {
                 match self {
-                    $(Self::$variant(inner) => inner.step(db).await.map(|next|
+                    $(Self::$variant(inner) => inner.step(db, ctx).await.map(|next|
                         match next {
                             $crate::NextStep::None => $crate::NextStep::None,
+                            $crate::NextStep::Done(r) => $crate::NextStep::Done(r),
                             $crate::NextStep::Now(x) => $crate::NextStep::Now(x.into()),
+                            $crate::NextStep::Many(xs) => $crate::NextStep::Many(xs.into_iter().map(Into::into).collect()),
                             $crate::NextStep::Delayed(x, d) => $crate::NextStep::Delayed(x.into(), d),
+                            $crate::NextStep::Reschedule(t) => $crate::NextStep::Reschedule(t),
+                            $crate::NextStep::DelaySelf(d) => $crate::NextStep::DelaySelf(d),
+                            $crate::NextStep::Join(x) => $crate::NextStep::Join(x.into()),
+                            $crate::NextStep::Call(c, x) => $crate::NextStep::Call(c, x.into()),
+                            $crate::NextStep::WaitSignal(x, t) => $crate::NextStep::WaitSignal(x.into(), t.map(|(d, s)| (d, s.into()))),
                         }
                     ),)*
                 }
             }
 
+            async fn run_compensation(
+                self,
+                db: &sqlx::PgPool,
+                ctx: &$crate::StepContext<$ctx>,
+            ) -> Result<(), $crate::StepError> {
+                use $crate::{ViaCompensate as _, ViaStep as _};
+                match self {
+                    $(Self::$variant(inner) => {
+                        match (&$crate::CompensationProbe::<$enum, $ctx, $variant>::new())
+                            .compensate(inner, db, ctx)
+                        {
+                            Ok(compensation) => compensation.await,
+                            Err(inner) => inner.run_compensation(db, ctx).await,
+                        }
+                    })*
+                }
+            }
+
             fn retry_limit(&self) -> i32 {
                 match self {
                     $(Self::$variant(inner) => inner.retry_limit(),)*
@@ -40,15 +78,183 @@ macro_rules! task {
                     $(Self::$variant(inner) => inner.retry_delay(),)*
                 }
             }
+
+            fn retry_strategy(&self) -> $crate::RetryStrategy {
+                match self {
+                    $(Self::$variant(inner) => inner.retry_strategy(),)*
+                }
+            }
+
+            fn retry_jitter(&self) -> f32 {
+                match self {
+                    $(Self::$variant(inner) => inner.retry_jitter(),)*
+                }
+            }
+
+            fn timeout(&self) -> Option<std::time::Duration> {
+                match self {
+                    $(Self::$variant(inner) => inner.timeout(),)*
+                }
+            }
+
+            fn max_concurrency(&self) -> Option<usize> {
+                match self {
+                    $(Self::$variant(inner) => inner.max_concurrency(),)*
+                }
+            }
+
+            fn is_singleton(&self) -> bool {
+                match self {
+                    $(Self::$variant(inner) => inner.is_singleton(),)*
+                }
+            }
+
+            fn rate_limit(&self) -> Option<f64> {
+                match self {
+                    $(Self::$variant(inner) => inner.rate_limit(),)*
+                }
+            }
+
+            fn has_compensation(&self) -> bool {
+                use $crate::{ViaCompensate as _, ViaStep as _};
+                match self {
+                    $(Self::$variant(inner) => {
+                        (&$crate::CompensationProbe::<$enum, $ctx, $variant>::new()).is_compensated()
+                            || inner.has_compensation()
+                    })*
+                }
+            }
+
+            fn step_type(&self) -> &'static str {
+                match self {
+                    $(Self::$variant(inner) => inner.step_type(),)*
+                }
+            }
         }
     }
 }
 
-/// The macro implements the outer enum wrapper containing all the tasks
+/// The macro implements the outer enum wrapper containing all the tasks, the
+/// tasks with an application context are wrapped with its type the same way
+/// as by [`task!`](crate::task)
 #[macro_export]
 macro_rules! scheduler {
-    ($enum:ident { $($variant:ident),* $(,)? }) => {
-        $crate::task!($enum { $($variant),* });
-        impl $crate::Scheduler for $enum {}
+    ($enum:ident { $($variant:ident),* $(,)? } $(, context: $ctx:ty)?) => {
+        $crate::task!($enum { $($variant),* } $(, context: $ctx)?);
+        impl $crate::Scheduler for $enum {
+            fn task_names() -> Option<Vec<String>> {
+                Some(vec![$(stringify!($variant).into()),*])
+            }
+        }
     }
 }
+
+/// Implements a linear task with each step going to the next one right away.
+/// The steps implement [`ChainStep`](crate::ChainStep) instead of
+/// [`Step`](crate::Step):
+///
+/// ```rust,ignore
+/// pg_task::chain!(Signup: CreateAccount -> SendWelcome -> NotifyAdmin);
+/// ```
+#[macro_export]
+macro_rules! chain {
+    ($enum:ident: $($step:ident)->+ $(, context: $ctx:ty)?) => {
+        $crate::task!($enum { $($step),+ } $(, context: $ctx)?);
+        $crate::chain!(@step $enum: $($step)->+);
+    };
+    (@step $enum:ident: $step:ident -> $next:ident $(-> $rest:ident)*) => {
+        #[async_trait::async_trait]
+        impl<C> $crate::Step<$enum, C> for $step
+        where
+            C: Clone + Send + Sync + 'static,
+            $step: $crate::ChainStep<C>,
+        {
+            async fn step(
+                self,
+                db: &sqlx::PgPool,
+                ctx: &$crate::StepContext<C>,
+            ) -> $crate::StepResult<$enum> {
+                let next: $next = $crate::ChainStep::run(self, db, ctx).await?;
+                $crate::NextStep::now(next)
+            }
+        }
+        $crate::chain!(@step $enum: $next $(-> $rest)*);
+    };
+    (@step $enum:ident: $step:ident) => {
+        #[async_trait::async_trait]
+        impl<C> $crate::Step<$enum, C> for $step
+        where
+            C: Clone + Send + Sync + 'static,
+            $step: $crate::ChainStep<C>,
+        {
+            async fn step(
+                self,
+                db: &sqlx::PgPool,
+                ctx: &$crate::StepContext<C>,
+            ) -> $crate::StepResult<$enum> {
+                let () = $crate::ChainStep::run(self, db, ctx).await?;
+                $crate::NextStep::none()
+            }
+        }
+    };
+}
+
+/// Implements [`Step`](crate::Step) for the steps of the task implementing
+/// [`BlockingStep`](crate::BlockingStep), so they're run on the blocking
+/// threads pool:
+///
+/// ```rust,ignore
+/// pg_task::task!(Report { Fetch, Render, Send });
+/// pg_task::blocking_step!(Report: Render);
+/// ```
+#[macro_export]
+macro_rules! blocking_step {
+    ($enum:ident: $($step:ident),+ $(,)?) => {
+        $(
+            #[async_trait::async_trait]
+            impl<C> $crate::Step<$enum, C> for $step
+            where
+                C: Clone + Send + Sync + 'static,
+                $step: $crate::BlockingStep<$enum, C>,
+            {
+                async fn step(
+                    self,
+                    db: &sqlx::PgPool,
+                    ctx: &$crate::StepContext<C>,
+                ) -> $crate::StepResult<$enum> {
+                    $crate::run_blocking(self, db, ctx).await
+                }
+            }
+        )+
+    };
+}
+
+/// Implements [`Step`](crate::Step) for the steps of the task implementing
+/// [`ChunkedStep`](crate::ChunkedStep), so each run processes a chunk and
+/// reschedules the step:
+///
+/// ```rust,ignore
+/// pg_task::task!(Backfill { ReindexUsers, Notify });
+/// pg_task::chunked_step!(Backfill: ReindexUsers);
+/// ```
+#[macro_export]
+macro_rules! chunked_step {
+    ($enum:ident: $($step:ident),+ $(,)?) => {
+        $(
+            #[async_trait::async_trait]
+            impl<C> $crate::Step<$enum, C> for $step
+            where
+                C: Clone + Send + Sync + 'static,
+                $step: $crate::ChunkedStep<$enum, C>,
+            {
+                async fn step(
+                    self,
+                    db: &sqlx::PgPool,
+                    ctx: &$crate::StepContext<C>,
+                ) -> $crate::StepResult<$enum> {
+                    $crate::run_chunk(self, db, ctx).await
+                }
+            }
+        )+
+    };
+}
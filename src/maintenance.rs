@@ -0,0 +1,58 @@
+use chrono::{DateTime, Days, NaiveTime, Utc};
+
+/// A daily time range when a worker doesn't claim tasks, e.g. during the
+/// nightly db maintenance, see
+/// [`Worker::with_maintenance_window`](crate::Worker::with_maintenance_window).
+/// It applies to all the steps unless it's limited with [`Self::for_steps`].
+#[derive(Clone, Debug)]
+pub struct MaintenanceWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+    steps: Option<Vec<String>>,
+}
+
+impl MaintenanceWindow {
+    /// Creates a window from `start` to `end` UTC each day, it spans midnight
+    /// if `end` is before `start`
+    pub fn daily(start: NaiveTime, end: NaiveTime) -> Self {
+        Self {
+            start,
+            end,
+            steps: None,
+        }
+    }
+
+    /// Limits the window to the steps, i.e. the variants of the task enums
+    pub fn for_steps(mut self, steps: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.steps = Some(steps.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Returns the steps of the window, `None` if it applies to all of them
+    pub(crate) fn steps(&self) -> Option<&[String]> {
+        self.steps.as_deref()
+    }
+
+    /// Returns true if the window is open at the time
+    pub(crate) fn is_active(&self, now: DateTime<Utc>) -> bool {
+        let time = now.time();
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+
+    /// Returns the time the window opens or closes next after `now`
+    pub(crate) fn next_change(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let next = |time: NaiveTime| {
+            let at = now.date_naive().and_time(time).and_utc();
+            if at > now {
+                at
+            } else {
+                at.checked_add_days(Days::new(1)).unwrap_or(at)
+            }
+        };
+        next(self.start).min(next(self.end))
+    }
+}
@@ -0,0 +1,15 @@
+use crate::{StepResult, TaskInfo};
+use async_trait::async_trait;
+
+/// Hooks around running steps registered with
+/// [`Worker::with_middleware`](crate::Worker::with_middleware), e.g. for
+/// custom logging, metrics or error reporting. `T` is the enum passed to the
+/// worker.
+#[async_trait]
+pub trait StepMiddleware<T>: Send + Sync {
+    /// Runs before the step, the task is in the state it was fetched to run
+    async fn before(&self, _task: &TaskInfo) {}
+
+    /// Runs after the step with its result, before the result is saved
+    async fn after(&self, _task: &TaskInfo, _result: &StepResult<T>) {}
+}
@@ -0,0 +1,120 @@
+use crate::{util::db_error, Error, Result};
+use sqlx::{migrate::Migrator, postgres::PgTransaction, PgPool};
+use tracing::{debug, info};
+
+/// The crate schema migrations from the `migrations` folder
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// The last migration of the schema the crate shipped before tracking the
+/// applied versions, the deployments created from it have the `pg_task`
+/// table without the versions
+const BASELINE_VERSION: i64 = 20240630005452;
+
+/// An arbitrary key of the advisory lock to apply migrations one at a time
+const MIGRATION_LOCK: i64 = 0x0070_675f_7461_736b; // "pg_task"
+
+/// Creates or upgrades the `pg_task` table and all the related db objects.
+///
+/// It's safe to call on each start of the app, including several instances
+/// at once. Applied versions are tracked in the `pg_task_schema_version`
+/// table. A `pg_task` table created before the versions were tracked is
+/// upgraded from the versions in the `sqlx migrate` history if there's one,
+/// or from the baseline schema otherwise.
+pub async fn migrate(db: &PgPool) -> Result<()> {
+    let mut tx = db.begin().await.map_err(db_error!("begin"))?;
+
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(MIGRATION_LOCK)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error!("lock"))?;
+
+    sqlx::query(
+        "
+        CREATE TABLE IF NOT EXISTS pg_task_schema_version (
+            version BIGINT PRIMARY KEY,
+            description TEXT NOT NULL,
+            applied_at timestamptz NOT NULL DEFAULT now()
+        )
+        ",
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error!("create version table"))?;
+
+    let mut applied: Vec<i64> = sqlx::query_scalar("SELECT version FROM pg_task_schema_version")
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(db_error!("applied versions"))?;
+    if applied.is_empty() {
+        applied = adopt_existing_schema(&mut tx).await?;
+    }
+
+    for migration in MIGRATOR.iter() {
+        if applied.contains(&migration.version) {
+            continue;
+        }
+        debug!(
+            "Applying migration {} {}",
+            migration.version, migration.description
+        );
+        sqlx::raw_sql(&migration.sql)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| Error::Migrate(e, migration.version))?;
+        sqlx::query("INSERT INTO pg_task_schema_version (version, description) VALUES ($1, $2)")
+            .bind(migration.version)
+            .bind(&*migration.description)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| Error::Migrate(e, migration.version))?;
+        info!(
+            "Applied migration {} {}",
+            migration.version, migration.description
+        );
+    }
+
+    tx.commit().await.map_err(db_error!("commit"))
+}
+
+/// Records the versions applied to a `pg_task` table created before the
+/// versions were tracked, returns them. They are taken from the `sqlx migrate`
+/// history if there's one, otherwise the table is considered created from the
+/// baseline schema.
+async fn adopt_existing_schema(tx: &mut PgTransaction<'_>) -> Result<Vec<i64>> {
+    let (has_table, has_sqlx_history): (bool, bool) = sqlx::query_as(
+        "SELECT to_regclass('pg_task') IS NOT NULL, to_regclass('_sqlx_migrations') IS NOT NULL",
+    )
+    .fetch_one(&mut **tx)
+    .await
+    .map_err(db_error!("existing schema"))?;
+    if !has_table {
+        return Ok(Vec::new());
+    }
+
+    let versions: Vec<i64> = if has_sqlx_history {
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+            .fetch_all(&mut **tx)
+            .await
+            .map_err(db_error!("sqlx migrations"))?
+    } else {
+        MIGRATOR
+            .iter()
+            .map(|m| m.version)
+            .filter(|&v| v <= BASELINE_VERSION)
+            .collect()
+    };
+    for migration in MIGRATOR.iter().filter(|m| versions.contains(&m.version)) {
+        sqlx::query("INSERT INTO pg_task_schema_version (version, description) VALUES ($1, $2)")
+            .bind(migration.version)
+            .bind(&*migration.description)
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| Error::Migrate(e, migration.version))?;
+    }
+    info!(
+        "Adopted the existing pg_task schema with {} applied migrations",
+        versions.len()
+    );
+    Ok(versions)
+}
@@ -1,29 +1,145 @@
-use crate::StepResult;
+use crate::{
+    payload::{self, Serialized},
+    Error, Scheduler, StepResult,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
 use std::time::Duration;
 
 /// Represents next step of the task
 pub enum NextStep<T> {
     /// The task is done
     None,
+    /// The task is done with the serialized result
+    Done(serde_json::Value),
     /// Run the next step immediately
     Now(T),
+    /// The task is done, each of the steps is run as a new task
+    Many(Vec<T>),
     /// Delay the next step
     Delayed(T, Duration),
+    /// Run the current step again at the time
+    Reschedule(DateTime<Utc>),
+    /// Run the current step again after the delay
+    DelaySelf(Duration),
+    /// Run the next step after all the children of the task are finished
+    Join(T),
+    /// Run the sub-task as a child to completion, then the next step
+    Call(SubTask, T),
+    /// Run the next step after a signal is received, or the timeout step if
+    /// there's no signal within the timeout
+    WaitSignal(T, Option<(Duration, T)>),
+}
+
+/// A task run as a sub-machine of the calling task, see [`NextStep::call`]
+pub struct SubTask {
+    pub(crate) step: Serialized,
+    pub(crate) payload_version: i32,
 }
 
 impl<T> NextStep<T> {
+    /// Converts the steps of the transition
+    pub(crate) fn map<U>(self, f: impl Fn(T) -> U) -> NextStep<U> {
+        match self {
+            Self::None => NextStep::None,
+            Self::Done(result) => NextStep::Done(result),
+            Self::Now(step) => NextStep::Now(f(step)),
+            Self::Many(steps) => NextStep::Many(steps.into_iter().map(f).collect()),
+            Self::Delayed(step, delay) => NextStep::Delayed(f(step), delay),
+            Self::Reschedule(at) => NextStep::Reschedule(at),
+            Self::DelaySelf(delay) => NextStep::DelaySelf(delay),
+            Self::Join(step) => NextStep::Join(f(step)),
+            Self::Call(sub_task, step) => NextStep::Call(sub_task, f(step)),
+            Self::WaitSignal(step, timeout) => {
+                NextStep::WaitSignal(f(step), timeout.map(|(d, s)| (d, f(s))))
+            }
+        }
+    }
+
     /// The task is done
     pub fn none() -> StepResult<T> {
         Ok(Self::None)
     }
 
+    /// The task is done with the result, e.g. a generated file URL. The task
+    /// is kept as done to get the result using [`result`](crate::result)
+    /// until it's removed by [`cleanup`](crate::cleanup).
+    pub fn done_with(result: impl Serialize) -> StepResult<T> {
+        let result = serde_json::to_value(result).map_err(Error::SerializeResult)?;
+        Ok(Self::Done(result))
+    }
+
     /// Run the next step immediately
     pub fn now(step: impl Into<T>) -> StepResult<T> {
         Ok(Self::Now(step.into()))
     }
 
+    /// The task is done, and each of the steps is enqueued as a new task to
+    /// run immediately, e.g. for a step discovering work to explode it into
+    /// parallel tasks. The tasks are enqueued in the same transaction the
+    /// current one is completed in.
+    pub fn many(steps: impl IntoIterator<Item = impl Into<T>>) -> StepResult<T> {
+        Ok(Self::Many(steps.into_iter().map(Into::into).collect()))
+    }
+
     /// Delay the next step
     pub fn delay(step: impl Into<T>, delay: Duration) -> StepResult<T> {
         Ok(Self::Delayed(step.into(), delay))
     }
+
+    /// Run the current step again at the time, e.g. to poll a bank settlement
+    /// until it's ready. Unlike returning an error it isn't counted as a
+    /// failed attempt, so the retry limit doesn't apply.
+    pub fn retry_at(at: DateTime<Utc>) -> StepResult<T> {
+        Ok(Self::Reschedule(at))
+    }
+
+    /// Run the current step again after the delay, see [`Self::retry_at`]
+    pub fn delay_self(delay: Duration) -> StepResult<T> {
+        Ok(Self::DelaySelf(delay))
+    }
+
+    /// Run the next step after all the children spawned by
+    /// [`StepContext::spawn_child`](crate::StepContext::spawn_child) are
+    /// finished, i.e. completed, failed after all the retries or cancelled.
+    /// The next step can get their outcomes using
+    /// [`StepContext::children`](crate::StepContext::children).
+    pub fn join(step: impl Into<T>) -> StepResult<T> {
+        Ok(Self::Join(step.into()))
+    }
+
+    /// Run the task to completion as a sub-machine, then the next step, e.g. to
+    /// reuse a `ChargeCard` flow across pipelines instead of flattening its
+    /// steps into each of them. The sub-task is spawned as a child, so it's
+    /// cancelled along with the calling task. The next step is run the same
+    /// way as after [`Self::join`], i.e. even if the sub-task fails, and can
+    /// get its result or error using
+    /// [`StepContext::children`](crate::StepContext::children).
+    pub fn call<S: Scheduler>(task: &S, step: impl Into<T>) -> StepResult<T> {
+        let sub_task = SubTask {
+            step: payload::serialize_with_names(task, S::CODEC)?,
+            payload_version: S::PAYLOAD_VERSION,
+        };
+        Ok(Self::Call(sub_task, step.into()))
+    }
+
+    /// Run the next step after a [`signal`](crate::signal) is received, e.g.
+    /// a webhook or a human approval. The next step can get the signal payload
+    /// using [`StepContext::signal`](crate::StepContext::signal).
+    pub fn wait_signal(step: impl Into<T>) -> StepResult<T> {
+        Ok(Self::WaitSignal(step.into(), None))
+    }
+
+    /// Run the next step after a [`signal`](crate::signal) is received, or
+    /// the `timeout_step` if there's no signal within the `timeout`
+    pub fn wait_signal_with_timeout(
+        step: impl Into<T>,
+        timeout: Duration,
+        timeout_step: impl Into<T>,
+    ) -> StepResult<T> {
+        Ok(Self::WaitSignal(
+            step.into(),
+            Some((timeout, timeout_step.into())),
+        ))
+    }
 }
@@ -0,0 +1,113 @@
+use async_trait::async_trait;
+use sqlx::types::Uuid;
+use std::sync::Arc;
+use tracing::error;
+
+/// A task failed after all the retry attempts
+#[derive(Clone, Debug)]
+pub struct TaskFailure {
+    /// Task id
+    pub id: Uuid,
+    /// Name of the task, i.e. the variant of the enum passed to the worker
+    pub task_name: String,
+    /// The failed step as it's stored in the table
+    pub step: serde_json::Value,
+    /// Number of times the step was tried
+    pub tried: i32,
+    /// The error message
+    pub error: String,
+}
+
+/// Gets notified about failed tasks, registered with
+/// [`Worker::with_failure_notifier`](crate::Worker::with_failure_notifier),
+/// e.g. to fire an alert. Notifiers run in the background after the failure is
+/// saved, so a slow one doesn't hold the worker.
+#[async_trait]
+pub trait FailureNotifier: Send + Sync {
+    /// Handles the failure
+    async fn notify(&self, failure: &TaskFailure);
+}
+
+/// Logs failures as errors with the `pg_task::failure` target, so they could
+/// be routed to an alerting subscriber
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TracingNotifier;
+
+#[async_trait]
+impl FailureNotifier for TracingNotifier {
+    async fn notify(&self, failure: &TaskFailure) {
+        error!(
+            target: "pg_task::failure",
+            id = %failure.id,
+            task_name = %failure.task_name,
+            step = %failure.step,
+            tried = failure.tried,
+            "task failed: {}",
+            failure.error
+        );
+    }
+}
+
+/// Posts failures as JSON to the url, enabled by the `webhook` feature:
+///
+/// ```json
+/// {"id": "...", "task_name": "...", "step": {...}, "tried": 3, "error": "..."}
+/// ```
+#[cfg(feature = "webhook")]
+#[derive(Clone, Debug)]
+pub struct WebhookNotifier {
+    client: reqwest::Client,
+    url: String,
+}
+
+#[cfg(feature = "webhook")]
+impl WebhookNotifier {
+    /// Creates a notifier posting to the url
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: url.into(),
+        }
+    }
+}
+
+#[cfg(feature = "webhook")]
+#[async_trait]
+impl FailureNotifier for WebhookNotifier {
+    async fn notify(&self, failure: &TaskFailure) {
+        let body = serde_json::json!({
+            "id": failure.id.to_string(),
+            "task_name": failure.task_name,
+            "step": failure.step,
+            "tried": failure.tried,
+            "error": failure.error,
+        });
+        let sent = self
+            .client
+            .post(&self.url)
+            .json(&body)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status);
+        if let Err(e) = sent {
+            tracing::warn!(
+                "[{}] can't post the failure to the webhook: {}",
+                failure.id,
+                source_chain::to_string(&e)
+            );
+        }
+    }
+}
+
+/// Runs the notifiers in the background
+pub(crate) fn notify_failure(notifiers: &[Arc<dyn FailureNotifier>], failure: TaskFailure) {
+    if notifiers.is_empty() {
+        return;
+    }
+    let failure = Arc::new(failure);
+    for notifier in notifiers {
+        let notifier = notifier.clone();
+        let failure = failure.clone();
+        tokio::spawn(async move { notifier.notify(&failure).await });
+    }
+}
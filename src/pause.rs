@@ -0,0 +1,64 @@
+use crate::{util::db_error, Result};
+use sqlx::{types::Uuid, PgExecutor};
+
+/// Pauses the task keeping its state. If the task is running, its current
+/// step is finished, but the next one isn't run until the task is resumed.
+/// Returns `false` if there's no such task.
+pub async fn pause<'e>(db: impl PgExecutor<'e>, id: Uuid) -> Result<bool> {
+    set_paused(db, id, true).await
+}
+
+/// Resumes the paused task. Returns `false` if there's no such task.
+pub async fn resume<'e>(db: impl PgExecutor<'e>, id: Uuid) -> Result<bool> {
+    set_paused(db, id, false).await
+}
+
+async fn set_paused<'e>(db: impl PgExecutor<'e>, id: Uuid, is_paused: bool) -> Result<bool> {
+    let updated = sqlx::query!(
+        "UPDATE pg_task SET is_paused = $2 WHERE id = $1",
+        id,
+        is_paused
+    )
+    .execute(db)
+    .await
+    .map_err(db_error!())?
+    .rows_affected();
+    Ok(updated > 0)
+}
+
+/// Pauses the whole queue, e.g. during an incident or a migration. Workers
+/// keep running, but they don't claim tasks until [`resume_all`], the steps
+/// running at the moment are finished.
+pub async fn pause_all<'e>(db: impl PgExecutor<'e>) -> Result<()> {
+    sqlx::query!(
+        "
+        UPDATE pg_task_control
+        SET is_paused = true,
+            paused_at = COALESCE(paused_at, now())
+        "
+    )
+    .execute(db)
+    .await
+    .map_err(db_error!())?;
+    Ok(())
+}
+
+/// Resumes the queue paused with [`pause_all`]
+pub async fn resume_all<'e>(db: impl PgExecutor<'e>) -> Result<()> {
+    // The control table has no trigger, so workers waiting for the tasks
+    // table changes are notified explicitly
+    sqlx::query!(
+        "
+        WITH resumed AS (
+            UPDATE pg_task_control
+            SET is_paused = false,
+                paused_at = NULL
+        )
+        SELECT pg_notify('pg_task_changed', current_schema())
+        "
+    )
+    .execute(db)
+    .await
+    .map_err(db_error!())?;
+    Ok(())
+}
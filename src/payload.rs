@@ -0,0 +1,199 @@
+use crate::{codec, Codec, Error, Json, Result, StepError};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::{fmt, sync::OnceLock};
+
+static CODEC: OnceLock<Box<dyn PayloadCodec>> = OnceLock::new();
+
+/// Transforms serialized steps before storing them into the table and back
+/// after fetching, e.g. to encrypt personal data
+pub trait PayloadCodec: Send + Sync + 'static {
+    /// Transforms a serialized step before storing it
+    fn encode(&self, payload: Value) -> std::result::Result<Value, StepError>;
+
+    /// Reverts the [`Self::encode`] transformation of a fetched step
+    fn decode(&self, payload: Value) -> std::result::Result<Value, StepError>;
+}
+
+/// Sets the codec for all the steps serialized in the process. It should be
+/// called once before scheduling or running any tasks.
+pub fn set_payload_codec(codec: impl PayloadCodec) -> Result<()> {
+    CODEC
+        .set(Box::new(codec))
+        .map_err(|_| Error::PayloadCodecAlreadySet)
+}
+
+/// A step serialized into the `step` and `step_bytes` column values along
+/// with its names
+#[derive(Clone)]
+pub struct Serialized {
+    /// The `step` column value
+    pub value: Value,
+    /// The `step_bytes` column value, it's set for the steps encoded with a
+    /// binary [`Codec`]
+    pub bytes: Option<Vec<u8>>,
+    /// Name of the task, i.e. the variant of the outer enum
+    pub task_name: String,
+    /// Name of the step, i.e. the variant of the task enum
+    pub step_name: String,
+}
+
+/// Serializes a step into the `step` and `step_bytes` column values, also
+/// returns the names of the task and step enum variants
+pub fn serialize_with_names(
+    step: &(impl Serialize + fmt::Debug),
+    codec: &dyn Codec,
+) -> Result<Serialized> {
+    let value =
+        serde_json::to_value(step).map_err(|e| Error::SerializeStep(e, format!("{step:?}")))?;
+    let (task_name, step_name) = variant_names(&value);
+    let (value, bytes) = encode(value, codec)?;
+    Ok(Serialized {
+        value,
+        bytes,
+        task_name,
+        step_name,
+    })
+}
+
+/// Transforms a serialized step into the `step` and `step_bytes` column
+/// values, it's reverted by [`decode`]. The bytes of a binary codec are kept
+/// base64 encoded in the `step` value if there's a [`PayloadCodec`] to
+/// transform them.
+pub fn encode(value: Value, codec: &dyn Codec) -> Result<(Value, Option<Vec<u8>>)> {
+    let Some(payload_codec) = CODEC.get() else {
+        let (value, bytes) = codec::wrap(value, codec).map_err(Error::EncodePayload)?;
+        #[cfg(feature = "compression")]
+        let value = compression::compress(value);
+        return Ok((value, bytes));
+    };
+    let value = codec::wrap_json(value, codec).map_err(Error::EncodePayload)?;
+    #[cfg(feature = "compression")]
+    let value = compression::compress(value);
+    let value = payload_codec.encode(value).map_err(Error::EncodePayload)?;
+    Ok((value, None))
+}
+
+/// Returns the variant name of a serialized enum, it's empty for other types
+fn variant_name(value: &Value) -> String {
+    match value {
+        Value::Object(map) if map.len() == 1 => map.keys().next().cloned().unwrap_or_default(),
+        Value::String(name) => name.clone(),
+        _ => String::new(),
+    }
+}
+
+/// Returns the names of the task and step enum variants of a decoded step
+pub fn variant_names(value: &Value) -> (String, String) {
+    let task_name = variant_name(value);
+    let step_name = value.get(&task_name).map(variant_name).unwrap_or_default();
+    (task_name, step_name)
+}
+
+/// Returns the name of the step stored in the `step` and `step_bytes`
+/// columns, e.g. `Task::Step`
+pub fn step_name(value: &Value, bytes: Option<&[u8]>) -> Option<String> {
+    let value = decode(value, bytes, &Json).ok()?;
+    let task = variant_name(&value);
+    let step = value.get(&task).map(variant_name).unwrap_or_default();
+    match (task.is_empty(), step.is_empty()) {
+        (true, _) => None,
+        (false, true) => Some(task),
+        (false, false) => Some(format!("{task}::{step}")),
+    }
+}
+
+/// Returns the name of the step enum variant stored in the `step` and
+/// `step_bytes` columns, it's empty if the step can't be decoded
+pub fn step_variant_name(value: &Value, bytes: Option<&[u8]>, codec: &dyn Codec) -> String {
+    let Ok(value) = decode(value, bytes, codec) else {
+        return String::new();
+    };
+    value
+        .get(variant_name(&value))
+        .map(variant_name)
+        .unwrap_or_default()
+}
+
+/// Deserializes a step from the `step` and `step_bytes` column values
+pub fn deserialize<T: DeserializeOwned>(
+    value: &Value,
+    bytes: Option<&[u8]>,
+    codec: &dyn Codec,
+) -> Result<T> {
+    deserialize_decoded(&decode(value, bytes, codec)?)
+}
+
+/// Deserializes a step decoded with [`decode`]
+pub fn deserialize_decoded<T: DeserializeOwned>(value: &Value) -> Result<T> {
+    T::deserialize(value).map_err(|e| Error::DeserializeStep(e, value.to_string()))
+}
+
+/// Reverts the transformations of the `step` and `step_bytes` column values
+/// made on serialization, returning the serialized step as is. Steps encoded
+/// with built-in codecs are decoded regardless of the given one.
+pub fn decode(value: &Value, bytes: Option<&[u8]>, codec: &dyn Codec) -> Result<Value> {
+    if let Some(bytes) = bytes {
+        return codec::unwrap_bytes(value, bytes, codec).map_err(Error::DecodePayload);
+    }
+    let value = match CODEC.get() {
+        Some(codec) => codec.decode(value.clone()).map_err(Error::DecodePayload)?,
+        None => value.clone(),
+    };
+    #[cfg(feature = "compression")]
+    let value = compression::decompress(&value)
+        .map_err(|e| Error::DeserializeStep(e, value.to_string()))?;
+    codec::unwrap(value, codec).map_err(Error::DecodePayload)
+}
+
+#[cfg(feature = "compression")]
+mod compression {
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+    use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+    use serde::de::Error as _;
+    use serde_json::{json, Value};
+    use std::io::{Read, Write};
+
+    /// Steps serialized into a larger JSON are compressed
+    const COMPRESSION_THRESHOLD: usize = 64 * 1024;
+
+    /// The key of the JSON object wrapping a compressed step
+    const COMPRESSED_KEY: &str = "pg_task_gzip";
+
+    /// Compresses the value if it's larger than the threshold
+    pub fn compress(value: Value) -> Value {
+        let json = value.to_string();
+        if json.len() <= COMPRESSION_THRESHOLD {
+            return value;
+        }
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        let compressed = encoder
+            .write_all(json.as_bytes())
+            .and_then(|_| encoder.finish());
+        match compressed {
+            Ok(bytes) => json!({ COMPRESSED_KEY: BASE64.encode(bytes) }),
+            Err(e) => {
+                tracing::warn!("Can't compress a step, storing it as is: {e}");
+                value
+            }
+        }
+    }
+
+    /// Decompresses the value if it's compressed, otherwise returns it as is
+    pub fn decompress(value: &Value) -> Result<Value, serde_json::Error> {
+        let Some(encoded) = value
+            .as_object()
+            .filter(|o| o.len() == 1)
+            .and_then(|o| o.get(COMPRESSED_KEY))
+            .and_then(Value::as_str)
+        else {
+            return Ok(value.clone());
+        };
+        let compressed = BASE64.decode(encoded).map_err(serde_json::Error::custom)?;
+        let mut json = Vec::new();
+        GzDecoder::new(&compressed[..])
+            .read_to_end(&mut json)
+            .map_err(serde_json::Error::custom)?;
+        serde_json::from_slice(&json)
+    }
+}
@@ -0,0 +1,165 @@
+use crate::{util::db_error, Result};
+use sqlx::PgPool;
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+/// A token bucket allowing up to `rate` actions per second with bursts of up
+/// to a second worth of them
+pub struct RateLimiter {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64) -> Self {
+        let capacity = rate.max(1.0);
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// Waits until an action is allowed and takes a token for it
+    pub async fn acquire(&mut self) {
+        self.refill();
+        if self.tokens < 1.0 {
+            sleep(Duration::from_secs_f64((1.0 - self.tokens) / self.rate)).await;
+            self.refill();
+        }
+        self.tokens -= 1.0;
+    }
+
+    /// Takes a token if an action is allowed right away
+    pub fn try_acquire(&mut self) -> bool {
+        self.refill();
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    /// Returns the tokens taken for the actions which didn't happen
+    pub fn refund(&mut self, tokens: usize) {
+        self.tokens = (self.tokens + tokens as f64).min(self.capacity);
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.refilled_at = now;
+    }
+}
+
+/// Waits for a token of the step type bucket shared by all the workers, see
+/// [`Step::RATE_LIMIT`](crate::Step::RATE_LIMIT). The token is taken with a
+/// single atomic update, it could be taken ahead leaving the bucket negative
+/// by up to its capacity, then the step waits until the bucket refills, so
+/// the waiting steps keep their order without polling the db. Once the debt
+/// is at its limit, the steps poll for the bucket to refill. Returns `false`
+/// without a token if the `cancellation` token is cancelled meanwhile, the
+/// token taken ahead is returned to the bucket in this case.
+pub(crate) async fn acquire_shared(
+    db: &PgPool,
+    step_type: &str,
+    rate: f64,
+    cancellation: &CancellationToken,
+) -> Result<bool> {
+    let capacity = rate.max(1.0);
+    loop {
+        let bucket = take_token(db, step_type, rate, capacity).await?;
+        if bucket.taken && bucket.tokens >= 0.0 {
+            return Ok(true);
+        }
+        // A taken token waits for the bucket to refill to zero, otherwise
+        // the step waits for a token to be taken without exceeding the debt
+        let missing = if bucket.taken {
+            -bucket.tokens
+        } else {
+            1.0 - capacity - bucket.tokens
+        };
+        tokio::select! {
+            () = sleep(Duration::from_secs_f64(missing.max(0.0) / rate)) => (),
+            () = cancellation.cancelled() => {
+                if bucket.taken {
+                    refund_token(db, step_type, capacity).await;
+                }
+                return Ok(false);
+            }
+        }
+        if bucket.taken {
+            return Ok(true);
+        }
+    }
+}
+
+/// The state of a shared bucket after taking a token
+struct Bucket {
+    /// If the token was taken, it isn't once the debt reaches the capacity
+    taken: bool,
+    /// Tokens left in the bucket after taking the token
+    tokens: f64,
+}
+
+/// Refills the shared bucket and takes a token unless the bucket is in debt
+/// of its whole capacity
+async fn take_token(db: &PgPool, step_type: &str, rate: f64, capacity: f64) -> Result<Bucket> {
+    let (taken, tokens): (bool, f64) = sqlx::query_as(
+        "
+        WITH inserted AS (
+            INSERT INTO pg_task_rate_limit (step_type, tokens, refilled_at)
+            VALUES ($1, $2 - 1, now())
+            ON CONFLICT (step_type) DO NOTHING
+        ),
+        refilled AS (
+            SELECT LEAST(
+                $2,
+                tokens + $3 * GREATEST(EXTRACT(EPOCH FROM now() - refilled_at)::FLOAT8, 0)
+            ) AS tokens
+            FROM pg_task_rate_limit
+            WHERE step_type = $1
+            FOR UPDATE
+        )
+        UPDATE pg_task_rate_limit
+        SET tokens = refilled.tokens - CASE WHEN refilled.tokens - 1 >= -$2 THEN 1 ELSE 0 END,
+            refilled_at = GREATEST(refilled_at, now())
+        FROM refilled
+        WHERE step_type = $1
+        RETURNING refilled.tokens - 1 >= -$2, pg_task_rate_limit.tokens
+        ",
+    )
+    .bind(step_type)
+    .bind(capacity)
+    .bind(rate)
+    .fetch_optional(db)
+    .await
+    .map_err(db_error!("rate limit"))?
+    // The bucket is just created by the insert with the token taken, the
+    // inserted row isn't visible to the other parts of the statement
+    .unwrap_or((true, capacity - 1.0));
+    Ok(Bucket { taken, tokens })
+}
+
+/// Returns the token taken ahead to the shared bucket
+async fn refund_token(db: &PgPool, step_type: &str, capacity: f64) {
+    if let Err(e) = sqlx::query(
+        "UPDATE pg_task_rate_limit SET tokens = LEAST($2, tokens + 1) WHERE step_type = $1",
+    )
+    .bind(step_type)
+    .bind(capacity)
+    .execute(db)
+    .await
+    {
+        warn!(
+            "Can't return a rate limit token of {step_type}: {}",
+            source_chain::to_string(&e)
+        );
+    }
+}
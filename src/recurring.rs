@@ -0,0 +1,613 @@
+use crate::{
+    payload::{self, Serialized},
+    traits::{insert_serialized, InsertOptions},
+    util::{chrono_duration_to_std, db_error},
+    Clock, Error, Result, Scheduler,
+};
+use chrono::{
+    DateTime, Datelike, Duration as ChronoDuration, NaiveDate, NaiveDateTime, Timelike, Utc,
+};
+use sqlx::{Acquire, PgConnection, PgExecutor, PgPool, Postgres};
+use std::{convert::Infallible, sync::Arc, time::Duration};
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+/// How often workers look for the schedules added or changed by other
+/// processes
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A run is missed if it isn't enqueued in this time, e.g. as no worker was
+/// running
+const MISSED_AFTER: ChronoDuration = ChronoDuration::minutes(1);
+
+/// The maximum number of missed runs enqueued with [`CatchUp::RunAll`]
+const MAX_CATCH_UP_RUNS: usize = 100;
+
+/// How far ahead a matching time is looked for, it's more than enough to find
+/// any existing date, e.g. the next Feb 29th on a Monday
+const SEARCH_LIMIT_DAYS: i64 = 366 * 30;
+
+/// What to do with the runs of a [`CronSchedule`] missed while no worker was
+/// running, e.g. over a weekend
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CatchUp {
+    /// Enqueues a single task for all the missed runs right away
+    #[default]
+    RunOnce,
+    /// Enqueues a task for each missed run right away, up to 100 of them,
+    /// e.g. for daily reports each covering its own day
+    RunAll,
+    /// Skips the missed runs and waits for the next one, e.g. for reminders
+    /// that are pointless when late
+    Skip,
+}
+
+impl CatchUp {
+    /// Returns the name stored in the `catch_up` column
+    fn name(self) -> &'static str {
+        match self {
+            Self::RunOnce => "run_once",
+            Self::RunAll => "run_all",
+            Self::Skip => "skip",
+        }
+    }
+
+    /// Parses the name stored in the `catch_up` column
+    fn from_name(name: &str) -> Self {
+        match name {
+            "run_all" => Self::RunAll,
+            "skip" => Self::Skip,
+            _ => Self::RunOnce,
+        }
+    }
+}
+
+/// A cron schedule evaluated in a time zone, e.g. `0 9 * * 1-5` in
+/// `America/New_York` runs at 9am local time on weekdays regardless of the
+/// daylight saving time.
+///
+/// The expression consists of five space separated fields: minute (0-59),
+/// hour (0-23), day of month (1-31), month (1-12) and day of week (0-7, 0
+/// and 7 are Sunday). Each field is `*`, a number, a range `1-5`, a list
+/// `1,15` or any of them with a step, e.g. `*/15` or `0-30/10`. If both days
+/// of month and week are restricted, either of them matches like in cron.
+#[derive(Clone, Debug)]
+pub struct CronSchedule {
+    expression: String,
+    timezone: String,
+    catch_up: CatchUp,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl CronSchedule {
+    /// Parses the cron expression evaluated in UTC
+    pub fn new(expression: &str) -> Result<Self> {
+        let invalid = |reason: &str| Error::InvalidCron(expression.into(), reason.into());
+        let fields: Vec<_> = expression.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(invalid("expected 5 fields"));
+        };
+        let mut weekdays_mask = parse_field(weekdays, 0, 7).map_err(|e| invalid(&e))?;
+        // Both 0 and 7 are Sunday
+        if weekdays_mask & 1 << 7 != 0 {
+            weekdays_mask = weekdays_mask & !(1 << 7) | 1;
+        }
+        Ok(Self {
+            expression: expression.into(),
+            timezone: "UTC".into(),
+            catch_up: CatchUp::default(),
+            minutes: parse_field(minutes, 0, 59).map_err(|e| invalid(&e))?,
+            hours: parse_field(hours, 0, 23).map_err(|e| invalid(&e))?,
+            days: parse_field(days, 1, 31).map_err(|e| invalid(&e))?,
+            months: parse_field(months, 1, 12).map_err(|e| invalid(&e))?,
+            weekdays: weekdays_mask,
+            any_day: days.starts_with('*'),
+            any_weekday: weekdays.starts_with('*'),
+        })
+    }
+
+    /// Evaluates the expression in the IANA time zone, e.g. `Europe/Berlin`.
+    /// Zones are resolved by Postgres, so its time zone database is used.
+    pub fn in_timezone(mut self, timezone: impl Into<String>) -> Self {
+        self.timezone = timezone.into();
+        self
+    }
+
+    /// Sets what to do with the runs missed while no worker was running,
+    /// [`CatchUp::RunOnce`] by default
+    pub fn with_catch_up(mut self, catch_up: CatchUp) -> Self {
+        self.catch_up = catch_up;
+        self
+    }
+
+    /// Returns the cron expression
+    pub fn expression(&self) -> &str {
+        &self.expression
+    }
+
+    /// Returns the time zone
+    pub fn timezone(&self) -> &str {
+        &self.timezone
+    }
+
+    /// Checks if the date matches the day fields
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let day = self.days & 1 << date.day() != 0;
+        let weekday = self.weekdays & 1 << date.weekday().num_days_from_sunday() != 0;
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    /// Returns the first matching local time after the given one
+    fn next_local(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut time =
+            after.date().and_hms_opt(after.hour(), after.minute(), 0)? + ChronoDuration::minutes(1);
+        let limit = time + ChronoDuration::days(SEARCH_LIMIT_DAYS);
+        while time < limit {
+            let date = time.date();
+            if self.months & 1 << date.month() == 0 {
+                let (year, month) = match date.month() {
+                    12 => (date.year() + 1, 1),
+                    month => (date.year(), month + 1),
+                };
+                time = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.matches_day(date) {
+                time = date.succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if self.hours & 1 << time.hour() == 0 {
+                time = date.and_hms_opt(time.hour(), 0, 0)? + ChronoDuration::hours(1);
+            } else if self.minutes & 1 << time.minute() == 0 {
+                time += ChronoDuration::minutes(1);
+            } else {
+                return Some(time);
+            }
+        }
+        None
+    }
+}
+
+/// Parses a cron field into a bit mask of its values
+fn parse_field(field: &str, min: u32, max: u32) -> std::result::Result<u64, String> {
+    let number = |s: &str| {
+        s.parse::<u32>()
+            .ok()
+            .filter(|n| (min..=max).contains(n))
+            .ok_or_else(|| format!("`{s}` isn't a number in {min}-{max}"))
+    };
+    let mut mask = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, Some(step)),
+                _ => return Err(format!("invalid step `{step}`")),
+            },
+            None => (part, None),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (number(start)?, number(end)?),
+                None if step.is_some() => (number(range)?, max),
+                None => (number(range)?, number(range)?),
+            },
+        };
+        if start > end {
+            return Err(format!("invalid range `{range}`"));
+        }
+        for value in (start..=end).step_by(step.unwrap_or(1) as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
+
+/// Enqueues the task by the cron schedule, e.g. to send a daily report at 9am
+/// in the user's time zone. The schedule is stored in the
+/// `pg_task_recurring` table under the unique name, and running workers
+/// enqueue the task when it's time. Scheduling under an existing name
+/// replaces the task and the schedule, the next run time is kept unless the
+/// schedule is changed.
+pub async fn schedule_recurring<'a, S: Scheduler>(
+    db: impl Acquire<'a, Database = Postgres>,
+    name: &str,
+    task: &S,
+    schedule: &CronSchedule,
+) -> Result<()> {
+    let step = payload::serialize_with_names(task, S::CODEC)?;
+    let mut tx = db.begin().await.map_err(db_error!("begin"))?;
+    let known = sqlx::query!(
+        r#"
+        SELECT
+            now() AS "now!",
+            EXISTS(SELECT 1 FROM pg_timezone_names WHERE name = $1) AS "timezone_exists!"
+        "#,
+        schedule.timezone,
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(db_error!("check time zone"))?;
+    if !known.timezone_exists {
+        return Err(Error::UnknownTimezone(schedule.timezone.clone()));
+    }
+    let now = known.now;
+    let next_run_at = next_run_at(&mut tx, schedule, now).await?;
+    if next_run_at.is_none() {
+        return Err(Error::InvalidCron(
+            schedule.expression.clone(),
+            "never matches".into(),
+        ));
+    }
+    sqlx::query!(
+        r#"
+        INSERT INTO pg_task_recurring (
+            name, step, task_name, step_name, payload_version, cron, timezone, next_run_at,
+            catch_up, step_bytes
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        ON CONFLICT (name) DO UPDATE
+        SET step = EXCLUDED.step,
+            step_bytes = EXCLUDED.step_bytes,
+            task_name = EXCLUDED.task_name,
+            step_name = EXCLUDED.step_name,
+            payload_version = EXCLUDED.payload_version,
+            cron = EXCLUDED.cron,
+            timezone = EXCLUDED.timezone,
+            catch_up = EXCLUDED.catch_up,
+            next_run_at = CASE
+                WHEN pg_task_recurring.cron = EXCLUDED.cron
+                     AND pg_task_recurring.timezone = EXCLUDED.timezone
+                THEN pg_task_recurring.next_run_at
+                ELSE EXCLUDED.next_run_at
+            END
+        "#,
+        name,
+        step.value,
+        step.task_name,
+        step.step_name,
+        S::PAYLOAD_VERSION,
+        schedule.expression,
+        schedule.timezone,
+        next_run_at,
+        schedule.catch_up.name(),
+        step.bytes,
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error!("upsert"))?;
+    tx.commit().await.map_err(db_error!("commit"))
+}
+
+/// Removes the cron schedule, returns `false` if there's no such schedule
+pub async fn unschedule_recurring<'e>(db: impl PgExecutor<'e>, name: &str) -> Result<bool> {
+    let removed = sqlx::query!("DELETE FROM pg_task_recurring WHERE name = $1", name)
+        .execute(db)
+        .await
+        .map_err(db_error!())?
+        .rows_affected();
+    Ok(removed > 0)
+}
+
+/// Returns the first time matching the schedule after the given one, or
+/// `None` if the expression never matches
+async fn next_run_at(
+    db: &mut PgConnection,
+    schedule: &CronSchedule,
+    after: DateTime<Utc>,
+) -> Result<Option<DateTime<Utc>>> {
+    let mut local = sqlx::query_scalar!(
+        r#"SELECT $1::TIMESTAMPTZ AT TIME ZONE $2::TEXT AS "local!""#,
+        after,
+        schedule.timezone,
+    )
+    .fetch_one(&mut *db)
+    .await
+    .map_err(db_error!("to local time"))?;
+    loop {
+        let Some(next) = schedule.next_local(local) else {
+            return Ok(None);
+        };
+        let at = sqlx::query_scalar!(
+            r#"SELECT $1::TIMESTAMP AT TIME ZONE $2::TEXT AS "at!""#,
+            next,
+            schedule.timezone,
+        )
+        .fetch_one(&mut *db)
+        .await
+        .map_err(db_error!("from local time"))?;
+        // A local time repeated when clocks go back is mapped to the same
+        // instant, so it's only run once
+        if at > after {
+            return Ok(Some(at));
+        }
+        local = next;
+    }
+}
+
+/// Enqueues the tasks of due schedules until the worker is stopped. The
+/// schedules are due by the worker clock if it's set, or the db time.
+pub async fn run(db: &PgPool, clock: Option<Arc<dyn Clock>>) -> Infallible {
+    loop {
+        let now = clock.as_ref().map(|clock| clock.now());
+        let wait = match enqueue_due(db, now).await {
+            Ok(wait) => wait,
+            Err(e) => {
+                warn!(
+                    "Can't enqueue recurring tasks: {}",
+                    source_chain::to_string(&e)
+                );
+                CHECK_INTERVAL
+            }
+        };
+        sleep(wait).await;
+    }
+}
+
+/// Enqueues the tasks of schedules due by `now`, or the db time if it's
+/// `None`, and returns the time to the next check
+pub(crate) async fn enqueue_due(db: &PgPool, now: Option<DateTime<Utc>>) -> Result<Duration> {
+    while enqueue_next_due(db, now).await? {}
+    let until_next = sqlx::query_scalar!(
+        r#"
+        SELECT MIN(next_run_at) - COALESCE($1, now()) AS "until_next"
+        FROM pg_task_recurring
+        "#,
+        now,
+    )
+    .fetch_one(db)
+    .await
+    .map_err(db_error!("next run"))?;
+    let wait = until_next
+        .map(|interval| crate::util::pg_interval_to_std(&interval))
+        .unwrap_or(CHECK_INTERVAL);
+    Ok(wait.min(CHECK_INTERVAL))
+}
+
+/// Enqueues the tasks of a single due schedule, returns `false` if there's
+/// nothing due. Schedules are locked, so concurrent workers enqueue each run
+/// only once.
+async fn enqueue_next_due(db: &PgPool, now: Option<DateTime<Utc>>) -> Result<bool> {
+    let mut tx = db.begin().await.map_err(db_error!("begin"))?;
+    let Some(due) = sqlx::query!(
+        r#"
+        SELECT
+            name,
+            step,
+            step_bytes,
+            task_name,
+            step_name,
+            payload_version,
+            cron,
+            timezone,
+            catch_up,
+            next_run_at AS "run_at!",
+            COALESCE($1, now()) AS "now!"
+        FROM pg_task_recurring
+        WHERE next_run_at <= COALESCE($1, now())
+        ORDER BY next_run_at
+        LIMIT 1
+        FOR UPDATE SKIP LOCKED
+        "#,
+        now,
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(db_error!("fetch due"))?
+    else {
+        return Ok(false);
+    };
+
+    let (runs, next_run_at) = match CronSchedule::new(&due.cron) {
+        Ok(schedule) => {
+            let schedule = schedule
+                .in_timezone(due.timezone)
+                .with_catch_up(CatchUp::from_name(&due.catch_up));
+            catch_up(&mut tx, &schedule, due.run_at, due.now).await?
+        }
+        Err(e) => {
+            warn!(
+                "Disabling recurring task {}: {}",
+                due.name,
+                source_chain::to_string(&e)
+            );
+            (1, None)
+        }
+    };
+    let step = Serialized {
+        value: due.step,
+        bytes: due.step_bytes,
+        task_name: due.task_name,
+        step_name: due.step_name,
+    };
+    for _ in 0..runs {
+        insert_serialized(
+            &mut *tx,
+            &step,
+            due.payload_version,
+            InsertOptions::new(due.now),
+        )
+        .await?;
+    }
+    sqlx::query!(
+        r#"
+        UPDATE pg_task_recurring
+        SET next_run_at = $2,
+            last_run_at = CASE WHEN $4 THEN $3 ELSE last_run_at END
+        WHERE name = $1
+        "#,
+        due.name,
+        next_run_at,
+        due.now,
+        runs > 0,
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error!("update next run"))?;
+    tx.commit().await.map_err(db_error!("commit"))?;
+    info!(
+        "Enqueued {runs} task(s) of recurring {}, next run in {:?}",
+        due.name,
+        next_run_at.map(|at| chrono_duration_to_std(at - due.now))
+    );
+    Ok(true)
+}
+
+/// Applies the catch-up policy of the schedule to its due run and the runs
+/// missed after it. Returns the number of tasks to enqueue and the time of
+/// the next run.
+async fn catch_up(
+    db: &mut PgConnection,
+    schedule: &CronSchedule,
+    run_at: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> Result<(usize, Option<DateTime<Utc>>)> {
+    let runs = match schedule.catch_up {
+        CatchUp::RunOnce => 1,
+        CatchUp::Skip => usize::from(now - run_at <= MISSED_AFTER),
+        CatchUp::RunAll => {
+            let mut runs = 1;
+            let mut after = run_at;
+            while runs < MAX_CATCH_UP_RUNS {
+                match next_run_at(db, schedule, after).await? {
+                    Some(at) if at <= now => {
+                        runs += 1;
+                        after = at;
+                    }
+                    next => return Ok((runs, next)),
+                }
+            }
+            runs
+        }
+    };
+    Ok((runs, next_run_at(db, schedule, now).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn local(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        Utc.from_utc_datetime(&local(s))
+    }
+
+    fn values(mask: u64) -> Vec<u32> {
+        (0..64).filter(|n| mask & 1 << n != 0).collect()
+    }
+
+    #[test]
+    fn parses_ranges_lists_and_steps() {
+        assert_eq!(
+            values(parse_field("*", 1, 12).unwrap()),
+            (1..=12).collect::<Vec<_>>()
+        );
+        assert_eq!(values(parse_field("5", 0, 59).unwrap()), [5]);
+        assert_eq!(values(parse_field("1-5", 0, 7).unwrap()), [1, 2, 3, 4, 5]);
+        assert_eq!(values(parse_field("1,15", 1, 31).unwrap()), [1, 15]);
+        assert_eq!(values(parse_field("*/15", 0, 59).unwrap()), [0, 15, 30, 45]);
+        assert_eq!(
+            values(parse_field("0-30/10", 0, 59).unwrap()),
+            [0, 10, 20, 30]
+        );
+        assert_eq!(values(parse_field("50/5", 0, 59).unwrap()), [50, 55]);
+        assert_eq!(
+            values(parse_field("1-3,20-23/2", 0, 23).unwrap()),
+            [1, 2, 3, 20, 22]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_fields() {
+        assert!(parse_field("60", 0, 59).is_err());
+        assert!(parse_field("0", 1, 31).is_err());
+        assert!(parse_field("5-1", 0, 59).is_err());
+        assert!(parse_field("*/0", 0, 59).is_err());
+        assert!(parse_field("*/x", 0, 59).is_err());
+        assert!(parse_field("", 0, 59).is_err());
+        assert!(CronSchedule::new("* * * *").is_err());
+    }
+
+    #[test]
+    fn treats_7_as_sunday() {
+        let schedule = CronSchedule::new("0 0 * * 7").unwrap();
+        // 2026-10-15 is a Thursday
+        let next = schedule.next_local(local("2026-10-15 12:00"));
+        assert_eq!(next, Some(local("2026-10-18 00:00")));
+    }
+
+    #[test]
+    fn finds_the_next_local_time() {
+        let schedule = CronSchedule::new("*/15 9-17 * * 1-5").unwrap();
+        let next = |s| schedule.next_local(local(s));
+        assert_eq!(next("2026-10-15 09:00"), Some(local("2026-10-15 09:15")));
+        assert_eq!(next("2026-10-15 09:07"), Some(local("2026-10-15 09:15")));
+        assert_eq!(next("2026-10-15 17:45"), Some(local("2026-10-16 09:00")));
+        // Friday evening to Monday morning
+        assert_eq!(next("2026-10-16 18:00"), Some(local("2026-10-19 09:00")));
+        // The next year
+        let schedule = CronSchedule::new("0 0 1 1 *").unwrap();
+        let next = schedule.next_local(local("2026-10-15 00:00"));
+        assert_eq!(next, Some(local("2027-01-01 00:00")));
+    }
+
+    #[test]
+    fn matches_either_day_of_month_or_week() {
+        // The 13th or any Friday
+        let schedule = CronSchedule::new("0 0 13 * 5").unwrap();
+        let next = |s| schedule.next_local(local(s));
+        assert_eq!(next("2026-10-10 00:00"), Some(local("2026-10-13 00:00")));
+        assert_eq!(next("2026-10-13 00:00"), Some(local("2026-10-16 00:00")));
+        // With an unrestricted day of week only the day of month matters
+        let schedule = CronSchedule::new("0 0 13 * *").unwrap();
+        let next = schedule.next_local(local("2026-10-13 00:00"));
+        assert_eq!(next, Some(local("2026-11-13 00:00")));
+        // And the other way around
+        let schedule = CronSchedule::new("0 0 */1 * 5").unwrap();
+        let next = schedule.next_local(local("2026-10-13 00:00"));
+        assert_eq!(next, Some(local("2026-10-16 00:00")));
+    }
+
+    #[test]
+    fn never_matches_impossible_dates() {
+        let schedule = CronSchedule::new("0 0 31 2 *").unwrap();
+        assert_eq!(schedule.next_local(local("2026-10-15 00:00")), None);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn runs_at_the_time_after_a_dst_gap(db: PgPool) -> anyhow::Result<()> {
+        let mut con = db.acquire().await?;
+        let schedule = CronSchedule::new("30 2 * * *")?.in_timezone("America/New_York");
+
+        // 2:30am doesn't exist on Mar 8th, as clocks go from 2am to 3am EST
+        let next = next_run_at(&mut con, &schedule, utc("2026-03-08 05:00")).await?;
+        assert_eq!(next, Some(utc("2026-03-08 07:30")));
+        // And it's 2:30am EDT the day after
+        let next = next_run_at(&mut con, &schedule, utc("2026-03-08 07:30")).await?;
+        assert_eq!(next, Some(utc("2026-03-09 06:30")));
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn runs_once_in_a_dst_overlap(db: PgPool) -> anyhow::Result<()> {
+        let mut con = db.acquire().await?;
+        let schedule = CronSchedule::new("30 1 * * *")?.in_timezone("America/New_York");
+
+        // 1:30am happens twice on Nov 1st, as clocks go from 2am EDT back to
+        // 1am EST, it's run at the latter
+        let next = next_run_at(&mut con, &schedule, utc("2026-11-01 04:00")).await?;
+        assert_eq!(next, Some(utc("2026-11-01 06:30")));
+        let next = next_run_at(&mut con, &schedule, utc("2026-11-01 06:30")).await?;
+        assert_eq!(next, Some(utc("2026-11-02 06:30")));
+        Ok(())
+    }
+}
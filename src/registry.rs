@@ -0,0 +1,104 @@
+use crate::{
+    util::{add_delay, db_error},
+    Result,
+};
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use std::{convert::Infallible, time::Duration};
+use tokio::time::sleep;
+use tracing::{trace, warn};
+
+/// A worker registered in the `pg_task_worker` table
+#[derive(Debug)]
+pub struct WorkerInfo {
+    /// Worker id, running tasks refer to it by their `locked_by` column
+    pub id: String,
+    /// Hostname of the worker
+    pub hostname: String,
+    /// Process id of the worker
+    pub pid: i32,
+    /// Time the worker was started
+    pub started_at: DateTime<Utc>,
+    /// Time of the last heartbeat of the worker
+    pub heartbeat_at: DateTime<Utc>,
+    /// Indicates if the worker heartbeats in time
+    pub is_alive: bool,
+}
+
+/// Returns the registered workers, the dead ones are kept until a worker with
+/// the same id is started
+pub async fn workers<'e>(db: impl PgExecutor<'e>) -> Result<Vec<WorkerInfo>> {
+    sqlx::query_as!(
+        WorkerInfo,
+        r#"
+        SELECT
+            id,
+            hostname,
+            pid,
+            started_at,
+            heartbeat_at,
+            alive_until > now() AS "is_alive!"
+        FROM pg_task_worker
+        ORDER BY id
+        "#
+    )
+    .fetch_all(db)
+    .await
+    .map_err(db_error!())
+}
+
+/// Registers the worker, it's considered alive for the `lease` duration
+pub async fn register(db: &PgPool, worker_id: &str, lease: Duration) -> Result<()> {
+    let hostname = gethostname::gethostname().to_string_lossy().into_owned();
+    sqlx::query!(
+        "
+        INSERT INTO pg_task_worker (id, hostname, pid, alive_until)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (id) DO UPDATE
+        SET hostname = EXCLUDED.hostname,
+            pid = EXCLUDED.pid,
+            started_at = now(),
+            heartbeat_at = now(),
+            alive_until = EXCLUDED.alive_until
+        ",
+        worker_id,
+        hostname,
+        std::process::id() as i32,
+        add_delay(Utc::now(), lease),
+    )
+    .execute(db)
+    .await
+    .map_err(db_error!())?;
+    Ok(())
+}
+
+/// Prolongs the worker to be alive every third of the `lease` duration
+pub async fn heartbeat(db: &PgPool, worker_id: &str, lease: Duration) -> Infallible {
+    loop {
+        sleep(lease / 3).await;
+        trace!("Worker heartbeat");
+        if let Err(e) = sqlx::query!(
+            "UPDATE pg_task_worker SET heartbeat_at = now(), alive_until = $2 WHERE id = $1",
+            worker_id,
+            add_delay(Utc::now(), lease),
+        )
+        .execute(db)
+        .await
+        {
+            warn!(
+                "Can't send worker heartbeat: {}",
+                source_chain::to_string(&e)
+            );
+        }
+    }
+}
+
+/// Removes the stopped worker
+pub async fn deregister(db: &PgPool, worker_id: &str) {
+    if let Err(e) = sqlx::query!("DELETE FROM pg_task_worker WHERE id = $1", worker_id)
+        .execute(db)
+        .await
+    {
+        warn!("Can't deregister worker: {}", source_chain::to_string(&e));
+    }
+}
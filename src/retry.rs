@@ -0,0 +1,91 @@
+use crate::util::add_jitter;
+use std::time::Duration;
+
+/// Calculates delays between retries of a failed step
+#[derive(Clone, Copy, Debug)]
+pub enum RetryStrategy {
+    /// The same delay before each retry
+    Fixed(Duration),
+    /// The delay starts with `initial` and is multiplied by `factor` on each
+    /// next retry up to `max`
+    Exponential {
+        /// The delay before the first retry
+        initial: Duration,
+        /// The multiplier of each next delay
+        factor: u32,
+        /// The maximum delay
+        max: Duration,
+    },
+    /// The delay is a function of the retry number starting from 1
+    Custom(fn(i32) -> Duration),
+}
+
+impl RetryStrategy {
+    /// Returns the delay before the given retry, retries are numbered from 1
+    pub fn delay(&self, retry: i32) -> Duration {
+        match *self {
+            Self::Fixed(delay) => delay,
+            Self::Exponential {
+                initial,
+                factor,
+                max,
+            } => factor
+                .checked_pow(retry.saturating_sub(1).max(0).unsigned_abs())
+                .and_then(|multiplier| initial.checked_mul(multiplier))
+                .map_or(max, |delay| delay.min(max)),
+            Self::Custom(f) => f(retry),
+        }
+    }
+}
+
+/// Overrides the retry settings of the task steps for a particular task, see
+/// [`EnqueueOptions::with_retry`](crate::EnqueueOptions::with_retry). The
+/// `None` fields keep the step settings.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RetryPolicy {
+    /// Overrides [`Step::RETRY_LIMIT`](crate::Step::RETRY_LIMIT)
+    pub limit: Option<i32>,
+    /// Replaces [`Step::RETRY_STRATEGY`](crate::Step::RETRY_STRATEGY) with a
+    /// fixed delay
+    pub delay: Option<Duration>,
+}
+
+/// Delays between checks of the db connection after it's lost, used by
+/// workers to restore fetching tasks and listening to the tasks table changes,
+/// see [`Worker::with_reconnect_policy`](crate::Worker::with_reconnect_policy).
+/// The default is a check per second.
+#[derive(Clone, Copy, Debug)]
+pub struct ReconnectPolicy {
+    /// The delay before the first check
+    pub initial_delay: Duration,
+    /// The multiplier of each next delay
+    pub multiplier: f64,
+    /// The maximum delay
+    pub max_delay: Duration,
+    /// Randomizes the delays by up to the fraction of them, from 0 to 1, so
+    /// workers losing the connection at once don't reconnect at once
+    pub jitter: f32,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            multiplier: 1.0,
+            max_delay: Duration::from_secs(1),
+            jitter: 0.0,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Returns the delay before the given check, checks are numbered from 1
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = i32::try_from(attempt.saturating_sub(1)).unwrap_or(i32::MAX);
+        let delay = self.initial_delay.as_secs_f64() * self.multiplier.max(1.0).powi(exponent);
+        let delay = Duration::try_from_secs_f64(delay)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        add_jitter(delay, self.jitter)
+    }
+}
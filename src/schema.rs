@@ -0,0 +1,12 @@
+use sqlx::postgres::PgConnectOptions;
+
+/// Sets the connection options to use the tasks tables in the `schema`, so
+/// several apps could keep their tasks in one database. The queries aren't
+/// qualified with a schema, it's set as the `search_path` of the connections.
+/// The schema should exist before calling [`migrate`](crate::migrate).
+pub fn in_schema(options: PgConnectOptions, schema: &str) -> PgConnectOptions {
+    let identifier = format!("\"{}\"", schema.replace('"', "\"\""));
+    // Spaces separate the startup options, so they are escaped
+    let value = identifier.replace('\\', "\\\\").replace(' ', "\\ ");
+    options.options([("search_path", value)])
+}
@@ -0,0 +1,42 @@
+use crate::{util::db_error, Error, Result};
+use serde::Serialize;
+use sqlx::{types::Uuid, PgExecutor};
+
+/// Sends a signal with the `payload` to the task, e.g. on a webhook or a human
+/// approval. The task waiting for a signal after
+/// [`NextStep::wait_signal`](crate::NextStep::wait_signal) runs its next step
+/// right away, and the step gets the payload using
+/// [`StepContext::signal`](crate::StepContext::signal). A signal received
+/// before the task starts waiting, e.g. while its earlier steps are running,
+/// isn't lost: it's kept pending and the next wait consumes it without
+/// waiting. Only the last payload is kept. Returns `false` if there's no such
+/// task or it's already done.
+pub async fn signal<'e>(
+    db: impl PgExecutor<'e>,
+    id: Uuid,
+    payload: &impl Serialize,
+) -> Result<bool> {
+    let payload = serde_json::to_value(payload).map_err(Error::SerializeSignal)?;
+    let updated = sqlx::query!(
+        "
+        UPDATE pg_task
+        SET signal = $2,
+            signaled_at = now(),
+            wakeup_at = CASE WHEN is_awaiting_signal THEN now() ELSE wakeup_at END,
+            is_signal_pending = NOT is_awaiting_signal,
+            is_awaiting_signal = false,
+            signal_timeout_step = NULL,
+            signal_timeout_step_bytes = NULL,
+            signal_timeout_payload_version = NULL
+        WHERE id = $1
+          AND done_at IS NULL
+        ",
+        id,
+        payload,
+    )
+    .execute(db)
+    .await
+    .map_err(db_error!())?
+    .rows_affected();
+    Ok(updated > 0)
+}
@@ -0,0 +1,122 @@
+use crate::{
+    util::{db_error, pg_interval_to_std},
+    Result,
+};
+use sqlx::PgPool;
+use std::time::Duration;
+
+/// Statistics of the tasks queue returned by [`stats`]
+#[derive(Clone, Debug, Default)]
+pub struct QueueStats {
+    /// Statistics of the unfinished tasks per step
+    pub steps: Vec<StepStats>,
+    /// Number of done tasks, they're only kept by workers with
+    /// [`Worker::with_keep_done`](crate::Worker::with_keep_done)
+    pub done: i64,
+}
+
+/// Statistics of the unfinished tasks at a step
+#[derive(Clone, Debug, Default)]
+pub struct StepStats {
+    /// Name of the task, i.e. the variant of the enum passed to the worker
+    pub task_name: String,
+    /// Name of the step, i.e. the variant of the task enum
+    pub step_name: String,
+    /// Number of tasks ready to run, i.e. waiting for a worker
+    pub ready: i64,
+    /// Number of tasks scheduled to run in the future
+    pub scheduled: i64,
+    /// Number of running tasks
+    pub running: i64,
+    /// Number of paused tasks
+    pub paused: i64,
+    /// Number of failed tasks
+    pub failed: i64,
+    /// Number of expired tasks
+    pub expired: i64,
+    /// How long the longest waiting ready task is waiting for a worker
+    pub oldest_ready_age: Option<Duration>,
+}
+
+impl QueueStats {
+    /// Sums up the statistics of all the steps, the names are left empty
+    pub fn total(&self) -> StepStats {
+        self.steps
+            .iter()
+            .fold(StepStats::default(), |total, s| StepStats {
+                ready: total.ready + s.ready,
+                scheduled: total.scheduled + s.scheduled,
+                running: total.running + s.running,
+                paused: total.paused + s.paused,
+                failed: total.failed + s.failed,
+                expired: total.expired + s.expired,
+                oldest_ready_age: total.oldest_ready_age.max(s.oldest_ready_age),
+                ..total
+            })
+    }
+}
+
+/// Returns the statistics of the tasks queue, e.g. for autoscalers and health
+/// dashboards
+pub async fn stats(db: &PgPool) -> Result<QueueStats> {
+    let steps = sqlx::query!(
+        r#"
+        SELECT
+            task_name,
+            step_name,
+            COUNT(*) FILTER (
+                WHERE COALESCE(locked_until <= now(), true)
+                  AND error IS NULL
+                  AND expired_at IS NULL
+                  AND NOT is_paused
+                  AND wakeup_at <= now()
+            ) AS "ready!",
+            COUNT(*) FILTER (
+                WHERE COALESCE(locked_until <= now(), true)
+                  AND error IS NULL
+                  AND expired_at IS NULL
+                  AND NOT is_paused
+                  AND wakeup_at > now()
+            ) AS "scheduled!",
+            COUNT(*) FILTER (WHERE locked_until > now()) AS "running!",
+            COUNT(*) FILTER (WHERE is_paused AND error IS NULL) AS "paused!",
+            COUNT(*) FILTER (WHERE error IS NOT NULL) AS "failed!",
+            COUNT(*) FILTER (WHERE expired_at IS NOT NULL) AS "expired!",
+            now() - MIN(wakeup_at) FILTER (
+                WHERE COALESCE(locked_until <= now(), true)
+                  AND error IS NULL
+                  AND expired_at IS NULL
+                  AND NOT is_paused
+                  AND wakeup_at <= now()
+            ) AS oldest_ready_age
+        FROM pg_task
+        WHERE done_at IS NULL
+        GROUP BY task_name, step_name
+        ORDER BY task_name, step_name
+        "#
+    )
+    .fetch_all(db)
+    .await
+    .map_err(db_error!("steps"))?
+    .into_iter()
+    .map(|r| StepStats {
+        task_name: r.task_name,
+        step_name: r.step_name,
+        ready: r.ready,
+        scheduled: r.scheduled,
+        running: r.running,
+        paused: r.paused,
+        failed: r.failed,
+        expired: r.expired,
+        oldest_ready_age: r.oldest_ready_age.as_ref().map(pg_interval_to_std),
+    })
+    .collect();
+
+    let done =
+        sqlx::query_scalar!(r#"SELECT COUNT(*) AS "done!" FROM pg_task WHERE done_at IS NOT NULL"#)
+            .fetch_one(db)
+            .await
+            .map_err(db_error!("done"))?;
+
+    Ok(QueueStats { steps, done })
+}
@@ -1,28 +1,235 @@
 use crate::{
-    util::{chrono_duration_to_std, db_error, ordinal, std_duration_to_chrono},
-    Error, NextStep, Result, Step, StepError,
+    claim,
+    context::{StepNames, StepTransaction},
+    events::Observers,
+    find,
+    lock::AdvisoryLock,
+    payload::{self, Serialized},
+    rate_limit, trace,
+    traits::{insert_many, insert_serialized, InsertOptions},
+    util::{
+        add_delay, add_jitter, chrono_duration_to_std, db_error, ordinal, pg_interval_to_std,
+        retry_transient, std_duration_to_pg_interval,
+    },
+    wait::{self, Outcome},
+    ClaimStrategy, Clock, Error, FatalError, IsolationLevel, NextStep, Result, RetryAfter,
+    RetryStrategy, Scheduler, Step, StepContext, StepError, StepMiddleware, SubTask, TaskEvent,
+    TaskFailure, TaskInfo,
 };
 use chrono::{DateTime, Utc};
-use serde::Serialize;
 use sqlx::{
-    postgres::{PgConnection, PgPool},
+    postgres::{types::PgInterval, PgConnection, PgPool, PgTransaction},
     types::Uuid,
+    Connection, PgExecutor,
 };
-use std::{fmt, time::Duration};
-use tracing::{debug, error, info, trace};
+use std::{
+    any::Any,
+    collections::HashMap,
+    convert::Infallible,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    task::JoinError,
+    time::sleep,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, trace, warn, Instrument};
 
-#[derive(Debug)]
+/// Interval to recheck the children of a joining task in case a wakeup from a
+/// finished child is missed
+const JOIN_RECHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Builds a query of tasks to claim with the predicate shared by all the ways
+/// of claiming them. The query is the concatenation of `$before`, the
+/// predicate and `$after`, `ready` adds the readiness check to the predicate.
+/// The predicate takes the first parameters of the query in the order of
+/// [`FetchOptions`] fields: `$1` tenant concurrency, `$2` payload version, `$3`
+/// the worker clock time, `$4` only steps, `$5` except steps and `$6` task
+/// names.
+macro_rules! claim_query {
+    ($record:ident, $before:literal, ready, $after:literal, $($arg:expr),+ $(,)?) => {
+        claim_query!(
+            @query $record,
+            $before,
+            [
+                "
+                AND GREATEST(LEAST(wakeup_at, deadline_at), locked_until) <= COALESCE($3::TIMESTAMPTZ, now())",
+                $after
+            ],
+            $($arg),+
+        )
+    };
+    ($record:ident, $before:literal, pending, $after:literal, $($arg:expr),+ $(,)?) => {
+        claim_query!(@query $record, $before, [$after], $($arg),+)
+    };
+    (@query $record:ident, $before:literal, [$($after:literal),+], $($arg:expr),+) => {
+        sqlx::query_as!(
+            $record,
+            $before
+                + "
+                error IS NULL
+                AND done_at IS NULL
+                AND cancelled_at IS NULL
+                AND is_paused = false
+                AND (is_awaiting_signal = false OR signal_timeout_step IS NOT NULL OR deadline_at IS NOT NULL)
+                AND is_unparseable = false
+                AND expired_at IS NULL
+                AND payload_version <= $2
+                AND ($4::TEXT[] IS NULL OR step_name = ANY($4))
+                AND step_name <> ALL($5)
+                AND ($6::TEXT[] IS NULL OR task_name = ANY($6))
+                AND NOT EXISTS (SELECT 1 FROM pg_task_control WHERE is_paused)
+                AND (
+                    group_key IS NULL
+                    OR NOT EXISTS (
+                        SELECT 1
+                        FROM pg_task earlier
+                        WHERE earlier.group_key = pg_task.group_key
+                          AND earlier.group_seq < pg_task.group_seq
+                          AND earlier.done_at IS NULL
+                          AND earlier.error IS NULL
+                          AND earlier.expired_at IS NULL
+                    )
+                )
+                AND (
+                    $1::BIGINT IS NULL
+                    OR tenant IS NULL
+                    OR tenant NOT IN (
+                        SELECT tenant
+                        FROM pg_task
+                        WHERE tenant IS NOT NULL
+                          AND locked_by IS NOT NULL
+                          AND locked_until > COALESCE($3::TIMESTAMPTZ, now())
+                        GROUP BY tenant
+                        HAVING COUNT(*) >= $1
+                    )
+                )"
+                $(+ $after)+,
+            $($arg),+
+        )
+    };
+}
+
+/// Worker settings for fetching tasks
+pub struct FetchOptions {
+    /// Tasks of tenants running this number of tasks already are skipped
+    pub tenant_concurrency: Option<i64>,
+    /// Tasks stored with a newer payload version are skipped, as the worker
+    /// can't deserialize them
+    pub payload_version: i32,
+    /// The time of the worker clock, tasks are ready when it's reached their
+    /// wakeup time. The db time is used if it's `None`.
+    pub now: Option<DateTime<Utc>>,
+    /// Only tasks at these steps are fetched if it's set
+    pub only_steps: Option<Vec<String>>,
+    /// Tasks at these steps are skipped
+    pub except_steps: Vec<String>,
+    /// Only tasks of these names are fetched if it's set, so workers of
+    /// different task enums don't claim each other's tasks
+    pub task_names: Option<Vec<String>>,
+    /// Isolation level of the claiming transaction, the db default if it's
+    /// `None`
+    pub isolation: Option<IsolationLevel>,
+    /// How concurrent claims of a task are prevented
+    pub claim_strategy: ClaimStrategy,
+}
+
+/// Worker settings for running steps
+pub struct RunOptions<S, C = ()> {
+    pub worker_id: String,
+    pub lease: Duration,
+    pub keep_done: bool,
+    pub middlewares: Vec<Arc<dyn StepMiddleware<S>>>,
+    pub observers: Observers,
+    /// Tasks which steps crashed workers more times in a row are failed
+    pub crash_limit: Option<i32>,
+    /// Cancelled on the worker shutdown, steps get its child tokens
+    pub shutdown: CancellationToken,
+    /// Semaphores limiting concurrency of step types by their names
+    pub step_semaphores: Mutex<HashMap<&'static str, Arc<Semaphore>>>,
+    /// The application context passed to the steps
+    pub app_context: C,
+    /// Schedules the steps instead of the system time
+    pub clock: Option<Arc<dyn Clock>>,
+}
+
+impl<S, C> RunOptions<S, C> {
+    /// Returns the current time of the worker clock
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock_now().unwrap_or_else(Utc::now)
+    }
+
+    /// Returns the current time of the worker clock if it's set, queries fall
+    /// back to the database time otherwise
+    pub fn clock_now(&self) -> Option<DateTime<Utc>> {
+        self.clock.as_ref().map(|clock| clock.now())
+    }
+
+    /// Waits for a permit to run a step of the type if its concurrency is
+    /// limited
+    async fn acquire_step_permit(
+        &self,
+        step_type: &'static str,
+        max_concurrency: Option<usize>,
+    ) -> Result<Option<OwnedSemaphorePermit>> {
+        let Some(max_concurrency) = max_concurrency else {
+            return Ok(None);
+        };
+        let semaphore = self
+            .step_semaphores
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(step_type)
+            .or_insert_with(|| Arc::new(Semaphore::new(max_concurrency)))
+            .clone();
+        if semaphore.available_permits() == 0 {
+            trace!("Waiting for a slot to run a step of {step_type}");
+        }
+        semaphore
+            .acquire_owned()
+            .await
+            .map(Some)
+            .map_err(Error::UnreachableWorkerSemaphoreClosed)
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Task {
     pub id: Uuid,
-    step: String,
+    step: serde_json::Value,
+    step_bytes: Option<Vec<u8>>,
     tried: i32,
     pub wakeup_at: DateTime<Utc>,
+    created_at: DateTime<Utc>,
+    /// Time the task was fetched by the worker clock, once the step is run
+    /// it's replaced by the time the step was started
+    started_at: DateTime<Utc>,
+    trace_context: Option<serde_json::Value>,
+    pub task_name: String,
+    parent_id: Option<Uuid>,
+    is_joining: bool,
+    is_awaiting_signal: bool,
+    payload_version: i32,
+    /// Overrides of the step retry settings, see
+    /// [`RetryPolicy`](crate::RetryPolicy)
+    retry_limit: Option<i32>,
+    retry_delay: Option<PgInterval>,
+    deadline_at: Option<DateTime<Utc>>,
+    /// Number of consecutive attempts to run the step interrupted by a worker
+    /// crash including the last one if the task is fetched with an expired
+    /// lock
+    crash_count: i32,
+    /// Id of the worker holding the lock, the task is only updated while it's
+    /// still held by the worker
+    locked_by: Option<String>,
 }
 
 impl Task {
-    /// Returns a delay before running the task
-    pub fn wait_before_running(&self) -> Option<Duration> {
-        let delay = self.wakeup_at - Utc::now();
+    /// Returns a delay before running the task at the current time
+    pub fn wait_before_running(&self, now: DateTime<Utc>) -> Option<Duration> {
+        let delay = self.wakeup_at - now;
         if delay <= chrono::Duration::zero() {
             None
         } else {
@@ -30,45 +237,401 @@ impl Task {
         }
     }
 
-    /// Fetches the closest task to run
-    pub async fn fetch_closest(con: &mut PgConnection) -> Result<Option<Self>> {
+    /// Fetches the closest task to run, ready tasks are ordered by priority.
+    /// Running tasks are considered to wake up when their lock expires, and
+    /// tasks with a deadline wake up by the deadline to be expired. Tasks
+    /// being fetched by other workers at the moment are skipped, so workers
+    /// don't wait for each other. Tasks not matching the `options` are skipped
+    /// too.
+    pub async fn fetch_closest(
+        con: &mut PgConnection,
+        options: &FetchOptions,
+    ) -> Result<Option<Self>> {
         trace!("Fetching the closest task to run");
-        sqlx::query_as!(
+        // The ready and the closest tasks are fetched separately, so each query
+        // could use its own index
+        let ready = claim_query!(
+            Task,
+            r#"
+            SELECT
+                id,
+                step,
+                step_bytes,
+                tried,
+                GREATEST(LEAST(wakeup_at, deadline_at), locked_until) AS "wakeup_at!",
+                created_at,
+                COALESCE($3::TIMESTAMPTZ, now()) AS "started_at!",
+                trace_context,
+                task_name,
+                parent_id,
+                is_joining,
+                is_awaiting_signal,
+                payload_version,
+                retry_limit,
+                retry_delay,
+                deadline_at,
+                crash_count + (locked_by IS NOT NULL)::INT AS "crash_count!",
+                locked_by
+            FROM pg_task
+            WHERE"#,
+            ready,
+            "
+            ORDER BY
+                priority DESC,
+                GREATEST(LEAST(wakeup_at, deadline_at), locked_until)
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+            ",
+            options.tenant_concurrency,
+            options.payload_version,
+            options.now,
+            options.only_steps.as_deref(),
+            &options.except_steps,
+            options.task_names.as_deref(),
+        )
+        .fetch_optional(&mut *con)
+        .await
+        .map_err(db_error!("ready"))?;
+        if ready.is_some() {
+            return Ok(ready);
+        }
+
+        claim_query!(
             Task,
             r#"
             SELECT
                 id,
                 step,
+                step_bytes,
                 tried,
-                wakeup_at
+                GREATEST(LEAST(wakeup_at, deadline_at), locked_until) AS "wakeup_at!",
+                created_at,
+                COALESCE($3::TIMESTAMPTZ, now()) AS "started_at!",
+                trace_context,
+                task_name,
+                parent_id,
+                is_joining,
+                is_awaiting_signal,
+                payload_version,
+                retry_limit,
+                retry_delay,
+                deadline_at,
+                crash_count + (locked_by IS NOT NULL)::INT AS "crash_count!",
+                locked_by
             FROM pg_task
-            WHERE is_running = false
-              AND error IS NULL
-            ORDER BY wakeup_at
+            WHERE"#,
+            pending,
+            "
+            ORDER BY GREATEST(LEAST(wakeup_at, deadline_at), locked_until)
             LIMIT 1
-            FOR UPDATE
-            "#,
+            FOR UPDATE SKIP LOCKED
+            ",
+            options.tenant_concurrency,
+            options.payload_version,
+            options.now,
+            options.only_steps.as_deref(),
+            &options.except_steps,
+            options.task_names.as_deref(),
+        )
+        .fetch_optional(con)
+        .await
+        .map_err(db_error!("closest"))
+    }
+
+    /// Fetches a ready task of the next task name after `last_task_name` in
+    /// alphabetical order, so tasks of different names are run in turns. Ready
+    /// tasks of the same name are ordered by priority. Returns `None` if the
+    /// task is being fetched by another worker at the moment. The names of
+    /// the pending tasks are walked by the index one by one, so the query
+    /// doesn't sort all the ready tasks.
+    pub async fn fetch_fair(
+        con: &mut PgConnection,
+        last_task_name: &str,
+        options: &FetchOptions,
+    ) -> Result<Option<Self>> {
+        trace!("Fetching a ready task after {last_task_name:?}");
+        claim_query!(
+            Task,
+            r#"
+            SELECT
+                id,
+                step,
+                step_bytes,
+                tried,
+                GREATEST(LEAST(wakeup_at, deadline_at), locked_until) AS "wakeup_at!",
+                created_at,
+                COALESCE($3::TIMESTAMPTZ, now()) AS "started_at!",
+                trace_context,
+                task_name,
+                parent_id,
+                is_joining,
+                is_awaiting_signal,
+                payload_version,
+                retry_limit,
+                retry_delay,
+                deadline_at,
+                crash_count + (locked_by IS NOT NULL)::INT AS "crash_count!",
+                locked_by
+            FROM pg_task
+            WHERE id = (
+                WITH RECURSIVE names AS (
+                    (
+                        SELECT task_name
+                        FROM pg_task
+                        WHERE error IS NULL
+                          AND done_at IS NULL
+                          AND cancelled_at IS NULL
+                          AND is_paused = false
+                        ORDER BY task_name
+                        LIMIT 1
+                    )
+                    UNION ALL
+                    SELECT (
+                        SELECT task_name
+                        FROM pg_task
+                        WHERE error IS NULL
+                          AND done_at IS NULL
+                          AND cancelled_at IS NULL
+                          AND is_paused = false
+                          AND task_name > names.task_name
+                        ORDER BY task_name
+                        LIMIT 1
+                    )
+                    FROM names
+                    WHERE names.task_name IS NOT NULL
+                )
+                SELECT head.id
+                FROM (
+                    SELECT task_name
+                    FROM names
+                    WHERE task_name IS NOT NULL
+                    ORDER BY task_name <= $7, task_name
+                ) AS turn
+                CROSS JOIN LATERAL (
+                    SELECT id
+                    FROM pg_task
+                    WHERE task_name = turn.task_name AND"#,
+            ready,
+            "
+                    ORDER BY
+                        priority DESC,
+                        GREATEST(LEAST(wakeup_at, deadline_at), locked_until)
+                    LIMIT 1
+                ) AS head
+                LIMIT 1
+            )
+            FOR UPDATE SKIP LOCKED
+            ",
+            options.tenant_concurrency,
+            options.payload_version,
+            options.now,
+            options.only_steps.as_deref(),
+            &options.except_steps,
+            options.task_names.as_deref(),
+            last_task_name,
         )
         .fetch_optional(con)
         .await
         .map_err(db_error!())
     }
 
-    /// Marks the task running
-    pub async fn mark_running(&self, con: &mut PgConnection) -> Result<()> {
+    /// Claims up to `limit` ready tasks at once locking them for the `lease`
+    /// duration on behalf of the worker. Tasks locked by concurrent claims are
+    /// skipped.
+    pub async fn claim_ready(
+        db: &PgPool,
+        worker_id: &str,
+        lease: Duration,
+        limit: usize,
+        options: &FetchOptions,
+    ) -> Result<Vec<Self>> {
+        trace!("Claiming up to {limit} ready tasks");
+        let mut tx = claim::begin(db, options.isolation, options.claim_strategy).await?;
+        // The readiness time is taken before the tasks are locked, so it's the
+        // same as the one of the fetched tasks
+        let tasks = claim_query!(
+            Task,
+            "
+            WITH claimed AS (
+                SELECT id, GREATEST(LEAST(wakeup_at, deadline_at), locked_until) AS ready_at
+                FROM pg_task
+                WHERE",
+            ready,
+            r#"
+                ORDER BY
+                    priority DESC,
+                    GREATEST(LEAST(wakeup_at, deadline_at), locked_until)
+                LIMIT $7
+                FOR UPDATE SKIP LOCKED
+            )
+            UPDATE pg_task
+            SET locked_until = COALESCE($3::TIMESTAMPTZ, now()) + $9::INTERVAL,
+                locked_by = $8,
+                crash_count = crash_count + (locked_by IS NOT NULL)::INT
+            FROM claimed
+            WHERE pg_task.id = claimed.id
+            RETURNING
+                pg_task.id,
+                pg_task.step,
+                pg_task.step_bytes,
+                pg_task.tried,
+                claimed.ready_at AS "wakeup_at!",
+                pg_task.created_at,
+                COALESCE($3::TIMESTAMPTZ, now()) AS "started_at!",
+                pg_task.trace_context,
+                pg_task.task_name,
+                pg_task.parent_id,
+                pg_task.is_joining,
+                pg_task.is_awaiting_signal,
+                pg_task.payload_version,
+                pg_task.retry_limit,
+                pg_task.retry_delay,
+                pg_task.deadline_at,
+                pg_task.crash_count,
+                pg_task.locked_by
+            "#,
+            options.tenant_concurrency,
+            options.payload_version,
+            options.now,
+            options.only_steps.as_deref(),
+            &options.except_steps,
+            options.task_names.as_deref(),
+            limit as i64,
+            worker_id,
+            std_duration_to_pg_interval(lease),
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(db_error!())?;
+        tx.commit().await.map_err(db_error!("commit"))?;
+        Ok(tasks)
+    }
+
+    /// Marks the task running by locking it for the `lease` duration from
+    /// `now`, or the db time if it's not set, on behalf of the worker
+    pub async fn mark_running(
+        &mut self,
+        con: &mut PgConnection,
+        worker_id: &str,
+        lease: Duration,
+        now: Option<DateTime<Utc>>,
+    ) -> Result<()> {
         trace!("[{}] mark running", self.id);
         sqlx::query!(
-            "UPDATE pg_task SET is_running = true WHERE id = $1",
-            self.id
+            "
+            UPDATE pg_task
+            SET locked_until = COALESCE($5::TIMESTAMPTZ, now()) + $2::INTERVAL,
+                locked_by = $3,
+                crash_count = $4
+            WHERE id = $1
+            ",
+            self.id,
+            std_duration_to_pg_interval(lease),
+            worker_id,
+            self.crash_count,
+            now,
         )
         .execute(con)
         .await
         .map_err(db_error!())?;
+        self.locked_by = Some(worker_id.into());
         Ok(())
     }
 
+    /// Renews the lock every third of the `lease` duration, it's intended to
+    /// run alongside of the step, so the step could run for any time. Failed
+    /// renewals are retried more often to renew the lock before it expires.
+    /// The `cancellation` token is cancelled once the task is found cancelled
+    /// or the lock is found lost.
+    async fn renew_lock<S, C>(
+        &self,
+        db: &PgPool,
+        options: &RunOptions<S, C>,
+        cancellation: &CancellationToken,
+    ) -> Infallible {
+        let lease = options.lease;
+        let mut renew_in = lease / 3;
+        loop {
+            sleep(renew_in).await;
+            trace!("[{}] renew lock", self.id);
+            renew_in = lease / 3;
+            match sqlx::query_scalar!(
+                r#"
+                UPDATE pg_task
+                SET locked_until = COALESCE($4::TIMESTAMPTZ, now()) + $2::INTERVAL
+                WHERE id = $1
+                  AND locked_by = $3
+                RETURNING cancelled_at IS NOT NULL AS "cancelled!"
+                "#,
+                self.id,
+                std_duration_to_pg_interval(lease),
+                options.worker_id,
+                options.clock_now(),
+            )
+            .fetch_optional(db)
+            .await
+            {
+                // The step result can't be saved anymore
+                Ok(None) => {
+                    if !cancellation.is_cancelled() {
+                        warn!(
+                            "[{}] the lock is lost, the task could be run by another worker",
+                            self.id
+                        );
+                        cancellation.cancel();
+                    }
+                }
+                Ok(Some(cancelled)) => {
+                    if cancelled && !cancellation.is_cancelled() {
+                        debug!("[{}] is cancelled while running", self.id);
+                        cancellation.cancel();
+                    }
+                }
+                Err(e) => {
+                    warn!(
+                        "[{}] can't renew lock: {}",
+                        self.id,
+                        source_chain::to_string(&e)
+                    );
+                    renew_in = lease / 10;
+                }
+            }
+        }
+    }
+
     /// Runs the current step of the task to completion
-    pub async fn run_step<S: Step<S>>(&self, db: &PgPool) -> Result<()> {
+    pub async fn run_step<S, C>(&self, db: &PgPool, options: &RunOptions<S, C>) -> Result<()>
+    where
+        S: Step<S, C> + Scheduler + 'static,
+        C: Clone + Send + Sync + 'static,
+    {
+        options.observers.emit(|| TaskEvent::Claimed {
+            id: self.id,
+            task_name: self.task_name.clone(),
+        });
+        if self
+            .deadline_at
+            .is_some_and(|deadline| deadline <= self.started_at)
+        {
+            return self.expire(db, options.clock_now()).await;
+        }
+        if self.is_joining && !self.finish_joining(db, options.now()).await? {
+            return Ok(());
+        }
+        if self.is_awaiting_signal {
+            return self.time_out_signal::<S>(db, options.clock_now()).await;
+        }
+        if options
+            .crash_limit
+            .is_some_and(|limit| self.crash_count > limit)
+        {
+            let err = format!(
+                "the step is likely poisonous as it has crashed workers {} times in a row",
+                self.crash_count
+            );
+            self.save_error(db, err.into(), options).await?;
+            return self.delete_if_cancelled(db, options.clock_now()).await;
+        }
         info!(
             "[{id}]{attempt} run step {step}",
             id = self.id,
@@ -79,141 +642,1389 @@ impl Task {
             },
             step = self.step
         );
-        let step: S = match serde_json::from_str(&self.step)
-            .map_err(|e| Error::DeserializeStep(e, format!("{:?}", self.step)))
-        {
+        let (step, task_name, step_name): (S, _, _) = match self.deserialize_step(db).await {
             Ok(x) => x,
-            Err(e) => {
-                self.save_error(db, e.into()).await.ok();
-                return Ok(());
+            // Db errors are propagated to retry the task once its lock expires,
+            // only the payloads which can't be read are quarantined
+            Err(
+                e @ (Error::DeserializeStep(..)
+                | Error::DecodePayload(_)
+                | Error::SerializeStep(..)
+                | Error::EncodePayload(_)),
+            ) => {
+                self.quarantine(db, e.into(), options.clock_now())
+                    .await
+                    .ok();
+                return self.delete_if_cancelled(db, options.clock_now()).await;
             }
+            Err(e) => return Err(e),
         };
 
-        let retry_limit = step.retry_limit();
-        let retry_delay = step.retry_delay();
-        match step.step(db).await {
+        let info = self.fetch_info_for_middlewares(db, options).await;
+        if let Some(info) = &info {
+            for middleware in &options.middlewares {
+                middleware.before(info).await;
+            }
+        }
+
+        let retry_limit = self.retry_limit.unwrap_or_else(|| step.retry_limit());
+        let retry_strategy = match &self.retry_delay {
+            Some(delay) => RetryStrategy::Fixed(pg_interval_to_std(delay)),
+            None => step.retry_strategy(),
+        };
+        let retry_jitter = step.retry_jitter();
+        let timeout = step.timeout();
+        let step_type = step.step_type();
+        let max_concurrency = step.max_concurrency();
+        let is_singleton = step.is_singleton();
+        let rate_limit = step.rate_limit();
+        let has_compensation = step.has_compensation();
+        let (id, attempt, created_at) = (self.id, self.tried + 1, self.created_at);
+        options.observers.emit(|| TaskEvent::StepStarted {
+            id,
+            step: self.step.clone(),
+            attempt,
+        });
+        let step_db = db.clone();
+        let cancellation = options.shutdown.child_token();
+        let step_cancellation = cancellation.clone();
+        let transaction = StepTransaction::default();
+        let step_transaction = transaction.clone();
+        let app_context = options.app_context.clone();
+        let clock = options.clock.clone();
+        let step_names = StepNames {
+            task_name,
+            step_name,
+            step_type,
+            locked_by: self.locked_by.clone(),
+        };
+        let step = async move {
+            let ctx = StepContext::new(
+                step_db.clone(),
+                id,
+                attempt,
+                created_at,
+                step_cancellation,
+                step_transaction,
+                app_context,
+            )
+            .with_codec(S::CODEC)
+            .with_clock(clock)
+            .with_step(step_names);
+            let heartbeats = ctx.heartbeats();
+            let step = step.step(&step_db, &ctx);
+            match timeout {
+                Some(timeout) => tokio::select! {
+                    result = step => result,
+                    () = heartbeats.timed_out(timeout) => Err(Error::StepTimeout(timeout).into()),
+                },
+                None => step.await,
+            }
+        }
+        .instrument(trace::step_span(self.id, self.trace_context.as_ref()));
+        // Spawning to catch panics of the step
+        let step = async {
+            let _permit = options
+                .acquire_step_permit(step_type, max_concurrency)
+                .await?;
+            if let Some(rate) = rate_limit {
+                trace!("[{id}] waiting for a rate limit token of {step_type}");
+                if !rate_limit::acquire_shared(db, step_type, rate, &cancellation).await? {
+                    return Ok(None);
+                }
+            }
+            let singleton_lock = if is_singleton {
+                trace!("[{id}] waiting for the singleton lock of {step_type}");
+                Some(
+                    AdvisoryLock::acquire_in_schema(db, &format!("pg_task:singleton:{step_type}"))
+                        .await?,
+                )
+            } else {
+                None
+            };
+            let started_at = options.now();
+            let result = tokio::spawn(step)
+                .await
+                .unwrap_or_else(|e| Err(join_error(e)));
+            if let Some(lock) = singleton_lock {
+                if let Err(e) = lock.release().await {
+                    warn!(
+                        "[{id}] can't release the singleton lock: {}",
+                        source_chain::to_string(&e)
+                    );
+                }
+            }
+            Ok::<_, StepError>(Some((started_at, result)))
+        };
+        let (started_at, result) = match tokio::select! {
+            result = step => result,
+            never = self.renew_lock(db, options, &cancellation) => match never {},
+        } {
+            Ok(Some(x)) => x,
+            // The step isn't started
+            Ok(None) => return self.release(db, options.clock_now()).await,
+            Err(e) => (self.started_at, Err(e)),
+        };
+        // The step could wait for the concurrency and rate limits after the
+        // task is fetched, so the transition is recorded with its start time
+        let task = Task {
+            started_at,
+            ..self.clone()
+        };
+        if let Some(info) = &info {
+            for middleware in &options.middlewares {
+                middleware.after(info, &result).await;
+            }
+        }
+        options.observers.emit(|| TaskEvent::StepFinished {
+            id,
+            step: self.step.clone(),
+            attempt,
+            error: result.as_ref().err().map(|e| source_chain::to_string(&**e)),
+        });
+        let tx = transaction.take();
+        // The spawned tasks are serialized before saving the transition, so
+        // the step fails like on its own errors with its writes rolled back.
+        // It's fatal, as serializing them again fails the same way.
+        let result = result.and_then(|next| match serialize_spawned(&next) {
+            Ok(spawned) => Ok((next, spawned)),
+            Err(e) => Err(FatalError::new(e).into()),
+        });
+        match result {
             Err(e) => {
-                if self.tried < retry_limit {
-                    self.retry(db, self.tried, retry_limit, retry_delay, e)
+                // Rolling back the writes of the failed step
+                drop(tx);
+                if self.tried < retry_limit && !FatalError::is_fatal(&e) {
+                    let retry_delay = RetryAfter::delay_of(&e).unwrap_or_else(|| {
+                        add_jitter(retry_strategy.delay(self.tried + 1), retry_jitter)
+                    });
+                    task.retry(db, self.tried, retry_limit, retry_delay, e, options)
                         .await?;
                 } else {
-                    self.save_error(db, e).await?;
+                    let e = task.compensate::<S, C>(db, options, e).await;
+                    task.save_error(db, e, options).await?;
                 }
             }
-            Ok(NextStep::None) => self.complete(db).await?,
-            Ok(NextStep::Now(step)) => self.save_next_step(db, step, Duration::ZERO).await?,
-            Ok(NextStep::Delayed(step, delay)) => self.save_next_step(db, step, delay).await?,
+            Ok((next, spawned)) => {
+                task.save_transition(db, tx, next, &spawned, has_compensation, options)
+                    .await?
+            }
         };
+        self.delete_if_cancelled(db, options.clock_now()).await
+    }
+
+    /// Saves the transition to the next step returned by the step. If the
+    /// step has opened [`StepContext::transaction`], the transition is
+    /// committed in it, otherwise it's saved using a connection of the pool
+    /// and retried on transient db errors. The `spawned` tasks are the
+    /// serialized ones of [`NextStep::Many`].
+    async fn save_transition<S: Scheduler, C>(
+        &self,
+        db: &PgPool,
+        tx: Option<PgTransaction<'static>>,
+        next: NextStep<S>,
+        spawned: &[Serialized],
+        has_compensation: bool,
+        options: &RunOptions<S, C>,
+    ) -> Result<()> {
+        match tx {
+            // The writes of the step can't be replayed, so it isn't retried
+            Some(mut tx) => {
+                self.apply_transition(db, &mut tx, &next, spawned, has_compensation, options)
+                    .await?;
+                tx.commit().await.map_err(db_error!("commit step"))
+            }
+            None => {
+                retry_transient(|| async {
+                    let mut con = db.acquire().await.map_err(db_error!("acquire"))?;
+                    self.apply_transition(db, &mut con, &next, spawned, has_compensation, options)
+                        .await
+                })
+                .await
+            }
+        }
+    }
+
+    /// Updates the task according to the next step using the connection
+    async fn apply_transition<S: Scheduler, C>(
+        &self,
+        db: &PgPool,
+        con: &mut PgConnection,
+        next: &NextStep<S>,
+        spawned: &[Serialized],
+        has_compensation: bool,
+        options: &RunOptions<S, C>,
+    ) -> Result<()> {
+        match next {
+            NextStep::None => {
+                self.complete::<S, C>(con, options.keep_done, None, Vec::new(), options)
+                    .await
+            }
+            NextStep::Done(result) => {
+                self.complete::<S, C>(
+                    con,
+                    options.keep_done,
+                    Some(result.clone()),
+                    Vec::new(),
+                    options,
+                )
+                .await
+            }
+            NextStep::Many(_) => {
+                self.complete::<S, C>(con, options.keep_done, None, spawned.to_vec(), options)
+                    .await
+            }
+            NextStep::Now(step) => {
+                self.save_next_step(db, con, step, options.now(), has_compensation, options)
+                    .await
+            }
+            NextStep::Delayed(step, delay) => {
+                let wakeup_at = add_delay(options.now(), *delay);
+                self.save_next_step(db, con, step, wakeup_at, has_compensation, options)
+                    .await
+            }
+            NextStep::Reschedule(at) => self.save_reschedule(con, *at).await,
+            NextStep::DelaySelf(delay) => {
+                self.save_reschedule(con, add_delay(options.now(), *delay))
+                    .await
+            }
+            NextStep::Join(step) => {
+                self.save_join(db, con, step, None, has_compensation, options)
+                    .await
+            }
+            NextStep::Call(sub_task, step) => {
+                self.save_join(db, con, step, Some(sub_task), has_compensation, options)
+                    .await
+            }
+            NextStep::WaitSignal(step, timeout) => {
+                let timeout = timeout
+                    .as_ref()
+                    .map(|(timeout, step)| (add_delay(options.now(), *timeout), step));
+                self.save_wait_signal(db, con, step, timeout, has_compensation, options)
+                    .await
+            }
+        }
+    }
+
+    /// Deserializes the current step along with the names of its task and step
+    /// variants. The step stored with an older payload version is upgraded
+    /// with [`Scheduler::migrate_payload`] and saved.
+    async fn deserialize_step<S: Scheduler>(&self, db: &PgPool) -> Result<(S, String, String)> {
+        let value = payload::decode(&self.step, self.step_bytes.as_deref(), S::CODEC)?;
+        let Some(step) = migrate_payload::<S>(&value, self.payload_version) else {
+            let (task_name, step_name) = payload::variant_names(&value);
+            return Ok((payload::deserialize_decoded(&value)?, task_name, step_name));
+        };
+        let serialized = payload::serialize_with_names(&step, S::CODEC)?;
+        let updated = sqlx::query!(
+            "
+            UPDATE pg_task
+            SET step = $2, step_bytes = $6, step_name = $3, payload_version = $4
+            WHERE id = $1 AND locked_by = $5
+            ",
+            self.id,
+            serialized.value,
+            serialized.step_name,
+            S::PAYLOAD_VERSION,
+            self.locked_by,
+            serialized.bytes,
+        )
+        .execute(db)
+        .await
+        .map_err(db_error!("save migrated step"))?
+        .rows_affected();
+        self.ensure_locked(updated)?;
+        debug!(
+            "[{}] migrated the step payload from version {} to {}",
+            self.id,
+            self.payload_version,
+            S::PAYLOAD_VERSION
+        );
+        Ok((step, serialized.task_name, serialized.step_name))
+    }
+
+    /// Fetches the task info if there are any middlewares to pass it to
+    async fn fetch_info_for_middlewares<S, C>(
+        &self,
+        db: &PgPool,
+        options: &RunOptions<S, C>,
+    ) -> Option<TaskInfo> {
+        if options.middlewares.is_empty() {
+            return None;
+        }
+        match find(db, self.id).await {
+            Ok(info) => info,
+            Err(e) => {
+                warn!(
+                    "[{}] can't fetch the task for middlewares: {}",
+                    self.id,
+                    source_chain::to_string(&e)
+                );
+                None
+            }
+        }
+    }
+
+    /// Removes the task if it was cancelled while the step was running
+    async fn delete_if_cancelled(&self, db: &PgPool, now: Option<DateTime<Utc>>) -> Result<()> {
+        let deleted = sqlx::query!(
+            "DELETE FROM pg_task WHERE id = $1 AND cancelled_at IS NOT NULL",
+            self.id
+        )
+        .execute(db)
+        .await
+        .map_err(db_error!())?
+        .rows_affected();
+        if deleted > 0 {
+            info!("[{}] is cancelled", self.id);
+            wait::notify_finished(db, self.id, &Outcome::Cancelled, &self.step).await;
+            self.wake_joining_parent(db, now).await;
+        }
         Ok(())
     }
 
-    /// Saves the task error
-    async fn save_error(&self, db: &PgPool, err: StepError) -> Result<()> {
-        let err_str = source_chain::to_string(&*err);
+    /// Unlocks the task without running its step, e.g. as the worker is
+    /// stopped while the step waits for a rate limit token, so the attempt
+    /// isn't counted
+    async fn release(&self, db: &PgPool, now: Option<DateTime<Utc>>) -> Result<()> {
+        debug!("[{}] is released without running the step", self.id);
+        let updated = sqlx::query!(
+            "
+            UPDATE pg_task
+            SET locked_until = NULL,
+                locked_by = NULL,
+                crash_count = 0
+            WHERE id = $1
+              AND locked_by = $2
+            ",
+            self.id,
+            self.locked_by,
+        )
+        .execute(db)
+        .await
+        .map_err(db_error!())?
+        .rows_affected();
+        self.ensure_locked(updated)?;
+        self.delete_if_cancelled(db, now).await
+    }
 
-        let (tried, step) = sqlx::query!(
+    /// Marks the task as expired instead of running its step after the
+    /// deadline
+    async fn expire(&self, db: &PgPool, now: Option<DateTime<Utc>>) -> Result<()> {
+        let mut tx = db.begin().await.map_err(db_error!("begin"))?;
+        let updated = sqlx::query!(
+            "
+            UPDATE pg_task
+            SET locked_until = NULL,
+                locked_by = NULL,
+                crash_count = 0,
+                expired_at = now()
+            WHERE id = $1
+              AND locked_by = $2
+            ",
+            self.id,
+            self.locked_by,
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error!())?
+        .rows_affected();
+        self.ensure_locked(updated)?;
+        self.delete_blobs(&mut *tx).await?;
+        self.log_transition(&mut *tx, None, Some("the deadline is exceeded"))
+            .await?;
+        tx.commit().await.map_err(db_error!("commit"))?;
+        wait::notify_finished(db, self.id, &Outcome::Expired, &self.step).await;
+        self.wake_joining_parent(db, now).await;
+        warn!(
+            "[{}] is expired as its deadline is exceeded at step {}",
+            self.id, self.step
+        );
+        self.delete_if_cancelled(db, now).await
+    }
+
+    /// Checks if all the children of the joining task are finished. If they
+    /// are, the task stops joining and returns `true`. Otherwise, the task is
+    /// put back to sleep until a finished child wakes it up.
+    async fn finish_joining(&self, db: &PgPool, now: DateTime<Utc>) -> Result<bool> {
+        let waiting = sqlx::query_scalar!(
             r#"
             UPDATE pg_task
-            SET is_running = false,
-                tried = tried + 1,
-                error = $2,
-                wakeup_at = now()
+            SET locked_until = NULL,
+                locked_by = NULL,
+                crash_count = 0,
+                -- A child could wake the task up after it was fetched
+                wakeup_at = CASE
+                    WHEN wakeup_at > $3 THEN $3
+                    ELSE $2
+                END
             WHERE id = $1
-            RETURNING tried, step::TEXT as "step!"
+              AND locked_by = $4
+              AND EXISTS (
+                  SELECT 1
+                  FROM pg_task child
+                  WHERE child.parent_id = $1
+                    AND child.done_at IS NULL
+                    AND child.error IS NULL
+                    AND child.expired_at IS NULL
+              )
+            RETURNING id
             "#,
             self.id,
-            &err_str,
+            add_delay(now, JOIN_RECHECK_INTERVAL),
+            self.started_at,
+            self.locked_by,
         )
-        .fetch_one(db)
+        .fetch_optional(db)
         .await
-        .map(|r| (r.tried, r.step))
-        .map_err(db_error!())?;
+        .map_err(db_error!("wait for children"))?
+        .is_some();
+        if waiting {
+            trace!("[{}] waits for its children to finish", self.id);
+            return Ok(false);
+        }
+
+        let updated = sqlx::query!(
+            "UPDATE pg_task SET is_joining = false WHERE id = $1 AND locked_by = $2",
+            self.id,
+            self.locked_by,
+        )
+        .execute(db)
+        .await
+        .map_err(db_error!("stop joining"))?
+        .rows_affected();
+        self.ensure_locked(updated)?;
+        debug!("[{}] all the children are finished", self.id);
+        Ok(true)
+    }
+
+    /// Switches the task waiting for a signal to its timeout step, it's run by
+    /// the worker picking the task up right away. If the signal is received
+    /// meanwhile, the task is just unlocked to run its current step.
+    async fn time_out_signal<S: Scheduler>(
+        &self,
+        db: &PgPool,
+        now: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        let mut tx = db.begin().await.map_err(db_error!("begin"))?;
+        let updated = sqlx::query!(
+            "
+            UPDATE pg_task
+            SET locked_until = NULL,
+                locked_by = NULL,
+                crash_count = 0,
+                step = CASE
+                    WHEN is_awaiting_signal THEN COALESCE(signal_timeout_step, step)
+                    ELSE step
+                END,
+                step_bytes = CASE
+                    WHEN is_awaiting_signal AND signal_timeout_step IS NOT NULL
+                        THEN signal_timeout_step_bytes
+                    ELSE step_bytes
+                END,
+                payload_version = CASE
+                    WHEN is_awaiting_signal AND signal_timeout_step IS NOT NULL
+                        THEN COALESCE(signal_timeout_payload_version, payload_version)
+                    ELSE payload_version
+                END,
+                is_awaiting_signal = false,
+                signal_timeout_step = NULL,
+                signal_timeout_step_bytes = NULL,
+                signal_timeout_payload_version = NULL,
+                wakeup_at = COALESCE($3::TIMESTAMPTZ, now())
+            WHERE id = $1
+              AND locked_by = $2
+            RETURNING step, step_bytes
+            ",
+            self.id,
+            self.locked_by,
+            now,
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(db_error!())?
+        .ok_or(Error::LockLost(self.id))?;
+        let timed_out = updated.step != self.step || updated.step_bytes != self.step_bytes;
+        if timed_out {
+            sqlx::query!(
+                "UPDATE pg_task SET step_name = $2 WHERE id = $1",
+                self.id,
+                payload::step_variant_name(&updated.step, updated.step_bytes.as_deref(), S::CODEC),
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error!("update step name"))?;
+            self.log_transition(&mut *tx, Some(&updated.step), None)
+                .await?;
+        }
+        tx.commit().await.map_err(db_error!("commit"))?;
+        if timed_out {
+            info!("[{}] timed out waiting for a signal", self.id);
+        }
+        Ok(())
+    }
+
+    /// Wakes up the parent task if it's waiting for its children to finish,
+    /// so it rechecks them
+    async fn wake_joining_parent<'e>(&self, db: impl PgExecutor<'e>, now: Option<DateTime<Utc>>) {
+        let Some(parent_id) = self.parent_id else {
+            return;
+        };
+        if let Err(e) = sqlx::query!(
+            "
+            UPDATE pg_task
+            SET wakeup_at = COALESCE($2::TIMESTAMPTZ, now())
+            WHERE id = $1 AND is_joining
+            ",
+            parent_id,
+            now,
+        )
+        .execute(db)
+        .await
+        {
+            warn!(
+                "[{}] can't wake up the parent task {parent_id}: {}",
+                self.id,
+                source_chain::to_string(&e)
+            );
+        }
+    }
+
+    /// Saves the task error
+    async fn save_error<S, C>(
+        &self,
+        db: &PgPool,
+        err: StepError,
+        options: &RunOptions<S, C>,
+    ) -> Result<()> {
+        let err_str = source_chain::to_string(&*err);
+
+        let (tried, step) = retry_transient(|| async {
+            let mut tx = db.begin().await.map_err(db_error!("begin"))?;
+            let saved = sqlx::query!(
+                r#"
+                UPDATE pg_task
+                SET locked_until = NULL,
+                    locked_by = NULL,
+                    tried = tried + 1,
+                    error = $2,
+                    errors = errors || jsonb_build_array($3::JSONB || jsonb_build_object('at', now())),
+                    step_started_at = $4,
+                    step_finished_at = now(),
+                    timings = timings || jsonb_build_array($5::JSONB || jsonb_build_object('finished_at', now())),
+                    wakeup_at = COALESCE($7::TIMESTAMPTZ, now())
+                WHERE id = $1
+                  AND locked_by = $6
+                RETURNING tried, step::TEXT as "step!"
+                "#,
+                self.id,
+                &err_str,
+                self.error_record(&*err),
+                self.started_at,
+                self.timing_record(),
+                self.locked_by,
+                options.clock_now(),
+            )
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(db_error!())?
+            .map(|r| (r.tried, r.step))
+            .ok_or(Error::LockLost(self.id))?;
+            self.log_transition(&mut *tx, None, Some(&err_str)).await?;
+            tx.commit().await.map_err(db_error!("commit"))?;
+            Ok(saved)
+        })
+        .await?;
+        wait::notify_finished(db, self.id, &Outcome::Failed(err_str.clone()), &self.step).await;
+        self.wake_joining_parent(db, options.clock_now()).await;
 
         error!(
             "[{id}] resulted in an error at step {step} on {attempt} attempt: {err_str}",
             id = self.id,
             attempt = ordinal(tried + 1)
         );
+        options.observers.emit(|| TaskEvent::Failed {
+            id: self.id,
+            step: self.step.clone(),
+            error: err_str.clone(),
+        });
+        options.observers.notify_failure(TaskFailure {
+            id: self.id,
+            task_name: self.task_name.clone(),
+            step: self.step.clone(),
+            tried,
+            error: err_str,
+        });
 
         Ok(())
     }
 
-    /// Updates the tasks step
-    async fn save_next_step(
+    /// Marks the task as unparseable, so it isn't fetched again until it's
+    /// fixed with [`admin::fix_unparseable`](crate::admin::fix_unparseable)
+    async fn quarantine(
         &self,
         db: &PgPool,
-        step: impl Serialize + fmt::Debug,
-        delay: Duration,
+        err: StepError,
+        now: Option<DateTime<Utc>>,
     ) -> Result<()> {
-        let step = match serde_json::to_string(&step)
-            .map_err(|e| Error::SerializeStep(e, format!("{:?}", step)))
-        {
+        let err_str = source_chain::to_string(&*err);
+        let mut tx = db.begin().await.map_err(db_error!("begin"))?;
+        let updated = sqlx::query!(
+            "
+            UPDATE pg_task
+            SET locked_until = NULL,
+                locked_by = NULL,
+                crash_count = 0,
+                is_unparseable = true,
+                error = $2,
+                errors = errors || jsonb_build_array($3::JSONB || jsonb_build_object('at', now())),
+                wakeup_at = COALESCE($5::TIMESTAMPTZ, now())
+            WHERE id = $1
+              AND locked_by = $4
+            ",
+            self.id,
+            &err_str,
+            self.error_record(&*err),
+            self.locked_by,
+            now,
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error!())?
+        .rows_affected();
+        self.ensure_locked(updated)?;
+        self.log_transition(&mut *tx, None, Some(&err_str)).await?;
+        tx.commit().await.map_err(db_error!("commit"))?;
+        wait::notify_finished(db, self.id, &Outcome::Failed(err_str.clone()), &self.step).await;
+        self.wake_joining_parent(db, now).await;
+
+        error!(
+            "[{id}] is quarantined as its step can't be deserialized: {err_str}",
+            id = self.id,
+        );
+
+        Ok(())
+    }
+
+    /// Runs compensations of the recorded completed steps in the reverse order
+    /// after the task has failed. Each compensated step is removed from the
+    /// record right away, so after a crash the compensation is resumed from
+    /// the same place. Like steps, compensations run while the lock is renewed
+    /// and their panics are caught. Returns the task error extended with the
+    /// compensation failure if any.
+    async fn compensate<S, C>(
+        &self,
+        db: &PgPool,
+        options: &RunOptions<S, C>,
+        err: StepError,
+    ) -> StepError
+    where
+        S: Step<S, C> + Scheduler + 'static,
+        C: Clone + Send + Sync + 'static,
+    {
+        let mut compensated = 0;
+        loop {
+            let entry = match sqlx::query!(
+                r#"
+                SELECT
+                    compensations -> -1 -> 'step' AS step,
+                    decode(compensations -> -1 ->> 'step_bytes', 'hex') AS step_bytes,
+                    (compensations -> -1 ->> 'payload_version')::INT AS payload_version
+                FROM pg_task
+                WHERE id = $1
+                "#,
+                self.id
+            )
+            .fetch_one(db)
+            .await
+            {
+                Ok(entry) => entry,
+                Err(e) => {
+                    return format!(
+                        "{}; the compensation is interrupted: {}",
+                        source_chain::to_string(&*err),
+                        source_chain::to_string(&e)
+                    )
+                    .into()
+                }
+            };
+            let Some(step) = entry.step else {
+                break;
+            };
+
+            debug!("[{}] compensate step {step}", self.id);
+            let transaction = StepTransaction::default();
+            let cancellation = options.shutdown.child_token();
+            let ctx = StepContext::new(
+                db.clone(),
+                self.id,
+                self.tried + 1,
+                self.created_at,
+                cancellation.clone(),
+                transaction.clone(),
+                options.app_context.clone(),
+            )
+            .with_clock(options.clock.clone());
+            let version = entry.payload_version.unwrap_or(S::PAYLOAD_VERSION);
+            let compensation = payload::decode(&step, entry.step_bytes.as_deref(), S::CODEC)
+                .and_then(|value| match migrate_payload::<S>(&value, version) {
+                    Some(step) => Ok(step),
+                    None => payload::deserialize_decoded(&value),
+                });
+            let result = match compensation {
+                Ok(compensation) => {
+                    let compensation_db = db.clone();
+                    let compensation = tokio::spawn(async move {
+                        compensation.run_compensation(&compensation_db, &ctx).await
+                    });
+                    tokio::select! {
+                        result = compensation => result.unwrap_or_else(|e| Err(join_error(e))),
+                        never = self.renew_lock(db, options, &cancellation) => match never {},
+                    }
+                }
+                Err(e) => Err(e.into()),
+            };
+            let tx = transaction.take();
+            if let Err(e) = result {
+                return format!(
+                    "{}; the compensation of step {step} is failed: {}",
+                    source_chain::to_string(&*err),
+                    source_chain::to_string(&*e)
+                )
+                .into();
+            }
+
+            if let Err(e) = self.pop_compensation(db, tx).await {
+                return format!(
+                    "{}; the compensation is interrupted: {}",
+                    source_chain::to_string(&*err),
+                    source_chain::to_string(&e)
+                )
+                .into();
+            }
+            compensated += 1;
+        }
+        if compensated > 0 {
+            info!("[{}] compensated {compensated} completed steps", self.id);
+        }
+        err
+    }
+
+    /// Removes the last compensated step, in the transaction opened by the
+    /// compensation if any
+    async fn pop_compensation(
+        &self,
+        db: &PgPool,
+        tx: Option<PgTransaction<'static>>,
+    ) -> Result<()> {
+        let query = sqlx::query!(
+            "
+            UPDATE pg_task
+            SET compensations = compensations - -1
+            WHERE id = $1
+              AND locked_by = $2
+            ",
+            self.id,
+            self.locked_by,
+        );
+        match tx {
+            Some(mut tx) => {
+                let updated = query
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(db_error!())?
+                    .rows_affected();
+                self.ensure_locked(updated)?;
+                tx.commit().await.map_err(db_error!("commit"))
+            }
+            None => {
+                let updated = query
+                    .execute(db)
+                    .await
+                    .map_err(db_error!())?
+                    .rows_affected();
+                self.ensure_locked(updated)
+            }
+        }
+    }
+
+    /// Updates the tasks step. The current step is recorded for compensation
+    /// if `has_compensation` is set.
+    async fn save_next_step<S: Scheduler, C>(
+        &self,
+        db: &PgPool,
+        con: &mut PgConnection,
+        step: &S,
+        wakeup_at: DateTime<Utc>,
+        has_compensation: bool,
+        options: &RunOptions<S, C>,
+    ) -> Result<()> {
+        let Serialized {
+            value: step,
+            bytes,
+            step_name,
+            ..
+        } = match payload::serialize_with_names(step, S::CODEC) {
             Ok(x) => x,
-            Err(e) => return self.save_error(db, e.into()).await,
+            Err(e) => return self.save_error(db, e.into(), options).await,
         };
         debug!("[{}] moved to the next step {step}", self.id);
 
-        sqlx::query!(
+        let mut tx = con.begin().await.map_err(db_error!("begin"))?;
+        let updated = sqlx::query!(
             "
             UPDATE pg_task
-            SET is_running = false,
+            SET locked_until = NULL,
+                locked_by = NULL,
+                crash_count = 0,
                 tried = 0,
+                progress = NULL,
+                compensations = CASE
+                    WHEN $4 THEN compensations || jsonb_build_array(
+                        jsonb_build_object(
+                            'step', step,
+                            'step_bytes', encode(step_bytes, 'hex'),
+                            'payload_version', payload_version
+                        )
+                    )
+                    ELSE compensations
+                END,
                 step = $2,
+                step_bytes = $10,
+                payload_version = $5,
+                step_started_at = $6,
+                step_finished_at = now(),
+                timings = timings || jsonb_build_array($7::JSONB || jsonb_build_object('finished_at', now())),
+                step_name = $8,
                 wakeup_at = $3
             WHERE id = $1
+              AND locked_by = $9
             ",
             self.id,
-            step,
-            Utc::now() + std_duration_to_chrono(delay),
+            &step,
+            wakeup_at,
+            has_compensation,
+            S::PAYLOAD_VERSION,
+            self.started_at,
+            self.timing_record(),
+            step_name,
+            self.locked_by,
+            bytes,
         )
-        .execute(db)
+        .execute(&mut *tx)
         .await
-        .map_err(db_error!())?;
-        Ok(())
+        .map_err(db_error!())?
+        .rows_affected();
+        self.ensure_locked(updated)?;
+        self.log_transition(&mut *tx, Some(&step), None).await?;
+        tx.commit().await.map_err(db_error!("commit"))
+    }
+
+    /// Schedules the current step to run again at the time
+    async fn save_reschedule(&self, con: &mut PgConnection, at: DateTime<Utc>) -> Result<()> {
+        debug!("[{}] rescheduled the step to {at}", self.id);
+        let updated = sqlx::query!(
+            "
+            UPDATE pg_task
+            SET locked_until = NULL,
+                locked_by = NULL,
+                crash_count = 0,
+                tried = 0,
+                step_started_at = $3,
+                step_finished_at = now(),
+                timings = timings || jsonb_build_array($4::JSONB || jsonb_build_object('finished_at', now())),
+                wakeup_at = $2
+            WHERE id = $1
+              AND locked_by = $5
+            ",
+            self.id,
+            at,
+            self.started_at,
+            self.timing_record(),
+            self.locked_by,
+        )
+        .execute(con)
+        .await
+        .map_err(db_error!())?
+        .rows_affected();
+        self.ensure_locked(updated)
     }
 
-    /// Removes the finished task
-    async fn complete(&self, db: &PgPool) -> Result<()> {
+    /// Updates the tasks step to run after all its children are finished,
+    /// spawning the sub-task as a child in the same transaction if any
+    async fn save_join<S: Scheduler, C>(
+        &self,
+        db: &PgPool,
+        con: &mut PgConnection,
+        step: &S,
+        sub_task: Option<&SubTask>,
+        has_compensation: bool,
+        options: &RunOptions<S, C>,
+    ) -> Result<()> {
+        let Serialized {
+            value: step,
+            bytes,
+            step_name,
+            ..
+        } = match payload::serialize_with_names(step, S::CODEC) {
+            Ok(x) => x,
+            Err(e) => return self.save_error(db, e.into(), options).await,
+        };
+        debug!(
+            "[{}] waits for its children to run the next step {step}",
+            self.id
+        );
+
+        let mut tx = con.begin().await.map_err(db_error!("begin"))?;
+        if let Some(sub_task) = sub_task {
+            let options = InsertOptions {
+                parent_id: Some(self.id),
+                ..InsertOptions::new(options.now())
+            };
+            let id = insert_serialized(&mut *tx, &sub_task.step, sub_task.payload_version, options)
+                .await?;
+            debug!("[{}] called sub-task {id}", self.id);
+        }
+        // The children are checked by the worker picking the task up right away
+        let updated = sqlx::query!(
+            "
+            UPDATE pg_task
+            SET locked_until = NULL,
+                locked_by = NULL,
+                crash_count = 0,
+                tried = 0,
+                progress = NULL,
+                compensations = CASE
+                    WHEN $3 THEN compensations || jsonb_build_array(
+                        jsonb_build_object(
+                            'step', step,
+                            'step_bytes', encode(step_bytes, 'hex'),
+                            'payload_version', payload_version
+                        )
+                    )
+                    ELSE compensations
+                END,
+                step = $2,
+                step_bytes = $10,
+                payload_version = $4,
+                step_started_at = $5,
+                step_finished_at = now(),
+                timings = timings || jsonb_build_array($6::JSONB || jsonb_build_object('finished_at', now())),
+                step_name = $7,
+                is_joining = true,
+                wakeup_at = COALESCE($9::TIMESTAMPTZ, now())
+            WHERE id = $1
+              AND locked_by = $8
+            ",
+            self.id,
+            &step,
+            has_compensation,
+            S::PAYLOAD_VERSION,
+            self.started_at,
+            self.timing_record(),
+            step_name,
+            self.locked_by,
+            options.clock_now(),
+            bytes,
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error!())?
+        .rows_affected();
+        self.ensure_locked(updated)?;
+        self.log_transition(&mut *tx, Some(&step), None).await?;
+        tx.commit().await.map_err(db_error!("commit"))
+    }
+
+    /// Updates the tasks step to run after a signal is received. A pending
+    /// signal received before is consumed instead of waiting. Without
+    /// a timeout the task sleeps until its deadline if any, so it's expired.
+    async fn save_wait_signal<S: Scheduler, C>(
+        &self,
+        db: &PgPool,
+        con: &mut PgConnection,
+        step: &S,
+        timeout: Option<(DateTime<Utc>, &S)>,
+        has_compensation: bool,
+        options: &RunOptions<S, C>,
+    ) -> Result<()> {
+        let Serialized {
+            value: step,
+            bytes,
+            step_name,
+            ..
+        } = match payload::serialize_with_names(step, S::CODEC) {
+            Ok(x) => x,
+            Err(e) => return self.save_error(db, e.into(), options).await,
+        };
+        let (timeout_at, timeout_step) = match timeout {
+            Some((timeout_at, timeout_step)) => {
+                match payload::serialize_with_names(timeout_step, S::CODEC) {
+                    Ok(x) => (Some(timeout_at), Some(x)),
+                    Err(e) => return self.save_error(db, e.into(), options).await,
+                }
+            }
+            None => (None, None),
+        };
+        let (timeout_step, timeout_step_bytes) = match timeout_step {
+            Some(Serialized { value, bytes, .. }) => (Some(value), bytes),
+            None => (None, None),
+        };
+        debug!(
+            "[{}] waits for a signal to run the next step {step}",
+            self.id
+        );
+
+        let mut tx = con.begin().await.map_err(db_error!("begin"))?;
+        let updated = sqlx::query!(
+            "
+            UPDATE pg_task
+            SET locked_until = NULL,
+                locked_by = NULL,
+                crash_count = 0,
+                tried = 0,
+                progress = NULL,
+                compensations = CASE
+                    WHEN $6 THEN compensations || jsonb_build_array(
+                        jsonb_build_object(
+                            'step', step,
+                            'step_bytes', encode(step_bytes, 'hex'),
+                            'payload_version', payload_version
+                        )
+                    )
+                    ELSE compensations
+                END,
+                step = $2,
+                step_bytes = $12,
+                payload_version = $7,
+                step_started_at = $5,
+                step_finished_at = now(),
+                timings = timings || jsonb_build_array($8::JSONB || jsonb_build_object('finished_at', now())),
+                is_awaiting_signal = NOT is_signal_pending,
+                is_signal_pending = false,
+                signal_timeout_step = CASE WHEN NOT is_signal_pending THEN $3::JSONB END,
+                signal_timeout_step_bytes = CASE WHEN NOT is_signal_pending THEN $13::BYTEA END,
+                signal_timeout_payload_version = CASE
+                    WHEN NOT is_signal_pending AND $3::JSONB IS NOT NULL THEN $7::INT
+                END,
+                wakeup_at = CASE
+                    WHEN NOT is_signal_pending
+                        THEN COALESCE($4, deadline_at, $11::TIMESTAMPTZ, now())
+                    ELSE COALESCE($11::TIMESTAMPTZ, now())
+                END,
+                step_name = $9
+            WHERE id = $1
+              AND locked_by = $10
+            ",
+            self.id,
+            &step,
+            timeout_step,
+            timeout_at,
+            self.started_at,
+            has_compensation,
+            S::PAYLOAD_VERSION,
+            self.timing_record(),
+            step_name,
+            self.locked_by,
+            options.clock_now(),
+            bytes,
+            timeout_step_bytes,
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error!())?
+        .rows_affected();
+        self.ensure_locked(updated)?;
+        self.log_transition(&mut *tx, Some(&step), None).await?;
+        tx.commit().await.map_err(db_error!("commit"))
+    }
+
+    /// Removes the finished task or marks it done if `keep_done` is set. A
+    /// child task is marked done while its parent is alive, so the parent
+    /// could get its outcome, and a task with a result is marked done to
+    /// return it. Done children of the task without results are removed unless
+    /// `keep_done` is set.
+    async fn complete<S: Scheduler, C>(
+        &self,
+        con: &mut PgConnection,
+        keep_done: bool,
+        result: Option<serde_json::Value>,
+        spawned: Vec<Serialized>,
+        options: &RunOptions<S, C>,
+    ) -> Result<()> {
+        let mut tx = con.begin().await.map_err(db_error!("begin"))?;
+        if !spawned.is_empty() {
+            let ids = insert_many(
+                &mut *tx,
+                spawned,
+                Vec::new(),
+                S::PAYLOAD_VERSION,
+                Some(self.id),
+            )
+            .await?;
+            debug!("[{}] spawned {} tasks", self.id, ids.len());
+        }
+        let marked_done = sqlx::query!(
+            "
+            UPDATE pg_task
+            SET locked_until = NULL,
+                locked_by = NULL,
+                crash_count = 0,
+                done_at = now(),
+                step_started_at = $4,
+                step_finished_at = now(),
+                timings = timings || jsonb_build_array($5::JSONB || jsonb_build_object('finished_at', now())),
+                result = $3
+            WHERE id = $1
+              AND locked_by = $6
+              AND (
+                  $2
+                  OR $3::JSONB IS NOT NULL
+                  OR EXISTS (
+                      SELECT 1
+                      FROM pg_task parent
+                      WHERE parent.id = pg_task.parent_id
+                        AND parent.done_at IS NULL
+                  )
+              )
+            ",
+            self.id,
+            keep_done,
+            result,
+            self.started_at,
+            self.timing_record(),
+            self.locked_by,
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error!("mark done"))?
+        .rows_affected()
+            > 0;
+        if marked_done {
+            self.delete_blobs(&mut *tx).await?;
+        } else {
+            let deleted = sqlx::query!(
+                "DELETE FROM pg_task WHERE id = $1 AND locked_by = $2",
+                self.id,
+                self.locked_by,
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error!("delete"))?
+            .rows_affected();
+            self.ensure_locked(deleted)?;
+        }
+        if !keep_done {
+            sqlx::query!(
+                "
+                DELETE FROM pg_task
+                WHERE parent_id = $1
+                  AND done_at IS NOT NULL
+                  AND result IS NULL
+                ",
+                self.id
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error!("delete done children"))?;
+        }
+        // Notified in the transaction to be delivered once the task is
+        // committed as finished
+        wait::notify_finished(&mut *tx, self.id, &Outcome::Done, &self.step).await;
+        self.wake_joining_parent(&mut *tx, options.clock_now())
+            .await;
+        self.log_transition(&mut *tx, None, None).await?;
+        tx.commit().await.map_err(db_error!("commit"))?;
         info!("[{}] is successfully completed", self.id);
-        sqlx::query!("DELETE FROM pg_task WHERE id = $1", self.id)
+        options.observers.emit(|| TaskEvent::Done { id: self.id });
+        Ok(())
+    }
+
+    /// Removes the data stored by the task steps with
+    /// [`StepContext::put_blob`], the data of removed tasks is removed by the
+    /// foreign key cascade
+    async fn delete_blobs<'e>(&self, db: impl PgExecutor<'e>) -> Result<()> {
+        sqlx::query!("DELETE FROM pg_task_blob WHERE task_id = $1", self.id)
             .execute(db)
             .await
-            .map_err(db_error!())?;
+            .map_err(db_error!("delete blobs"))?;
+        Ok(())
+    }
+
+    /// Returns an error if the update hasn't found the task locked by the
+    /// worker, e.g. as its lease is expired and another worker has claimed it
+    fn ensure_locked(&self, updated: u64) -> Result<()> {
+        if updated == 0 {
+            return Err(Error::LockLost(self.id));
+        }
         Ok(())
     }
 
+    /// Describes the step error for the `errors` column, the time is added by
+    /// the db
+    fn error_record(&self, err: &(dyn std::error::Error + 'static)) -> serde_json::Value {
+        let mut chain = vec![err.to_string()];
+        let mut source = err.source();
+        while let Some(e) = source {
+            chain.push(e.to_string());
+            source = e.source();
+        }
+        serde_json::json!({
+            "chain": chain,
+            "step": payload::step_name(&self.step, self.step_bytes.as_deref()),
+            "attempt": self.tried + 1,
+        })
+    }
+
+    /// Describes the step run for the `timings` column, the finishing time is
+    /// added by the db
+    fn timing_record(&self) -> serde_json::Value {
+        serde_json::json!({
+            "step": payload::step_name(&self.step, self.step_bytes.as_deref()),
+            "attempt": self.tried + 1,
+            "started_at": self.started_at,
+        })
+    }
+
     /// Schedules the task for retry
-    async fn retry(
+    async fn retry<S, C>(
         &self,
         db: &PgPool,
         tried: i32,
         retry_limit: i32,
         delay: Duration,
         err: StepError,
+        options: &RunOptions<S, C>,
     ) -> Result<()> {
-        let delay = std_duration_to_chrono(delay);
+        let err_str = source_chain::to_string(&*err);
+        options.observers.emit(|| TaskEvent::Retried {
+            id: self.id,
+            step: self.step.clone(),
+            attempt: tried + 1,
+            delay,
+            error: err_str.clone(),
+        });
         debug!(
-            "[{id}] scheduled {attempt} of {retry_limit} retries in {delay:?} on error: {err}",
+            "[{id}] scheduled {attempt} of {retry_limit} retries in {delay:?} on error: {err_str}",
             id = self.id,
             attempt = ordinal(tried + 1),
-            err = source_chain::to_string(&*err),
         );
 
+        retry_transient(|| async {
+            let mut tx = db.begin().await.map_err(db_error!("begin"))?;
+            let updated = sqlx::query!(
+                "
+                UPDATE pg_task
+                SET locked_until = NULL,
+                    locked_by = NULL,
+                    crash_count = 0,
+                    tried = tried + 1,
+                    errors = errors || jsonb_build_array($3::JSONB || jsonb_build_object('at', now())),
+                    step_started_at = $4,
+                    step_finished_at = now(),
+                    timings = timings || jsonb_build_array($5::JSONB || jsonb_build_object('finished_at', now())),
+                    wakeup_at = $2
+                WHERE id = $1
+                  AND locked_by = $6
+                ",
+                self.id,
+                add_delay(options.now(), delay),
+                self.error_record(&*err),
+                self.started_at,
+                self.timing_record(),
+                self.locked_by,
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error!())?
+            .rows_affected();
+            self.ensure_locked(updated)?;
+            self.log_transition(&mut *tx, Some(&self.step), Some(&err_str))
+                .await?;
+            tx.commit().await.map_err(db_error!("commit"))
+        })
+        .await
+    }
+
+    /// Records the step transition into the `pg_task_log` table in the
+    /// transaction saving it, `to_step` is `None` if the task is completed or
+    /// failed
+    #[cfg(feature = "audit-log")]
+    async fn log_transition<'e>(
+        &self,
+        db: impl PgExecutor<'e>,
+        to_step: Option<&serde_json::Value>,
+        error: Option<&str>,
+    ) -> Result<()> {
         sqlx::query!(
             "
-            UPDATE pg_task
-            SET is_running = false,
-                tried = tried + 1,
-                wakeup_at = $2
-            WHERE id = $1
+            INSERT INTO pg_task_log (task_id, from_step, to_step, attempt, error, started_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
             ",
             self.id,
-            Utc::now() + delay,
+            self.step,
+            to_step,
+            self.tried + 1,
+            error,
+            self.started_at,
         )
         .execute(db)
         .await
-        .map_err(db_error!())?;
+        .map_err(db_error!("record the step transition"))?;
+        Ok(())
+    }
 
+    #[cfg(not(feature = "audit-log"))]
+    async fn log_transition<'e>(
+        &self,
+        _db: impl PgExecutor<'e>,
+        _to_step: Option<&serde_json::Value>,
+        _error: Option<&str>,
+    ) -> Result<()> {
         Ok(())
     }
 }
+
+/// Serializes the tasks the step fans out into with [`NextStep::Many`]
+fn serialize_spawned<S: Scheduler>(next: &NextStep<S>) -> Result<Vec<Serialized>> {
+    match next {
+        NextStep::Many(steps) => steps
+            .iter()
+            .map(|step| payload::serialize_with_names(step, S::CODEC))
+            .collect(),
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Converts the error of a spawned step or compensation, e.g. a panic, into a
+/// step error
+fn join_error(e: JoinError) -> StepError {
+    let msg = if e.is_panic() {
+        panic_message(e.into_panic())
+    } else {
+        e.to_string()
+    };
+    Error::StepPanicked(msg).into()
+}
+
+/// Extracts the message of a panic
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    if let Some(msg) = panic.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = panic.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".into()
+    }
+}
+
+/// Upgrades a decoded step stored with an older payload version with
+/// [`Scheduler::migrate_payload`], returns `None` if it's up to date or isn't
+/// migrated
+pub(crate) fn migrate_payload<S: Scheduler>(value: &serde_json::Value, version: i32) -> Option<S> {
+    if version < S::PAYLOAD_VERSION {
+        S::migrate_payload(version, value.clone())
+    } else {
+        None
+    }
+}
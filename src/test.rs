@@ -0,0 +1,137 @@
+//! Helpers for integration tests of tasks
+
+use crate::{
+    payload,
+    task::migrate_payload,
+    util::{add_delay, db_error},
+    Clock, Result, Scheduler, Step, Worker,
+};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::{
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+/// Processes the ready tasks with a default worker until there are none left,
+/// see [`Worker::run_until_idle`]. Call it on a configured worker instead to
+/// pass e.g. an application context.
+///
+/// ```rust,ignore
+/// pg_task::enqueue(&db, &Tasks::Signup(CreateAccount { email }.into())).await?;
+/// pg_task::test::run_until_idle::<Tasks>(&db).await?;
+/// assert_eq!(accounts_count(&db).await?, 1);
+/// ```
+pub async fn run_until_idle<S: Step<S> + Scheduler + 'static>(db: &PgPool) -> Result<usize> {
+    Worker::<S>::new(db.clone()).run_until_idle().await
+}
+
+/// Returns the pending tasks, i.e. not finished, failed, expired or cancelled
+/// ones, in the order they were enqueued, so a test could check what was
+/// enqueued without parsing the `step` column. The tasks are decoded with the
+/// scheduler codec and upgraded with [`Scheduler::migrate_payload`]. Pass a
+/// `task_name`, i.e. a variant of the scheduler enum, to get only the tasks of
+/// it.
+///
+/// ```rust,ignore
+/// let emails = pg_task::test::enqueued_tasks::<Tasks>(&db, Some("Mailer")).await?;
+/// assert_eq!(emails.len(), 2);
+/// ```
+pub async fn enqueued_tasks<S: Scheduler>(db: &PgPool, task_name: Option<&str>) -> Result<Vec<S>> {
+    let rows = sqlx::query!(
+        "
+        SELECT step, step_bytes, payload_version
+        FROM pg_task
+        WHERE done_at IS NULL
+          AND error IS NULL
+          AND expired_at IS NULL
+          AND cancelled_at IS NULL
+          AND ($1::TEXT IS NULL OR task_name = $1)
+        ORDER BY created_at, id
+        ",
+        task_name,
+    )
+    .fetch_all(db)
+    .await
+    .map_err(db_error!())?;
+    let mut tasks = Vec::with_capacity(rows.len());
+    for row in rows {
+        let value = payload::decode(&row.step, row.step_bytes.as_deref(), S::CODEC)?;
+        let task = match migrate_payload(&value, row.payload_version) {
+            Some(task) => task,
+            None => payload::deserialize_decoded(&value)?,
+        };
+        tasks.push(task);
+    }
+    Ok(tasks)
+}
+
+/// Returns the first pending task matching the `matcher`, see
+/// [`enqueued_tasks`]
+///
+/// ```rust,ignore
+/// pg_task::test::assert_enqueued::<Tasks>(&db, |t| {
+///     matches!(t, Tasks::Mailer(Mailer::SendEmail(SendEmail { to, .. })) if to == "a@b.c")
+/// })
+/// .await?;
+/// ```
+///
+/// # Panics
+///
+/// Panics listing the pending tasks if none of them match
+pub async fn assert_enqueued<S: Scheduler>(db: &PgPool, matcher: impl Fn(&S) -> bool) -> Result<S> {
+    let mut tasks = enqueued_tasks::<S>(db, None).await?;
+    match tasks.iter().position(matcher) {
+        Some(i) => Ok(tasks.swap_remove(i)),
+        None => panic!("no matching task is enqueued, the pending tasks are: {tasks:#?}"),
+    }
+}
+
+/// A clock advanced by hand to test delayed steps and retries without
+/// waiting, see [`Worker::with_clock`]. It starts at the current time and its
+/// clones share the time.
+///
+/// ```rust,ignore
+/// let clock = ManualClock::new();
+/// let worker = Worker::<Tasks>::new(db.clone()).with_clock(clock.clone());
+/// worker.run_until_idle().await?;
+/// clock.advance(Duration::from_secs(6 * 60 * 60));
+/// worker.run_until_idle().await?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct ManualClock(Arc<Mutex<DateTime<Utc>>>);
+
+impl ManualClock {
+    /// Creates a clock set to the current time
+    pub fn new() -> Self {
+        Self::starting_at(Utc::now())
+    }
+
+    /// Creates a clock set to the time
+    pub fn starting_at(now: DateTime<Utc>) -> Self {
+        Self(Arc::new(Mutex::new(now)))
+    }
+
+    /// Moves the clock forward
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        *now = add_delay(*now, duration);
+    }
+
+    /// Sets the clock to the time
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = now;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,47 @@
+use serde_json::Value;
+use sqlx::types::Uuid;
+use tracing::Span;
+
+/// Returns the trace context of the current span to store it with a new task.
+/// It's always `None` without the `opentelemetry` feature.
+pub fn current_context() -> Option<Value> {
+    #[cfg(feature = "opentelemetry")]
+    {
+        use std::collections::HashMap;
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        let mut carrier = HashMap::<String, String>::new();
+        let cx = Span::current().context();
+        opentelemetry::global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(&cx, &mut carrier)
+        });
+        if carrier.is_empty() {
+            return None;
+        }
+        serde_json::to_value(carrier).ok()
+    }
+    #[cfg(not(feature = "opentelemetry"))]
+    None
+}
+
+/// Creates a span to run a step in, it's a child of the stored trace context
+/// of the task if there's one
+pub fn step_span(task_id: Uuid, trace_context: Option<&Value>) -> Span {
+    let span = tracing::info_span!("pg_task step", task_id = %task_id);
+    #[cfg(feature = "opentelemetry")]
+    if let Some(carrier) = trace_context.and_then(|x| {
+        serde_json::from_value::<std::collections::HashMap<String, String>>(x.clone()).ok()
+    }) {
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        let cx = opentelemetry::global::get_text_map_propagator(|propagator| {
+            propagator.extract(&carrier)
+        });
+        if let Err(e) = span.set_parent(cx) {
+            tracing::warn!("[{task_id}] can't set the parent trace context: {e}");
+        }
+    }
+    #[cfg(not(feature = "opentelemetry"))]
+    let _ = trace_context;
+    span
+}
@@ -1,15 +1,24 @@
-use crate::{util::std_duration_to_chrono, Error, StepResult};
+use crate::{
+    payload::{self, Serialized},
+    trace,
+    util::std_duration_to_pg_interval,
+    Codec, EnqueueOptions, Error, Json, NextStep, RetryPolicy, RetryStrategy, StepContext,
+    StepError, StepResult, TaskHandle,
+};
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use serde::{de::DeserializeOwned, Serialize};
 use sqlx::{types::Uuid, PgExecutor, PgPool};
-use std::{fmt, time::Duration};
+use std::{fmt, future::Future, marker::PhantomData, pin::Pin, time::Duration};
 
-/// A tait to implement on each task step
+/// A tait to implement on each task step. The steps of a task get the same
+/// application context `C` passed to the worker with
+/// [`Worker::with_context`](crate::Worker::with_context), it's `()` by default.
 #[async_trait]
-pub trait Step<Task>
+pub trait Step<Task, C = ()>
 where
     Task: Sized,
+    C: Clone + Send + Sync + 'static,
     Self: Into<Task> + Send + Sized + fmt::Debug + DeserializeOwned + Serialize,
 {
     /// How many times retry_limit the step on an error
@@ -18,8 +27,47 @@ where
     /// The time to wait between retries
     const RETRY_DELAY: Duration = Duration::from_secs(1);
 
+    /// Calculates the time to wait between retries, by default it's the fixed
+    /// `RETRY_DELAY`
+    const RETRY_STRATEGY: RetryStrategy = RetryStrategy::Fixed(Self::RETRY_DELAY);
+
+    /// Randomizes retry delays by up to this fraction, e.g. `0.2` makes them
+    /// vary within ±20%, so tasks failed together don't retry all at once
+    const RETRY_JITTER: f32 = 0.0;
+
+    /// The maximum time for the step to run, after it the step is aborted and
+    /// considered failed. It's restarted by each
+    /// [`StepContext::heartbeat`](crate::StepContext::heartbeat).
+    const TIMEOUT: Option<Duration> = None;
+
+    /// The maximum number of the steps of this type running at once on a
+    /// worker, independently of the worker concurrency. It should be positive,
+    /// otherwise the task enum doesn't compile.
+    const MAX_CONCURRENCY: Option<usize> = None;
+
+    /// Set it to run at most one step of this type at once across all the
+    /// workers, e.g. for rebuilding a search index. It's enforced with a
+    /// Postgres advisory lock, the steps waiting for it occupy their worker
+    /// slots.
+    const SINGLETON: bool = false;
+
+    /// The maximum number of the steps of this type started per second across
+    /// all the workers, e.g. to respect the rate limit of an external API. It
+    /// could be fractional, e.g. `0.5` is a step per two seconds. It's a token
+    /// bucket stored in Postgres allowing bursts of up to a second worth of
+    /// steps, the steps waiting for a token occupy their worker slots. It
+    /// should be a positive finite number, otherwise the task enum doesn't
+    /// compile.
+    const RATE_LIMIT: Option<f64> = None;
+
     /// Processes the current step and returns the next if any
-    async fn step(self, db: &PgPool) -> StepResult<Task>;
+    async fn step(self, db: &PgPool, ctx: &StepContext<C>) -> StepResult<Task>;
+
+    /// Runs [`Compensate::compensate`] of the step if it's implemented,
+    /// doesn't mean to be changed in impls
+    async fn run_compensation(self, _db: &PgPool, _ctx: &StepContext<C>) -> Result<(), StepError> {
+        Ok(())
+    }
 
     /// Proxies the `RETRY` const, doesn't mean to be changed in impls
     fn retry_limit(&self) -> i32 {
@@ -30,20 +78,436 @@ where
     fn retry_delay(&self) -> Duration {
         Self::RETRY_DELAY
     }
+
+    /// Proxies the `RETRY_STRATEGY` const, doesn't mean to be changed in impls
+    fn retry_strategy(&self) -> RetryStrategy {
+        Self::RETRY_STRATEGY
+    }
+
+    /// Proxies the `RETRY_JITTER` const, doesn't mean to be changed in impls
+    fn retry_jitter(&self) -> f32 {
+        Self::RETRY_JITTER
+    }
+
+    /// Proxies the `TIMEOUT` const, doesn't mean to be changed in impls
+    fn timeout(&self) -> Option<Duration> {
+        Self::TIMEOUT
+    }
+
+    /// Proxies the `MAX_CONCURRENCY` const, doesn't mean to be changed in impls
+    fn max_concurrency(&self) -> Option<usize> {
+        const {
+            assert!(
+                !matches!(Self::MAX_CONCURRENCY, Some(0)),
+                "MAX_CONCURRENCY should be positive"
+            );
+        }
+        Self::MAX_CONCURRENCY
+    }
+
+    /// Proxies the `SINGLETON` const, doesn't mean to be changed in impls
+    fn is_singleton(&self) -> bool {
+        Self::SINGLETON
+    }
+
+    /// Proxies the `RATE_LIMIT` const, doesn't mean to be changed in impls
+    fn rate_limit(&self) -> Option<f64> {
+        const {
+            if let Some(rate) = Self::RATE_LIMIT {
+                assert!(
+                    rate.is_finite() && rate > 0.0,
+                    "RATE_LIMIT should be a positive finite number"
+                );
+            }
+        }
+        Self::RATE_LIMIT
+    }
+
+    /// Tells if the step implements [`Compensate`], doesn't mean to be
+    /// changed in impls
+    fn has_compensation(&self) -> bool {
+        false
+    }
+
+    /// Returns the type name of the current step, doesn't mean to be changed
+    /// in impls
+    fn step_type(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+}
+
+/// Undoes the effects of a completed step, e.g. refunds a payment, when a
+/// later step of the task fails after all the retries. Compensations of the
+/// completed steps run in the reverse order. The steps implementing it are
+/// recorded on completion by [`task!`](crate::task), dynamic steps should be
+/// registered with
+/// [`StepRegistry::register_compensated`](crate::StepRegistry::register_compensated).
+#[async_trait]
+pub trait Compensate<Task, C = ()>: Step<Task, C>
+where
+    C: Clone + Send + Sync + 'static,
+{
+    /// Undoes the effects of the step
+    async fn compensate(self, db: &PgPool, ctx: &StepContext<C>) -> Result<(), StepError>;
+}
+
+/// A future of a step compensation
+type CompensationFuture<'a> = Pin<Box<dyn Future<Output = Result<(), StepError>> + Send + 'a>>;
+
+/// Tells if a step of a task enum implements [`Compensate`], it's used by
+/// [`task!`](crate::task). The methods of [`ViaCompensate`] are picked for the
+/// steps implementing it, and the ones of [`ViaStep`] behind one more
+/// reference otherwise, e.g. for nested task enums dispatching to their steps.
+#[doc(hidden)]
+pub struct CompensationProbe<Task, C, S>(PhantomData<(Task, C, S)>);
+
+impl<Task, C, S> CompensationProbe<Task, C, S> {
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<Task, C, S> Default for CompensationProbe<Task, C, S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[doc(hidden)]
+pub trait ViaCompensate<C, S> {
+    fn is_compensated(&self) -> bool;
+
+    fn compensate<'a>(
+        &self,
+        step: S,
+        db: &'a PgPool,
+        ctx: &'a StepContext<C>,
+    ) -> Result<CompensationFuture<'a>, S>;
+}
+
+impl<Task, C, S> ViaCompensate<C, S> for CompensationProbe<Task, C, S>
+where
+    C: Clone + Send + Sync + 'static,
+    S: Compensate<Task, C> + 'static,
+{
+    fn is_compensated(&self) -> bool {
+        true
+    }
+
+    fn compensate<'a>(
+        &self,
+        step: S,
+        db: &'a PgPool,
+        ctx: &'a StepContext<C>,
+    ) -> Result<CompensationFuture<'a>, S> {
+        Ok(Compensate::compensate(step, db, ctx))
+    }
+}
+
+/// Returns the step back to be compensated by its own
+/// [`Step::run_compensation`]
+#[doc(hidden)]
+pub trait ViaStep<C, S> {
+    fn is_compensated(&self) -> bool;
+
+    fn compensate<'a>(
+        &self,
+        step: S,
+        db: &'a PgPool,
+        ctx: &'a StepContext<C>,
+    ) -> Result<CompensationFuture<'a>, S>;
+}
+
+impl<Task, C, S> ViaStep<C, S> for &CompensationProbe<Task, C, S> {
+    fn is_compensated(&self) -> bool {
+        false
+    }
+
+    fn compensate<'a>(
+        &self,
+        step: S,
+        _db: &'a PgPool,
+        _ctx: &'a StepContext<C>,
+    ) -> Result<CompensationFuture<'a>, S> {
+        Err(step)
+    }
+}
+
+/// A simplified step of a linear task created by [`chain!`](crate::chain)
+#[async_trait]
+pub trait ChainStep<C = ()>: Send + Sized
+where
+    C: Clone + Send + Sync + 'static,
+{
+    /// The next step of the chain, `()` for the last one
+    type Next;
+
+    /// Processes the current step and returns the next one
+    async fn run(self, db: &PgPool, ctx: &StepContext<C>) -> Result<Self::Next, StepError>;
+}
+
+/// A step doing CPU-bound work, e.g. image processing or PDF generation. It's
+/// run on the blocking threads pool with `tokio::task::spawn_blocking`, so it
+/// doesn't starve the async runtime. [`blocking_step!`](crate::blocking_step)
+/// implements [`Step`] for it. A step aborted by its timeout keeps running
+/// on its thread until it returns.
+pub trait BlockingStep<Task, C = ()>: Send + Sized + 'static {
+    /// Processes the current step and returns the next if any
+    fn run(self, db: &PgPool, ctx: &StepContext<C>) -> StepResult<Task>;
+}
+
+/// Runs the blocking step on the blocking threads pool, it's used by
+/// [`blocking_step!`](crate::blocking_step)
+#[doc(hidden)]
+pub async fn run_blocking<Task, C, S>(
+    step: S,
+    db: &PgPool,
+    ctx: &StepContext<C>,
+) -> StepResult<Task>
+where
+    Task: Send + 'static,
+    C: Clone + Send + Sync + 'static,
+    S: BlockingStep<Task, C>,
+{
+    let (db, ctx) = (db.clone(), ctx.clone());
+    match tokio::task::spawn_blocking(move || step.run(&db, &ctx)).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// A step processing a large data set in chunks, e.g. a million rows a
+/// thousand at a time. Each run of the step processes a chunk after the cursor
+/// kept in the step, then the step is rescheduled with the advanced cursor, so
+/// an error or a worker crash only repeats the current chunk.
+/// [`chunked_step!`](crate::chunked_step) implements [`Step`] for it.
+#[async_trait]
+pub trait ChunkedStep<Task, C = ()>: Into<Task> + Send + Sized
+where
+    C: Clone + Send + Sync + 'static,
+{
+    /// The time to wait between the chunks, e.g. to leave room for other
+    /// queries to the db
+    const CHUNK_DELAY: Duration = Duration::ZERO;
+
+    /// Returns the progress of the step by its cursor, it's reported with
+    /// [`StepContext::report_progress`] before processing each chunk
+    fn progress(&self) -> ChunkProgress;
+
+    /// Processes the chunk after the cursor and advances it, returns `false`
+    /// if there was nothing left to process
+    async fn process_chunk(&mut self, db: &PgPool, ctx: &StepContext<C>)
+        -> Result<bool, StepError>;
+
+    /// Returns the next step after all the chunks are processed
+    fn finish(self) -> StepResult<Task> {
+        NextStep::none()
+    }
+}
+
+/// Progress of a [`ChunkedStep`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChunkProgress {
+    /// The number of the processed items
+    pub done: i64,
+    /// The total number of the items
+    pub total: i64,
+}
+
+impl ChunkProgress {
+    /// Returns the processed share of the items in percents, it's 100 if
+    /// there are no items at all
+    pub fn percent(&self) -> f64 {
+        if self.total <= 0 {
+            return 100.0;
+        }
+        (self.done as f64 * 100.0 / self.total as f64).min(100.0)
+    }
+}
+
+/// Processes a chunk of the chunked step and reschedules it, it's used by
+/// [`chunked_step!`](crate::chunked_step)
+#[doc(hidden)]
+pub async fn run_chunk<Task, C, S>(
+    mut step: S,
+    db: &PgPool,
+    ctx: &StepContext<C>,
+) -> StepResult<Task>
+where
+    C: Clone + Send + Sync + 'static,
+    S: ChunkedStep<Task, C>,
+{
+    let progress = step.progress();
+    ctx.report_progress(progress.done, progress.total).await?;
+    if !step.process_chunk(db, ctx).await? {
+        return step.finish();
+    }
+    if S::CHUNK_DELAY.is_zero() {
+        NextStep::now(step)
+    } else {
+        NextStep::delay(step, S::CHUNK_DELAY)
+    }
 }
 
 /// A tait to implement on the outer enum wrapper containing all the tasks
 #[async_trait]
 pub trait Scheduler: fmt::Debug + DeserializeOwned + Serialize + Sized + Sync {
-    /// Enqueues the task to be run immediately
-    async fn enqueue<'e>(&self, db: impl PgExecutor<'e>) -> crate::Result<Uuid> {
+    /// Version of the steps payload format stored with each step. Increase it
+    /// on incompatible changes of the steps, e.g. renaming a variant, and
+    /// upgrade the payloads stored with older versions in
+    /// [`Self::migrate_payload`]. Workers don't claim tasks stored with a
+    /// newer version, so increase it on adding variants too, then old workers
+    /// skip the new ones during rolling deploys.
+    const PAYLOAD_VERSION: i32 = 0;
+
+    /// The format the steps are stored in, e.g. `Cbor` to store binary-heavy
+    /// states compactly. The steps stored in the built-in formats stay
+    /// readable after changing it.
+    const CODEC: &'static dyn Codec = &Json;
+
+    /// The maximum number of pending tasks of this kind, i.e. unfinished and
+    /// not failed ones with the same task name. Enqueueing more returns
+    /// [`Error::QueueFull`](crate::Error::QueueFull), protecting the db and
+    /// downstream systems from producer bursts.
+    fn max_pending(&self) -> Option<i64> {
+        None
+    }
+
+    /// The maximum number of pending tasks of a tenant, see
+    /// [`Self::enqueue_for_tenant`]. Enqueueing more returns
+    /// [`Error::TenantQueueFull`](crate::Error::TenantQueueFull), so a noisy
+    /// tenant can't flood the queue.
+    fn max_pending_per_tenant(&self) -> Option<i64> {
+        None
+    }
+
+    /// Upgrades a step payload stored with an older `PAYLOAD_VERSION` before
+    /// running it. The payload is passed as it was serialized, returning
+    /// `None` deserializes it as is.
+    fn migrate_payload(_version: i32, _payload: serde_json::Value) -> Option<Self> {
+        None
+    }
+
+    /// Names of the tasks stored in the `task_name` column which workers of
+    /// this enum claim, i.e. the names of its variants, so workers of
+    /// different enums could share a table without claiming each other's
+    /// tasks. It's implemented by [`scheduler!`](crate::scheduler), implement
+    /// it too if you implement the trait manually. `None` claims tasks of any
+    /// name.
+    fn task_names() -> Option<Vec<String>> {
+        None
+    }
+
+    /// Enqueues the task to be run immediately, returns its handle
+    async fn enqueue<'e>(&self, db: impl PgExecutor<'e>) -> crate::Result<TaskHandle<Self>> {
         self.schedule(db, Utc::now()).await
     }
 
+    /// Enqueues the task with the options combining its time, priority,
+    /// deduplication key, retry settings and the rest, see [`EnqueueOptions`]
+    async fn enqueue_with<'e>(
+        &self,
+        db: impl PgExecutor<'e>,
+        options: EnqueueOptions,
+    ) -> crate::Result<TaskHandle<Self>> {
+        insert(db, self, options.into_insert())
+            .await
+            .map(TaskHandle::new)
+    }
+
     /// Schedules a task to be run after a specified delay
-    async fn delay<'e>(&self, db: impl PgExecutor<'e>, delay: Duration) -> crate::Result<Uuid> {
-        let delay = std_duration_to_chrono(delay);
-        self.schedule(db, Utc::now() + delay).await
+    async fn delay<'e>(
+        &self,
+        db: impl PgExecutor<'e>,
+        delay: Duration,
+    ) -> crate::Result<TaskHandle<Self>> {
+        self.enqueue_with(db, EnqueueOptions::new().with_delay(delay))
+            .await
+    }
+
+    /// Enqueues the task to be run immediately with the given priority. Ready
+    /// tasks with higher priority are run first, the default priority is 0.
+    async fn enqueue_with_priority<'e>(
+        &self,
+        db: impl PgExecutor<'e>,
+        priority: i32,
+    ) -> crate::Result<TaskHandle<Self>> {
+        self.enqueue_with(db, EnqueueOptions::new().with_priority(priority))
+            .await
+    }
+
+    /// Enqueues the task to be run immediately with the given metadata, e.g.
+    /// `json!({"tenant": 42})`. It's stored in the `metadata` column to
+    /// filter and attribute tasks without looking into their steps.
+    async fn enqueue_with_meta<'e>(
+        &self,
+        db: impl PgExecutor<'e>,
+        metadata: serde_json::Value,
+    ) -> crate::Result<TaskHandle<Self>> {
+        self.enqueue_with(db, EnqueueOptions::new().with_metadata(metadata))
+            .await
+    }
+
+    /// Enqueues the task to be run immediately unless there's already a
+    /// pending task with the same deduplication `key`, e.g. "sync-user-123".
+    /// Returns the handle of the new or the existing task. The key is released
+    /// once the task is done, failed or expired.
+    async fn enqueue_unique<'e>(
+        &self,
+        db: impl PgExecutor<'e>,
+        key: &str,
+    ) -> crate::Result<TaskHandle<Self>> {
+        self.enqueue_with(db, EnqueueOptions::new().with_unique_key(key))
+            .await
+    }
+
+    /// Enqueues the task of the `tenant` to be run immediately. Children of
+    /// the task inherit its tenant. Workers can limit the number of running
+    /// tasks of each tenant with
+    /// [`Worker::with_tenant_concurrency`](crate::Worker::with_tenant_concurrency).
+    async fn enqueue_for_tenant<'e>(
+        &self,
+        db: impl PgExecutor<'e>,
+        tenant: &str,
+    ) -> crate::Result<TaskHandle<Self>> {
+        self.enqueue_with(db, EnqueueOptions::new().with_tenant(tenant))
+            .await
+    }
+
+    /// Enqueues the task to be run immediately in the group of the `key`, e.g.
+    /// "order:123". Tasks of a group run one at a time in the enqueue order
+    /// across all the workers, the next task isn't started until the previous
+    /// one is finished, failed or expired. Children of the task don't belong
+    /// to its group.
+    async fn enqueue_in_group<'e>(
+        &self,
+        db: impl PgExecutor<'e>,
+        key: &str,
+    ) -> crate::Result<TaskHandle<Self>> {
+        self.enqueue_with(db, EnqueueOptions::new().with_group(key))
+            .await
+    }
+
+    /// Enqueues many tasks to be run immediately using a single insert, which
+    /// is much faster than enqueueing them one by one. Returns handles of the
+    /// tasks in the same order. None of the tasks are enqueued if they would
+    /// exceed [`Self::max_pending`] of any kind.
+    async fn enqueue_many<'e>(
+        db: impl PgExecutor<'e>,
+        tasks: impl IntoIterator<Item = Self> + Send,
+    ) -> crate::Result<Vec<TaskHandle<Self>>>
+    where
+        Self: Send,
+    {
+        let mut serialized = Vec::new();
+        let mut max_pending = Vec::new();
+        for task in tasks {
+            serialized.push(payload::serialize_with_names(&task, Self::CODEC)?);
+            max_pending.push(task.max_pending());
+        }
+        let ids = insert_many(db, serialized, max_pending, Self::PAYLOAD_VERSION, None).await?;
+        Ok(ids.into_iter().map(TaskHandle::new).collect())
     }
 
     /// Schedules a task to run at a specified time in the future
@@ -51,17 +515,269 @@ pub trait Scheduler: fmt::Debug + DeserializeOwned + Serialize + Sized + Sync {
         &self,
         db: impl PgExecutor<'e>,
         at: DateTime<Utc>,
-    ) -> crate::Result<Uuid> {
-        let step = serde_json::to_string(self)
-            .map_err(|e| Error::SerializeStep(e, format!("{self:?}")))?;
-        sqlx::query!(
-            "INSERT INTO pg_task (step, wakeup_at) VALUES ($1, $2) RETURNING id",
-            step,
-            at
+    ) -> crate::Result<TaskHandle<Self>> {
+        self.enqueue_with(db, EnqueueOptions::new().with_at(at))
+            .await
+    }
+
+    /// Schedules a task to run at a specified time, unless it's too late. If
+    /// the task isn't finished by the `deadline`, its next step isn't run and
+    /// the task is marked expired instead.
+    async fn schedule_with_deadline<'e>(
+        &self,
+        db: impl PgExecutor<'e>,
+        at: DateTime<Utc>,
+        deadline: DateTime<Utc>,
+    ) -> crate::Result<TaskHandle<Self>> {
+        self.enqueue_with(
+            db,
+            EnqueueOptions::new().with_at(at).with_deadline(deadline),
         )
-        .map(|r| r.id)
-        .fetch_one(db)
         .await
-        .map_err(Error::AddTask)
+    }
+}
+
+/// Column values of a new task
+pub(crate) struct InsertOptions {
+    pub wakeup_at: DateTime<Utc>,
+    pub priority: i32,
+    pub metadata: serde_json::Value,
+    pub unique_key: Option<String>,
+    pub trace_context: Option<serde_json::Value>,
+    pub parent_id: Option<Uuid>,
+    pub retry: RetryPolicy,
+    pub deadline_at: Option<DateTime<Utc>>,
+    pub tenant: Option<String>,
+    pub group_key: Option<String>,
+    pub max_pending: Option<i64>,
+    pub max_pending_per_tenant: Option<i64>,
+}
+
+impl InsertOptions {
+    pub fn new(wakeup_at: DateTime<Utc>) -> Self {
+        Self {
+            wakeup_at,
+            priority: 0,
+            metadata: serde_json::json!({}),
+            unique_key: None,
+            trace_context: trace::current_context(),
+            parent_id: None,
+            retry: RetryPolicy::default(),
+            deadline_at: None,
+            tenant: None,
+            group_key: None,
+            max_pending: None,
+            max_pending_per_tenant: None,
+        }
+    }
+}
+
+/// Inserts the task into the table. If there's already a task with the same
+/// unique key, returns its id instead.
+pub(crate) async fn insert<'e, T: Scheduler>(
+    db: impl PgExecutor<'e>,
+    task: &T,
+    options: InsertOptions,
+) -> crate::Result<Uuid> {
+    let step = payload::serialize_with_names(task, T::CODEC)?;
+    let options = InsertOptions {
+        max_pending: task.max_pending(),
+        max_pending_per_tenant: task.max_pending_per_tenant(),
+        ..options
+    };
+    insert_serialized(db, &step, T::PAYLOAD_VERSION, options).await
+}
+
+/// Inserts many serialized tasks into the table using a single insert, the
+/// tasks get the tenant of the `tenant_of` task if it's set. Nothing is
+/// inserted if the tasks would exceed the [`Scheduler::max_pending`] of their
+/// name, `max_pending` holds the limits in the order of the tasks and could be
+/// shorter than them for the unlimited ones.
+pub(crate) async fn insert_many<'e>(
+    db: impl PgExecutor<'e>,
+    serialized: Vec<Serialized>,
+    max_pending: Vec<Option<i64>>,
+    payload_version: i32,
+    tenant_of: Option<Uuid>,
+) -> crate::Result<Vec<Uuid>> {
+    let mut steps = Vec::with_capacity(serialized.len());
+    let mut step_bytes = Vec::with_capacity(serialized.len());
+    let mut task_names = Vec::with_capacity(serialized.len());
+    let mut step_names = Vec::with_capacity(serialized.len());
+    for s in serialized {
+        steps.push(s.value);
+        step_bytes.push(s.bytes);
+        task_names.push(s.task_name);
+        step_names.push(s.step_name);
+    }
+    let inserted = sqlx::query!(
+        r#"
+        WITH batch AS (
+            SELECT *
+            FROM UNNEST($1::JSONB[], $7::BYTEA[], $2::TEXT[], $3::TEXT[], $8::BIGINT[])
+                WITH ORDINALITY AS t(step, step_bytes, task_name, step_name, max_pending, n)
+        ),
+        capped AS (
+            SELECT task_name, COUNT(*) AS count, MIN(max_pending) AS max_pending
+            FROM batch
+            WHERE max_pending IS NOT NULL
+            GROUP BY task_name
+        ),
+        full_queue AS (
+            SELECT capped.task_name
+            FROM capped
+            CROSS JOIN LATERAL (
+                SELECT COUNT(*) AS count
+                FROM (
+                    SELECT 1
+                    FROM pg_task
+                    WHERE task_name = capped.task_name
+                      AND done_at IS NULL
+                      AND error IS NULL
+                      AND expired_at IS NULL
+                    LIMIT capped.max_pending
+                ) AS t
+            ) AS pending
+            WHERE pending.count + capped.count > capped.max_pending
+            LIMIT 1
+        ),
+        inserted AS (
+            INSERT INTO pg_task (
+                step, step_bytes, task_name, step_name, trace_context, payload_version, tenant
+            )
+            SELECT
+                step, step_bytes, task_name, step_name, $4, $5,
+                (SELECT tenant FROM pg_task WHERE id = $6)
+            FROM batch
+            WHERE NOT EXISTS (SELECT 1 FROM full_queue)
+            ORDER BY n
+            RETURNING id
+        )
+        SELECT id AS "id?", NULL AS "full_queue?: String" FROM inserted
+        UNION ALL
+        SELECT NULL, task_name FROM full_queue
+        "#,
+        &steps,
+        &task_names,
+        &step_names,
+        trace::current_context(),
+        payload_version,
+        tenant_of,
+        &step_bytes as &[Option<Vec<u8>>],
+        &max_pending as &[Option<i64>],
+    )
+    .fetch_all(db)
+    .await
+    .map_err(Error::AddTask)?;
+    inserted
+        .into_iter()
+        .map(|row| match (row.id, row.full_queue) {
+            (Some(id), _) => Ok(id),
+            (None, full_queue) => Err(Error::QueueFull(full_queue.unwrap_or_default())),
+        })
+        .collect()
+}
+
+/// Inserts the serialized task into the table, see [`insert`]
+pub(crate) async fn insert_serialized<'e>(
+    db: impl PgExecutor<'e>,
+    step: &Serialized,
+    payload_version: i32,
+    options: InsertOptions,
+) -> crate::Result<Uuid> {
+    let inserted = sqlx::query!(
+        r#"
+        WITH tenant AS (
+            SELECT COALESCE($15, (SELECT tenant FROM pg_task WHERE id = $8)) AS name
+        ),
+        pending AS (
+            SELECT COUNT(*) AS count
+            FROM (
+                SELECT 1
+                FROM pg_task
+                WHERE $14::BIGINT IS NOT NULL
+                  AND task_name = $7
+                  AND done_at IS NULL
+                  AND error IS NULL
+                  AND expired_at IS NULL
+                LIMIT $14
+            ) AS t
+        ),
+        tenant_pending AS (
+            SELECT COUNT(*) AS count
+            FROM (
+                SELECT 1
+                FROM pg_task, tenant
+                WHERE $16::BIGINT IS NOT NULL
+                  AND pg_task.tenant = tenant.name
+                  AND done_at IS NULL
+                  AND error IS NULL
+                  AND expired_at IS NULL
+                LIMIT $16
+            ) AS t
+        ),
+        inserted AS (
+            INSERT INTO pg_task (
+                step, task_name, wakeup_at, priority, metadata, unique_key, trace_context,
+                parent_id, payload_version, retry_limit, retry_delay, deadline_at, step_name,
+                tenant, group_key, group_seq, step_bytes
+            )
+            SELECT
+                $1, $7, $2, $3, $4, $5, $6, $8, $9, $10, $11, $12, $13, tenant.name, $17::TEXT,
+                CASE WHEN $17 IS NOT NULL THEN nextval('pg_task_group_seq') END, $18::BYTEA
+            FROM tenant, pending, tenant_pending
+            WHERE ($14 IS NULL OR pending.count < $14)
+              AND ($16 IS NULL OR tenant_pending.count < $16)
+            ON CONFLICT (unique_key)
+                WHERE unique_key IS NOT NULL
+                  AND done_at IS NULL
+                  AND error IS NULL
+                  AND expired_at IS NULL
+                DO NOTHING
+            RETURNING id
+        )
+        SELECT id AS "id?", NULL AS "full_tenant?: String" FROM inserted
+        UNION ALL
+        SELECT id, NULL
+        FROM pg_task
+        WHERE unique_key = $5
+          AND done_at IS NULL
+          AND error IS NULL
+          AND expired_at IS NULL
+        UNION ALL
+        SELECT NULL, NULL FROM pending WHERE pending.count >= $14
+        UNION ALL
+        SELECT NULL, tenant.name FROM tenant, tenant_pending WHERE tenant_pending.count >= $16
+        LIMIT 1
+        "#,
+        step.value,
+        options.wakeup_at,
+        options.priority,
+        options.metadata,
+        options.unique_key,
+        options.trace_context,
+        step.task_name,
+        options.parent_id,
+        payload_version,
+        options.retry.limit,
+        options.retry.delay.map(std_duration_to_pg_interval),
+        options.deadline_at,
+        step.step_name,
+        options.max_pending,
+        options.tenant,
+        options.max_pending_per_tenant,
+        options.group_key,
+        step.bytes.as_deref(),
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(Error::AddTask)?
+    // The conflicting task was committed after the statement has started and
+    // isn't visible for it, the case is too rare to retry
+    .ok_or(Error::AddTask(sqlx::Error::RowNotFound))?;
+    match (inserted.id, inserted.full_tenant) {
+        (Some(id), _) => Ok(id),
+        (None, Some(tenant)) => Err(Error::TenantQueueFull(tenant)),
+        (None, None) => Err(Error::QueueFull(step.task_name.clone())),
     }
 }
@@ -11,6 +11,50 @@ pub fn std_duration_to_chrono(std_duration: std::time::Duration) -> chrono::Dura
     chrono::Duration::from_std(std_duration).unwrap_or(chrono::Duration::MAX)
 }
 
+/// The longest delay of scheduling, longer ones are clamped to it, e.g. a huge
+/// delay from a `Retry-After` header
+pub const MAX_DELAY: std::time::Duration = std::time::Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+/// Returns the time after the delay clamped to [`MAX_DELAY`], so it never
+/// overflows
+pub fn add_delay(
+    time: chrono::DateTime<chrono::Utc>,
+    delay: std::time::Duration,
+) -> chrono::DateTime<chrono::Utc> {
+    time.checked_add_signed(std_duration_to_chrono(delay.min(MAX_DELAY)))
+        .unwrap_or(chrono::DateTime::<chrono::Utc>::MAX_UTC)
+}
+
+/// Converts a Postgres interval to a std duration, months aren't expected as
+/// intervals are only stored from std durations
+pub fn pg_interval_to_std(interval: &sqlx::postgres::types::PgInterval) -> std::time::Duration {
+    let micros = i64::from(interval.days) * 86_400_000_000 + interval.microseconds;
+    std::time::Duration::from_micros(micros.max(0).unsigned_abs())
+}
+
+/// Converts a std duration to a Postgres interval
+pub fn std_duration_to_pg_interval(
+    std_duration: std::time::Duration,
+) -> sqlx::postgres::types::PgInterval {
+    sqlx::postgres::types::PgInterval {
+        months: 0,
+        days: 0,
+        microseconds: i64::try_from(std_duration.as_micros()).unwrap_or(i64::MAX),
+    }
+}
+
+/// Randomly changes the duration by up to the `jitter` fraction of it, the
+/// result saturates instead of overflowing
+pub fn add_jitter(duration: std::time::Duration, jitter: f32) -> std::time::Duration {
+    let jitter = jitter.clamp(0.0, 1.0);
+    if jitter == 0.0 {
+        return duration;
+    }
+    let factor = 1.0 + jitter * (fastrand::f32() * 2.0 - 1.0);
+    std::time::Duration::try_from_secs_f64(duration.as_secs_f64() * f64::from(factor))
+        .unwrap_or(std::time::Duration::MAX)
+}
+
 /// Returns the ordinal string of a given integer
 pub fn ordinal(n: i32) -> String {
     match n.abs() {
@@ -25,13 +69,66 @@ pub fn ordinal(n: i32) -> String {
 }
 
 /// Waits for the db reconnection
-pub async fn wait_for_reconnection(db: &sqlx::PgPool, sleep: std::time::Duration) {
-    while let Err(sqlx::Error::Io(_)) = sqlx::query!("SELECT id FROM pg_task LIMIT 1")
-        .fetch_optional(db)
-        .await
-    {
+pub async fn wait_for_reconnection(db: &sqlx::PgPool, policy: &crate::ReconnectPolicy) {
+    let mut attempt = 1;
+    loop {
+        tokio::time::sleep(policy.delay(attempt)).await;
+        let Err(sqlx::Error::Io(_)) = sqlx::query!("SELECT id FROM pg_task LIMIT 1")
+            .fetch_optional(db)
+            .await
+        else {
+            break;
+        };
         tracing::trace!("Waiting for db reconnection");
-        tokio::time::sleep(sleep).await;
+        attempt = attempt.saturating_add(1);
+    }
+}
+
+/// Number of attempts of a worker query failing with transient db errors
+const TRANSIENT_ATTEMPTS: u32 = 3;
+
+/// Delay before the first retry of a transient db error, it grows linearly
+/// with the attempts
+const TRANSIENT_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// Runs the worker query again on transient db errors, so an infrastructure
+/// blip doesn't fail the step or make it run again. The closure should
+/// acquire a new connection for each attempt as the failed one may be broken.
+pub async fn retry_transient<T, F, Fut>(mut query: F) -> crate::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = crate::Result<T>>,
+{
+    let mut attempt = 1;
+    loop {
+        match query().await {
+            Err(e) if attempt < TRANSIENT_ATTEMPTS && is_transient(&e) => {
+                tracing::warn!(
+                    "Retrying a query after a transient db error: {}",
+                    source_chain::to_string(&e)
+                );
+                tokio::time::sleep(TRANSIENT_RETRY_DELAY * attempt).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Returns true if the error is caused by a lost connection or a concurrent
+/// transaction, so the query may succeed if it's run again
+fn is_transient(e: &crate::Error) -> bool {
+    let crate::Error::Db(e, _) = e else {
+        return false;
+    };
+    match e {
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut => true,
+        sqlx::Error::Database(e) => e.code().is_some_and(|code| {
+            // connection exceptions, serialization failures, deadlocks and
+            // administrator shutdowns
+            code.starts_with("08") || matches!(&*code, "40001" | "40P01" | "57P01")
+        }),
+        _ => false,
     }
 }
 
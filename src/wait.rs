@@ -0,0 +1,333 @@
+use crate::{util::db_error, Error, Result};
+use sqlx::{postgres::PgListener, types::Uuid, PgExecutor, PgPool};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock, PoisonError},
+    time::Duration,
+};
+use tokio::{
+    sync::{oneshot, watch},
+    time::sleep,
+};
+use tracing::{trace, warn};
+
+/// The channel notified on tasks reaching their terminal states
+const FINISHED_CHANNEL: &str = "pg_task_finished";
+
+/// Postgres rejects notification payloads starting from 8000 bytes
+const MAX_PAYLOAD_LEN: usize = 7999;
+
+/// The time to wait before reconnecting the listener of the finished tasks
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// The listeners of the finished tasks by the databases, they're shared by
+/// all the waiters in the process
+static LISTENERS: OnceLock<Mutex<HashMap<String, Arc<Waiters>>>> = OnceLock::new();
+
+/// The terminal state of a task returned by [`wait_for`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The task is successfully completed
+    Done,
+    /// The task has failed with the error
+    Failed(String),
+    /// The task isn't finished by its deadline, see
+    /// [`schedule_with_deadline`](crate::schedule_with_deadline)
+    Expired,
+    /// The task is cancelled
+    Cancelled,
+    /// The task is finished and removed while waiting for it, but its
+    /// notification is missed on a reconnection of the listener, so it's
+    /// either done or cancelled
+    Removed,
+    /// There's no such task when the waiting starts, e.g. it isn't committed
+    /// yet, or it was finished and removed before. Workers keep done tasks
+    /// with [`Worker::with_keep_done`](crate::Worker::with_keep_done).
+    NotFound,
+}
+
+impl Outcome {
+    /// Returns the name of the outcome used in notifications
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Done => "done",
+            Self::Failed(_) => "failed",
+            Self::Expired => "expired",
+            Self::Cancelled => "cancelled",
+            Self::Removed => "removed",
+            Self::NotFound => "not_found",
+        }
+    }
+}
+
+/// Waits for the task to reach a terminal state, e.g. to enqueue some work in
+/// a request handler and respond with its outcome. Workers notify about
+/// finished tasks using `LISTEN/NOTIFY`, so there's no polling. All the
+/// waiting in the process shares a single listening connection per database,
+/// which is kept open after the first call. Returns [`Error::WaitTimeout`] if
+/// the task isn't finished in time.
+pub async fn wait_for(db: &PgPool, id: Uuid, timeout: Duration) -> Result<Outcome> {
+    let waiters = Waiters::get(db);
+    let wait = async {
+        let mut is_first_check = true;
+        loop {
+            // Listening is started before checking, so the finishing isn't missed
+            let mut registration = waiters.register(id).await;
+            match current_outcome(db, id).await? {
+                Some(Outcome::NotFound) if !is_first_check => return Ok(Outcome::Removed),
+                Some(outcome) => return Ok(outcome),
+                None => is_first_check = false,
+            }
+            let Ok(Finished::Outcome(outcome)) = (&mut registration.receiver).await else {
+                // The connection is lost and the notifications could be missed
+                continue;
+            };
+            return Ok(match outcome.as_str() {
+                "done" => Outcome::Done,
+                "expired" => Outcome::Expired,
+                "cancelled" => Outcome::Cancelled,
+                _ => current_outcome(db, id)
+                    .await?
+                    .unwrap_or(Outcome::Failed(String::new())),
+            });
+        }
+    };
+    tokio::time::timeout(timeout, wait)
+        .await
+        .map_err(|_| Error::WaitTimeout(timeout))?
+}
+
+/// A message to a waiter of a task
+enum Finished {
+    /// The task is finished with the outcome name
+    Outcome(String),
+    /// The listener is reconnected, so the task should be checked again
+    Recheck,
+}
+
+/// The waiters of the tasks of a database along with its listener
+struct Waiters {
+    senders: Mutex<HashMap<Uuid, Vec<oneshot::Sender<Finished>>>>,
+    /// Tells if the listener is listening at the moment
+    is_listening: watch::Sender<bool>,
+}
+
+/// A waiter of a task, it's unregistered on drop
+struct Registration {
+    waiters: Arc<Waiters>,
+    id: Uuid,
+    receiver: oneshot::Receiver<Finished>,
+}
+
+impl Waiters {
+    /// Returns the waiters of the database, starting its listener on the
+    /// first call
+    fn get(db: &PgPool) -> Arc<Self> {
+        let options = db.connect_options();
+        let key = format!(
+            "{}:{}/{}",
+            options.get_host(),
+            options.get_port(),
+            options.get_database().unwrap_or_default()
+        );
+        let listeners = LISTENERS.get_or_init(Default::default);
+        let mut listeners = listeners.lock().unwrap_or_else(PoisonError::into_inner);
+        listeners
+            .entry(key.clone())
+            .or_insert_with(|| {
+                let waiters = Arc::new(Self {
+                    senders: Mutex::default(),
+                    is_listening: watch::Sender::new(false),
+                });
+                let stopped = Stopped {
+                    waiters: waiters.clone(),
+                    key,
+                };
+                tokio::spawn(waiters.clone().listen(db.clone(), stopped));
+                waiters
+            })
+            .clone()
+    }
+
+    /// Registers a waiter of the task once the listener is listening
+    async fn register(self: &Arc<Self>, id: Uuid) -> Registration {
+        let mut is_listening = self.is_listening.subscribe();
+        // The sender is kept by the waiters, so the receiving can't fail
+        is_listening.wait_for(|is| *is).await.ok();
+        let (sender, receiver) = oneshot::channel();
+        self.senders
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(id)
+            .or_default()
+            .push(sender);
+        Registration {
+            waiters: self.clone(),
+            id,
+            receiver,
+        }
+    }
+
+    /// Sends the message to the waiters of the task, or to all of them
+    fn send(&self, id: Option<Uuid>, finished: impl Fn() -> Finished) {
+        let mut senders = self.senders.lock().unwrap_or_else(PoisonError::into_inner);
+        let senders: Vec<_> = match id {
+            Some(id) => senders.remove(&id).unwrap_or_default(),
+            None => senders.drain().flat_map(|(_, senders)| senders).collect(),
+        };
+        for sender in senders {
+            sender.send(finished()).ok();
+        }
+    }
+
+    /// Receives the notifications of the finished tasks and passes them to
+    /// the waiters, reconnecting on errors
+    async fn listen(self: Arc<Self>, db: PgPool, _stopped: Stopped) {
+        loop {
+            let mut listener = match connect(&db).await {
+                Ok(listener) => listener,
+                Err(e) => {
+                    warn!(
+                        "Can't listen to the finished tasks: {}",
+                        source_chain::to_string(&e)
+                    );
+                    sleep(RECONNECT_DELAY).await;
+                    continue;
+                }
+            };
+            self.is_listening.send_replace(true);
+            // The tasks could be finished while the listener was disconnected
+            self.send(None, || Finished::Recheck);
+            // `None` means the connection is lost
+            while let Ok(Some(notification)) = listener.try_recv().await {
+                let Ok(payload) = serde_json::from_str::<serde_json::Value>(notification.payload())
+                else {
+                    continue;
+                };
+                let Some(Ok(id)) = payload["id"].as_str().map(Uuid::parse_str) else {
+                    continue;
+                };
+                let outcome = payload["outcome"].as_str().unwrap_or_default();
+                trace!("[{id}] is finished: {outcome}");
+                self.send(Some(id), || Finished::Outcome(outcome.into()));
+            }
+            self.is_listening.send_replace(false);
+        }
+    }
+}
+
+/// Forgets the listener once its task is dropped, e.g. with the runtime it's
+/// spawned on, so the next waiting starts a new one
+struct Stopped {
+    waiters: Arc<Waiters>,
+    key: String,
+}
+
+impl Drop for Stopped {
+    fn drop(&mut self) {
+        self.waiters.is_listening.send_replace(false);
+        let Some(listeners) = LISTENERS.get() else {
+            return;
+        };
+        let mut listeners = listeners.lock().unwrap_or_else(PoisonError::into_inner);
+        if listeners
+            .get(&self.key)
+            .is_some_and(|waiters| Arc::ptr_eq(waiters, &self.waiters))
+        {
+            listeners.remove(&self.key);
+        }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.receiver.close();
+        let mut senders = self
+            .waiters
+            .senders
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(waiting) = senders.get_mut(&self.id) {
+            waiting.retain(|sender| !sender.is_closed());
+            if waiting.is_empty() {
+                senders.remove(&self.id);
+            }
+        }
+    }
+}
+
+/// Connects the listener of the finished tasks
+async fn connect(db: &PgPool) -> Result<PgListener> {
+    let mut listener = PgListener::connect_with(db)
+        .await
+        .map_err(Error::ListenerConnect)?;
+    listener
+        .listen(FINISHED_CHANNEL)
+        .await
+        .map_err(Error::ListenerListen)?;
+    Ok(listener)
+}
+
+/// Returns the outcome of the task if it's finished
+async fn current_outcome(db: &PgPool, id: Uuid) -> Result<Option<Outcome>> {
+    let Some(task) = sqlx::query!(
+        r#"
+        SELECT
+            done_at IS NOT NULL AS "is_done!",
+            error,
+            expired_at IS NOT NULL AS "is_expired!"
+        FROM pg_task
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(db_error!())?
+    else {
+        return Ok(Some(Outcome::NotFound));
+    };
+    Ok(if task.is_done {
+        Some(Outcome::Done)
+    } else if let Some(error) = task.error {
+        Some(Outcome::Failed(error))
+    } else if task.is_expired {
+        Some(Outcome::Expired)
+    } else {
+        None
+    })
+}
+
+/// Notifies the waiters that the task is finished at the step, the
+/// notification is sent on the transaction commit if any. The step is omitted
+/// if it doesn't fit into the payload.
+pub(crate) async fn notify_finished<'e>(
+    db: impl PgExecutor<'e>,
+    id: Uuid,
+    outcome: &Outcome,
+    step: &serde_json::Value,
+) {
+    let mut payload = serde_json::json!({
+        "id": id.to_string(),
+        "outcome": outcome.name(),
+        "step": step,
+    })
+    .to_string();
+    if payload.len() > MAX_PAYLOAD_LEN {
+        payload = serde_json::json!({
+            "id": id.to_string(),
+            "outcome": outcome.name(),
+            "step": null,
+        })
+        .to_string();
+    }
+    if let Err(e) = sqlx::query!("SELECT pg_notify($1, $2)", FINISHED_CHANNEL, payload)
+        .execute(db)
+        .await
+    {
+        warn!(
+            "[{id}] can't notify about the task finishing: {}",
+            source_chain::to_string(&e)
+        );
+    }
+}
@@ -1,66 +1,470 @@
 use crate::{
-    listener::Listener,
-    task::Task,
-    util::{db_error, wait_for_reconnection},
-    Error, Result, Step, LOST_CONNECTION_SLEEP,
+    claim,
+    events::Observers,
+    health::HealthState,
+    listener::WorkerListener,
+    maintenance::MaintenanceWindow,
+    rate_limit::RateLimiter,
+    recurring, registry,
+    task::{FetchOptions, RunOptions, Task},
+    util::{chrono_duration_to_std, db_error, retry_transient, wait_for_reconnection},
+    ClaimStrategy, Clock, Error, FailureNotifier, IsolationLevel, Listener, ReconnectPolicy,
+    Result, Scheduler, Step, StepMiddleware, TaskEvent, WorkerHealth,
 };
-use sqlx::postgres::PgPool;
-use std::{marker::PhantomData, sync::Arc, time::Duration};
-use tokio::{sync::Semaphore, time::sleep};
+use chrono::{DateTime, Utc};
+use sqlx::{
+    postgres::{PgConnectOptions, PgPool},
+    types::Uuid,
+};
+use std::{
+    convert::Infallible,
+    future::Future,
+    iter,
+    marker::PhantomData,
+    result::Result as StdResult,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::{sleep, timeout},
+};
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
 use tracing::{debug, error, info, trace, warn};
 
-/// A worker for processing tasks
-pub struct Worker<T> {
+const DEFAULT_LEASE: Duration = Duration::from_secs(60);
+
+/// Interval to recheck tasks of the tenants running the maximum number of
+/// tasks, as finishing of a task doesn't always change the table
+const TENANT_RECHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A worker for processing tasks, `C` is the application context passed to
+/// the steps
+pub struct Worker<T, C = ()> {
     db: PgPool,
-    listener: Listener,
+    listener: WorkerListener,
     tasks: PhantomData<T>,
     concurrency: usize,
+    drain_timeout: Option<Duration>,
+    lease: Duration,
+    id: String,
+    keep_done: bool,
+    middlewares: Vec<Arc<dyn StepMiddleware<T>>>,
+    observers: Observers,
+    rate_limit: Option<f64>,
+    fair: bool,
+    batch_size: usize,
+    health: Arc<HealthState>,
+    reconnect: ReconnectPolicy,
+    crash_limit: Option<i32>,
+    tenant_concurrency: Option<usize>,
+    only_steps: Option<Vec<String>>,
+    except_steps: Vec<String>,
+    maintenance: Vec<MaintenanceWindow>,
+    isolation: Option<IsolationLevel>,
+    claim_strategy: ClaimStrategy,
+    clock: Option<Arc<dyn Clock>>,
+    app_context: C,
+}
+
+/// Returns a unique id of a new worker
+fn default_id() -> String {
+    let host = gethostname::gethostname();
+    let uuid =
+        sqlx::types::uuid::Builder::from_random_bytes(fastrand::u128(..).to_le_bytes()).into_uuid();
+    format!("{}:{}:{uuid}", host.to_string_lossy(), std::process::id())
 }
 
-impl<S: Step<S>> Worker<S> {
+impl<S> Worker<S> {
     /// Creates a new worker
     pub fn new(db: PgPool) -> Self {
-        let listener = Listener::new();
+        let listener = WorkerListener::new(Listener::new());
         let concurrency = num_cpus::get();
         Self {
             db,
             listener,
             concurrency,
+            drain_timeout: None,
+            lease: DEFAULT_LEASE,
+            id: default_id(),
+            keep_done: false,
+            middlewares: Vec::new(),
+            observers: Observers::default(),
+            rate_limit: None,
+            fair: false,
+            batch_size: 1,
+            health: Default::default(),
+            reconnect: ReconnectPolicy::default(),
+            crash_limit: None,
+            tenant_concurrency: None,
+            only_steps: None,
+            except_steps: Vec::new(),
+            maintenance: Vec::new(),
+            isolation: None,
+            claim_strategy: ClaimStrategy::default(),
+            clock: None,
+            app_context: (),
             tasks: PhantomData,
         }
     }
 
+    /// Passes the application context to the steps, e.g. HTTP clients, config
+    /// and API keys. Steps get it with
+    /// [`StepContext::app_context`](crate::StepContext::app_context), the
+    /// tasks should be wrapped with its type, see [`task!`](crate::task).
+    pub fn with_context<C>(self, context: C) -> Worker<S, C> {
+        Worker {
+            db: self.db,
+            listener: self.listener,
+            tasks: self.tasks,
+            concurrency: self.concurrency,
+            drain_timeout: self.drain_timeout,
+            lease: self.lease,
+            id: self.id,
+            keep_done: self.keep_done,
+            middlewares: self.middlewares,
+            observers: self.observers,
+            rate_limit: self.rate_limit,
+            fair: self.fair,
+            batch_size: self.batch_size,
+            health: self.health,
+            reconnect: self.reconnect,
+            crash_limit: self.crash_limit,
+            tenant_concurrency: self.tenant_concurrency,
+            only_steps: self.only_steps,
+            except_steps: self.except_steps,
+            maintenance: self.maintenance,
+            isolation: self.isolation,
+            claim_strategy: self.claim_strategy,
+            clock: self.clock,
+            app_context: context,
+        }
+    }
+}
+
+impl<S, C> Worker<S, C> {
     /// Sets the number of concurrent tasks, default is the number of CPU cores
     pub fn with_concurrency(mut self, concurrency: usize) -> Self {
         self.concurrency = concurrency;
         self
     }
 
+    /// Sets the maximum time to wait for the current steps to finish on
+    /// stopping, by default it waits until all of them are done
+    pub fn with_drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = Some(drain_timeout);
+        self
+    }
+
+    /// Sets the duration tasks are locked for while running. The lock is
+    /// renewed while the step is alive, so it expires only if the worker is
+    /// crashed. Then the task is picked up again by any other worker. The
+    /// default is 1 minute.
+    pub fn with_lease(mut self, lease: Duration) -> Self {
+        self.lease = lease;
+        self
+    }
+
+    /// Sets the worker id, the default is `<hostname>:<pid>:<random uuid>`.
+    /// The id should be unique for each worker. A stable one persisting
+    /// across restarts lets the worker unlock the tasks which remained locked
+    /// under its id after a crash right on start, instead of waiting for
+    /// their leases to expire.
+    pub fn with_id(mut self, id: impl Into<String>) -> Self {
+        self.id = id.into();
+        self
+    }
+
+    /// Keeps completed tasks in the table marking them done instead of
+    /// removing, e.g. for auditing. Use [`cleanup`](crate::cleanup) to remove
+    /// the old ones.
+    pub fn with_keep_done(mut self, keep_done: bool) -> Self {
+        self.keep_done = keep_done;
+        self
+    }
+
+    /// Schedules the steps by the clock instead of the db time, e.g. a
+    /// [`ManualClock`](crate::test::ManualClock) to test steps delayed for
+    /// hours without waiting. A waiting worker doesn't notice the clock
+    /// changes, so run the tasks with [`Self::run_until_idle`] after changing
+    /// it.
+    pub fn with_clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Adds a middleware running around each step, middlewares run in the order
+    /// they're added
+    pub fn with_middleware(mut self, middleware: impl StepMiddleware<S> + 'static) -> Self {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    /// Fails tasks which steps have crashed workers more than `limit` times in
+    /// a row, so a poisonous payload, e.g. running out of memory, can't take
+    /// down workers forever. A crash is detected by the task lock expiration,
+    /// so a step of a worker losing its db connection for longer than the
+    /// lease counts too. The failed tasks could be retried with
+    /// [`retry_now`](crate::retry_now) after a fix.
+    pub fn with_crash_limit(mut self, limit: i32) -> Self {
+        self.crash_limit = Some(limit);
+        self
+    }
+
+    /// Adds a notifier of tasks failed after all the retry attempts, e.g.
+    /// [`TracingNotifier`](crate::TracingNotifier) or `WebhookNotifier`
+    /// enabled by the `webhook` feature
+    pub fn with_failure_notifier(mut self, notifier: impl FailureNotifier + 'static) -> Self {
+        self.observers.failure_notifiers.push(Arc::new(notifier));
+        self
+    }
+
+    /// Returns a stream of the task lifecycle events, e.g. to mirror the tasks
+    /// state into another system in real time. Only the events emitted after
+    /// the subscription are received, and a subscriber lagging for more than
+    /// 1024 events misses the older ones.
+    pub fn events(&self) -> impl Stream<Item = TaskEvent> + Send + 'static {
+        BroadcastStream::new(self.observers.events.subscribe()).filter_map(StdResult::ok)
+    }
+
+    /// Returns the current state of the worker, e.g. for liveness and
+    /// readiness probes to detect a silently wedged worker. The worker should
+    /// be shared, e.g. in an `Arc`, to check it while running.
+    pub fn health(&self) -> WorkerHealth {
+        self.health
+            .report(self.listener.is_connected(), self.concurrency)
+    }
+
+    /// Limits the number of tasks claimed per second, e.g. to protect the db
+    /// or downstream systems while catching up with a backlog. The rate
+    /// could be fractional, e.g. `0.5` is a task per two seconds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the rate isn't a positive finite number
+    pub fn with_rate_limit(mut self, tasks_per_second: f64) -> Self {
+        assert!(
+            tasks_per_second.is_finite() && tasks_per_second > 0.0,
+            "the rate limit should be a positive finite number, got {tasks_per_second}"
+        );
+        self.rate_limit = Some(tasks_per_second);
+        self
+    }
+
+    /// Runs ready tasks of different names in turns, so a large backlog of
+    /// one task doesn't starve the others. Priorities are only respected
+    /// between the tasks of the same name.
+    pub fn with_fair_scheduling(mut self, fair: bool) -> Self {
+        self.fair = fair;
+        self
+    }
+
+    /// Limits the number of running tasks of each tenant across all the
+    /// workers, see [`Scheduler::enqueue_for_tenant`], so a noisy tenant can't
+    /// starve the others. Concurrent claims by different workers could exceed
+    /// the limit a bit. Tasks without a tenant aren't limited.
+    pub fn with_tenant_concurrency(mut self, limit: usize) -> Self {
+        self.tenant_concurrency = Some(limit);
+        self
+    }
+
+    /// Only runs the tasks at the steps, i.e. the variants of the task enums,
+    /// e.g. to dedicate a beefy machine to heavy steps while other workers
+    /// skip them with [`Self::except_steps`]
+    pub fn only_steps(mut self, steps: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.only_steps = Some(steps.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Skips the tasks at the steps, i.e. the variants of the task enums, see
+    /// [`Self::only_steps`]
+    pub fn except_steps(mut self, steps: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.except_steps = steps.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Doesn't claim tasks during the window, e.g. the nightly db maintenance.
+    /// The steps running when the window opens are finished. Several windows
+    /// could be set, e.g. for different steps.
+    pub fn with_maintenance_window(mut self, window: MaintenanceWindow) -> Self {
+        self.maintenance.push(window);
+        self
+    }
+
+    /// Sets the isolation level of the transactions claiming tasks, the db
+    /// default is used otherwise. Serialization failures of the stricter
+    /// levels are retried.
+    pub fn with_isolation_level(mut self, isolation: IsolationLevel) -> Self {
+        self.isolation = Some(isolation);
+        self
+    }
+
+    /// Sets how workers prevent claiming of a task by more than one of them,
+    /// the default is [`ClaimStrategy::SkipLocked`]
+    pub fn with_claim_strategy(mut self, strategy: ClaimStrategy) -> Self {
+        self.claim_strategy = strategy;
+        self
+    }
+
+    /// Sets the options of the db connection used to listen to the tasks table
+    /// changes, by default it's acquired from the worker pool. E.g. the pool
+    /// could go through PgBouncer, while the listening connection goes
+    /// directly to Postgres, or use different credentials or TLS settings. A
+    /// url could be parsed into the options with `url.parse()`.
+    pub fn with_listener_connect_options(mut self, options: PgConnectOptions) -> Self {
+        self.listener = self.listener.with_connect_options(options);
+        self
+    }
+
+    /// Uses the listener of the tasks table changes shared with other
+    /// workers, e.g. of different task enums in one process, so they hold a
+    /// single `LISTEN` connection. Set the connection options or polling on
+    /// the shared listener itself.
+    pub fn with_listener(mut self, listener: Listener) -> Self {
+        self.listener = WorkerListener::new(listener);
+        self
+    }
+
+    /// Sets the delays between checks of the db connection after it's lost,
+    /// both for fetching tasks and listening to the tasks table changes. The
+    /// default is a check per second.
+    pub fn with_reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = policy;
+        self
+    }
+
+    /// Polls the tasks table every `interval` instead of listening to its
+    /// changes, e.g. behind PgBouncer in the transaction pooling mode which
+    /// doesn't support `LISTEN`. The stop notification isn't received in this
+    /// mode, use [`Self::run_with_shutdown`] to stop the worker.
+    pub fn with_polling(mut self, interval: Duration) -> Self {
+        self.listener = WorkerListener::new(Listener::polling(interval));
+        self
+    }
+
+    /// Claims up to `batch_size` ready tasks for the free slots at once, which
+    /// reduces db round trips under high throughput. It doesn't apply with
+    /// [`Self::with_fair_scheduling`], which claims tasks one by one.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+}
+
+impl<S, C> Worker<S, C>
+where
+    S: Step<S, C> + Scheduler + 'static,
+    C: Clone + Send + Sync + 'static,
+{
     /// Runs all ready tasks to completion and waits for new ones
     pub async fn run(&self) -> Result<()> {
+        self.run_with_shutdown(std::future::pending()).await
+    }
+
+    /// Runs the worker until the `shutdown` future resolves (or the stop
+    /// notification is received). After that, it stops claiming new tasks,
+    /// waits for the current steps to finish and returns `Ok(())`.
+    pub async fn run_with_shutdown(&self, shutdown: impl Future<Output = ()>) -> Result<()> {
+        let stop_on_shutdown = async {
+            shutdown.await;
+            info!("Got the shutdown signal");
+            self.listener.stop_worker();
+            std::future::pending::<Infallible>().await
+        };
+        registry::register(&self.db, &self.id, self.lease).await?;
+        self.health.is_running.store(true, Ordering::Relaxed);
+        let result = tokio::select! {
+            result = self.process_tasks() => result,
+            never = stop_on_shutdown => match never {},
+            never = registry::heartbeat(&self.db, &self.id, self.lease) => match never {},
+            never = recurring::run(&self.db, self.clock.clone()) => match never {},
+        };
+        self.health.is_running.store(false, Ordering::Relaxed);
+        registry::deregister(&self.db, &self.id).await;
+        result
+    }
+
+    /// Processes tasks until the worker is stopped
+    async fn process_tasks(&self) -> Result<()> {
         self.unlock_stale_tasks().await?;
-        self.listener.listen(self.db.clone()).await?;
+        self.listener
+            .listen(self.db.clone(), self.reconnect)
+            .await?;
 
         let semaphore = Arc::new(Semaphore::new(self.concurrency));
+        let options = self.run_options();
+        let mut rate_limiter = self.rate_limit.map(RateLimiter::new);
+        let mut last_task_name = String::new();
 
         loop {
-            match self.recv_task().await {
+            // Acquiring a permit before claiming a task, otherwise its lease
+            // could expire while waiting for the permit
+            let permit = semaphore
+                .clone()
+                .acquire_owned()
+                .await
+                .map_err(Error::UnreachableWorkerSemaphoreClosed)?;
+            if let Some(rate_limiter) = &mut rate_limiter {
+                rate_limiter.acquire().await;
+            }
+
+            let mut extra_permits = Vec::new();
+            if !self.fair {
+                while extra_permits.len() + 1 < self.batch_size {
+                    let Ok(permit) = semaphore.clone().try_acquire_owned() else {
+                        break;
+                    };
+                    if rate_limiter.as_mut().is_some_and(|r| !r.try_acquire()) {
+                        break;
+                    }
+                    extra_permits.push(permit);
+                }
+            }
+            if !extra_permits.is_empty() && !self.listener.time_to_stop_worker() {
+                let limit = extra_permits.len() + 1;
+                let tasks = match Task::claim_ready(
+                    &self.db,
+                    &self.id,
+                    self.lease,
+                    limit,
+                    &self.fetch_options(),
+                )
+                .await
+                {
+                    Ok(tasks) => tasks,
+                    Err(e) => {
+                        warn!(
+                            "Can't claim a batch of tasks: {}",
+                            source_chain::to_string(&e)
+                        );
+                        Vec::new()
+                    }
+                };
+                // Tokens are only spent on the claimed tasks, the first one is
+                // kept for fetching a task if none are claimed
+                if let Some(rate_limiter) = &mut rate_limiter {
+                    rate_limiter.refund(limit - tasks.len().max(1));
+                }
+                if !tasks.is_empty() {
+                    self.health.claimed();
+                    let permits = iter::once(permit).chain(extra_permits);
+                    for (task, permit) in tasks.into_iter().zip(permits) {
+                        self.spawn_step(task, permit, options.clone());
+                    }
+                    continue;
+                }
+            }
+            drop(extra_permits);
+
+            match retry_transient(|| self.recv_task(&last_task_name)).await {
                 Ok(Some(task)) => {
-                    let permit = semaphore
-                        .clone()
-                        .acquire_owned()
-                        .await
-                        .map_err(Error::UnreachableWorkerSemaphoreClosed)?;
-                    let db = self.db.clone();
-                    tokio::spawn(async move {
-                        if let Err(e) = task.run_step::<S>(&db).await {
-                            error!("[{}] {}", task.id, source_chain::to_string(&e));
-                        };
-                        drop(permit);
-                    });
+                    self.health.claimed();
+                    last_task_name.clone_from(&task.task_name);
+                    self.spawn_step(task, permit, options.clone());
                 }
                 Ok(None) => {
-                    self.wait_for_steps_to_finish(semaphore.clone()).await;
+                    drop(permit);
+                    self.drain(semaphore.clone()).await;
                     info!("Stopped");
                     return Ok(());
                 }
@@ -69,24 +473,122 @@ impl<S: Step<S>> Worker<S> {
                         "Can't fetch a task (probably due to db connection loss):\n{}",
                         source_chain::to_string(&e)
                     );
-                    sleep(LOST_CONNECTION_SLEEP).await;
-                    wait_for_reconnection(&self.db, LOST_CONNECTION_SLEEP).await;
+                    wait_for_reconnection(&self.db, &self.reconnect).await;
                     warn!("Task fetching is probably restored");
                 }
             }
         }
     }
 
-    /// Unlocks all tasks. This is intended to run at the start of the worker as
-    /// some tasks could remain locked as running indefinitely if the
-    /// previous run ended due to some kind of crash.
+    /// Processes the ready tasks one by one until there are none left and
+    /// returns the number of the steps run. The steps the tasks move to right
+    /// away and retries without a delay are processed too, the tasks scheduled
+    /// for later are left. The tasks of due recurring schedules are enqueued
+    /// first. It's intended for integration tests to check the final state of
+    /// tasks without running the worker in the background.
+    pub async fn run_until_idle(&self) -> Result<usize> {
+        self.enqueue_recurring().await?;
+        let options = self.run_options();
+        let mut processed = 0;
+        while self.run_next(&options).await?.is_some() {
+            processed += 1;
+        }
+        Ok(processed)
+    }
+
+    /// Claims a ready task and runs its current step, returns the task id or
+    /// `None` if there are no ready tasks. It doesn't wait for tasks, so it's
+    /// useful for tests, batch jobs started by cron, or debugging a single
+    /// step. The tasks of due recurring schedules are enqueued first.
+    pub async fn tick(&self) -> Result<Option<Uuid>> {
+        self.enqueue_recurring().await?;
+        self.run_next(&self.run_options()).await
+    }
+
+    /// Enqueues the tasks of due recurring schedules, which the running
+    /// worker does in the background
+    async fn enqueue_recurring(&self) -> Result<()> {
+        let now = self.clock.as_ref().map(|clock| clock.now());
+        retry_transient(|| recurring::enqueue_due(&self.db, now)).await?;
+        Ok(())
+    }
+
+    /// Claims a ready task and runs its current step
+    async fn run_next(&self, options: &RunOptions<S, C>) -> Result<Option<Uuid>> {
+        let fetch_options = self.fetch_options();
+        let Some(task) = retry_transient(|| {
+            Task::claim_ready(&self.db, &self.id, self.lease, 1, &fetch_options)
+        })
+        .await?
+        .pop() else {
+            return Ok(None);
+        };
+        task.run_step(&self.db, options).await?;
+        Ok(Some(task.id))
+    }
+
+    /// Returns the options of running steps
+    fn run_options(&self) -> Arc<RunOptions<S, C>> {
+        Arc::new(RunOptions {
+            worker_id: self.id.clone(),
+            lease: self.lease,
+            keep_done: self.keep_done,
+            middlewares: self.middlewares.clone(),
+            observers: self.observers.clone(),
+            crash_limit: self.crash_limit,
+            shutdown: self.listener.stop_token(),
+            step_semaphores: Default::default(),
+            app_context: self.app_context.clone(),
+            clock: self.clock.clone(),
+        })
+    }
+
+    /// Runs the current step of the claimed task in the background, the permit
+    /// is released after it's finished
+    fn spawn_step(&self, task: Task, permit: OwnedSemaphorePermit, options: Arc<RunOptions<S, C>>) {
+        let db = self.db.clone();
+        let health = self.health.clone();
+        health.in_flight.fetch_add(1, Ordering::Relaxed);
+        tokio::spawn(async move {
+            if let Err(e) = task.run_step(&db, &options).await {
+                error!("[{}] {}", task.id, source_chain::to_string(&e));
+            };
+            health.in_flight.fetch_sub(1, Ordering::Relaxed);
+            drop(permit);
+        });
+    }
+
+    /// Unlocks the tasks locked by this worker. This is intended to run at the
+    /// start of the worker as some tasks could remain locked until their lease
+    /// expires if the previous run ended due to some kind of crash. The stale
+    /// tasks which were cancelled meanwhile are removed.
     async fn unlock_stale_tasks(&self) -> Result<()> {
-        let unlocked =
-            sqlx::query!("UPDATE pg_task SET is_running = false WHERE is_running = true")
-                .execute(&self.db)
-                .await
-                .map_err(Error::UnlockStaleTasks)?
-                .rows_affected();
+        let cancelled = sqlx::query!(
+            "DELETE FROM pg_task WHERE locked_by = $1 AND cancelled_at IS NOT NULL",
+            self.id
+        )
+        .execute(&self.db)
+        .await
+        .map_err(Error::UnlockStaleTasks)?
+        .rows_affected();
+        if cancelled > 0 {
+            debug!("Removed {} cancelled stale tasks", cancelled)
+        }
+
+        let unlocked = sqlx::query!(
+            "
+            UPDATE pg_task
+            SET locked_until = NULL,
+                locked_by = NULL,
+                crash_count = crash_count + 1
+            WHERE locked_by = $1
+            ",
+            self.id
+        )
+        .execute(&self.db)
+        .await
+        .map_err(Error::UnlockStaleTasks)?
+        .rows_affected();
         if unlocked == 0 {
             debug!("No stale tasks to unlock")
         } else {
@@ -97,37 +599,122 @@ impl<S: Step<S>> Worker<S> {
 
     /// Waits until the next task is ready, marks it running and returns it.
     /// Returns `None` if the worker is stopped
-    async fn recv_task(&self) -> Result<Option<Task>> {
+    async fn recv_task(&self, last_task_name: &str) -> Result<Option<Task>> {
         trace!("Receiving the next task");
 
         loop {
+            // Subscribing before the check to not miss a stop signal between them
+            let table_changes = self.listener.subscribe();
             if self.listener.time_to_stop_worker() {
                 return Ok(None);
             }
 
-            let table_changes = self.listener.subscribe();
-            let mut tx = self.db.begin().await.map_err(db_error!("begin"))?;
+            let options = self.fetch_options();
+            let mut tx = claim::begin(&self.db, options.isolation, options.claim_strategy).await?;
 
-            let Some(task) = Task::fetch_closest(&mut tx).await? else {
+            let fair_task = if self.fair {
+                Task::fetch_fair(&mut tx, last_task_name, &options).await?
+            } else {
+                None
+            };
+            let task = match fair_task {
+                Some(task) => Some(task),
+                None => Task::fetch_closest(&mut tx, &options).await?,
+            };
+            let Some(mut task) = task else {
                 // No tasks, waiting for the tasks table changes
                 tx.commit().await.map_err(db_error!("no tasks"))?;
-                table_changes.wait_forever().await;
+                match self.recheck_interval(options.now.unwrap_or_else(Utc::now)) {
+                    Some(interval) => table_changes.wait_for(interval).await,
+                    None => table_changes.wait_forever().await,
+                }
                 continue;
             };
 
-            if let Some(delay) = task.wait_before_running() {
-                // Waiting until a task is ready or for the tasks table to change
+            if let Some(mut delay) = task.wait_before_running(options.now.unwrap_or_else(Utc::now))
+            {
+                // Sleeping until the task is ready or another one is due to
+                // wake up earlier, rather than checking on each table change
                 tx.commit().await.map_err(db_error!("wait"))?;
-                table_changes.wait_for(delay).await;
+                if let Some(interval) = self.recheck_interval(options.now.unwrap_or_else(Utc::now))
+                {
+                    delay = delay.min(interval);
+                }
+                self.listener.wait_for_wakeup(delay).await;
                 continue;
             };
 
-            task.mark_running(&mut tx).await?;
+            task.mark_running(&mut tx, &self.id, self.lease, options.now)
+                .await?;
             tx.commit().await.map_err(db_error!("mark running"))?;
             return Ok(Some(task));
         }
     }
 
+    /// Returns the options of fetching tasks
+    fn fetch_options(&self) -> FetchOptions {
+        let now = self.clock.as_ref().map(|clock| clock.now());
+        let mut only_steps = self.only_steps.clone();
+        let mut except_steps = self.except_steps.clone();
+        let maintenance_now = now.unwrap_or_else(Utc::now);
+        for window in self
+            .maintenance
+            .iter()
+            .filter(|w| w.is_active(maintenance_now))
+        {
+            match window.steps() {
+                Some(steps) => except_steps.extend_from_slice(steps),
+                // No steps match the empty list
+                None => only_steps = Some(Vec::new()),
+            }
+        }
+        FetchOptions {
+            tenant_concurrency: self
+                .tenant_concurrency
+                .map(|limit| i64::try_from(limit).unwrap_or(i64::MAX)),
+            payload_version: S::PAYLOAD_VERSION,
+            now,
+            only_steps,
+            except_steps,
+            task_names: S::task_names(),
+            isolation: self.isolation,
+            claim_strategy: self.claim_strategy,
+        }
+    }
+
+    /// Returns the interval to check for tasks even if the table isn't
+    /// changed, as the tasks skipped for the busy tenants or the maintenance
+    /// windows could become claimable without changes
+    fn recheck_interval(&self, now: DateTime<Utc>) -> Option<Duration> {
+        let tenants = self.tenant_concurrency.map(|_| TENANT_RECHECK_INTERVAL);
+        let maintenance = self
+            .maintenance
+            .iter()
+            .map(|w| chrono_duration_to_std(w.next_change(now) - now))
+            .min();
+        tenants.into_iter().chain(maintenance).min()
+    }
+
+    /// Waits for the current steps to finish within the drain timeout if it's
+    /// set. Unfinished tasks stay locked until [`Self::unlock_stale_tasks`].
+    async fn drain(&self, semaphore: Arc<Semaphore>) {
+        let Some(drain_timeout) = self.drain_timeout else {
+            return self.wait_for_steps_to_finish(semaphore).await;
+        };
+        if timeout(
+            drain_timeout,
+            self.wait_for_steps_to_finish(semaphore.clone()),
+        )
+        .await
+        .is_err()
+        {
+            warn!(
+                "The drain timeout of {drain_timeout:?} is expired, leaving the current steps of {} tasks unfinished",
+                self.concurrency - semaphore.available_permits()
+            );
+        }
+    }
+
     async fn wait_for_steps_to_finish(&self, semaphore: Arc<Semaphore>) {
         let mut logged_tasks_left = None;
         loop {
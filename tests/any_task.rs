@@ -0,0 +1,55 @@
+use async_trait::async_trait;
+use pg_task::{
+    AnyTask, NextStep, Scheduler, Step, StepContext, StepResult, TaskType, TaskTypes, Worker,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+pg_task::task!(Invoice { Issue });
+
+impl TaskType for Invoice {
+    const NAME: &'static str = "Invoice";
+}
+
+pg_task::task!(Order { Charge });
+pg_task::scheduler!(OrderTasks { Order });
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Issue;
+#[async_trait]
+impl Step<Invoice> for Issue {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Invoice> {
+        NextStep::done_with("issued")
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Charge;
+#[async_trait]
+impl Step<Order> for Charge {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Order> {
+        NextStep::done_with("charged")
+    }
+}
+
+#[sqlx::test(migrations = false)]
+async fn runs_along_with_workers_of_other_enums(db: PgPool) -> anyhow::Result<()> {
+    pg_task::migrate(&db).await?;
+    pg_task::set_task_types(TaskTypes::new().register::<Invoice>())?;
+    let any = Worker::<AnyTask>::new(db.clone());
+    let orders = Worker::<OrderTasks>::new(db.clone());
+    let invoice = AnyTask::from(Invoice::from(Issue)).enqueue(&db).await?;
+    let order = pg_task::enqueue(&db, &OrderTasks::Order(Charge.into())).await?;
+
+    assert_eq!(any.run_until_idle().await?, 1);
+    let info = order.status(&db).await?.expect("the order is pending");
+    assert!(!info.is_unparseable);
+    assert_eq!(info.result, None);
+
+    assert_eq!(orders.run_until_idle().await?, 1);
+    let info = invoice.status(&db).await?.expect("the invoice is issued");
+    assert_eq!(info.result, Some(serde_json::json!("issued")));
+    let info = order.status(&db).await?.expect("the order is charged");
+    assert_eq!(info.result, Some(serde_json::json!("charged")));
+    Ok(())
+}
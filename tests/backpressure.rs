@@ -0,0 +1,79 @@
+use async_trait::async_trait;
+use pg_task::{Error, NextStep, Scheduler, Step, StepContext, StepResult};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+pg_task::task!(Email { Send });
+pg_task::task!(Report { Build });
+pg_task::task!(Tasks { Email, Report });
+
+impl Scheduler for Tasks {
+    fn max_pending(&self) -> Option<i64> {
+        match self {
+            Self::Email(_) => Some(2),
+            Self::Report(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Send;
+#[async_trait]
+impl Step<Email> for Send {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Email> {
+        NextStep::none()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Build;
+#[async_trait]
+impl Step<Report> for Build {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Report> {
+        NextStep::none()
+    }
+}
+
+fn email() -> Tasks {
+    Tasks::Email(Send.into())
+}
+
+fn report() -> Tasks {
+    Tasks::Report(Build.into())
+}
+
+async fn pending(db: &PgPool) -> anyhow::Result<i64> {
+    Ok(sqlx::query_scalar("SELECT COUNT(*) FROM pg_task")
+        .fetch_one(db)
+        .await?)
+}
+
+#[sqlx::test(migrations = false)]
+async fn rejects_tasks_over_the_limit(db: PgPool) -> anyhow::Result<()> {
+    pg_task::migrate(&db).await?;
+    pg_task::enqueue(&db, &email()).await?;
+    pg_task::enqueue(&db, &email()).await?;
+
+    let err = pg_task::enqueue(&db, &email()).await.unwrap_err();
+    assert!(matches!(err, Error::QueueFull(name) if name == "Email"));
+    pg_task::enqueue(&db, &report()).await?;
+    assert_eq!(pending(&db).await?, 3);
+    Ok(())
+}
+
+#[sqlx::test(migrations = false)]
+async fn rejects_batches_over_the_limit(db: PgPool) -> anyhow::Result<()> {
+    pg_task::migrate(&db).await?;
+    pg_task::enqueue(&db, &email()).await?;
+
+    let err = Tasks::enqueue_many(&db, [report(), email(), email()])
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::QueueFull(name) if name == "Email"));
+    assert_eq!(pending(&db).await?, 1);
+
+    let handles = Tasks::enqueue_many(&db, [report(), email()]).await?;
+    assert_eq!(handles.len(), 2);
+    assert_eq!(pending(&db).await?, 3);
+    Ok(())
+}
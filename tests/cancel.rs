@@ -0,0 +1,122 @@
+use async_trait::async_trait;
+use pg_task::{CancelledTree, NextStep, Step, StepContext, StepResult, Worker};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::time::Duration;
+
+pg_task::task!(Import {
+    FanOut,
+    Collect,
+    Part,
+    Long,
+    AfterLong
+});
+pg_task::scheduler!(Tasks { Import });
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct FanOut;
+#[async_trait]
+impl Step<Import> for FanOut {
+    async fn step(self, _db: &PgPool, ctx: &StepContext) -> StepResult<Import> {
+        for _ in 0..2 {
+            ctx.spawn_child(&Tasks::Import(Part.into())).await?;
+        }
+        NextStep::join(Collect)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Collect;
+#[async_trait]
+impl Step<Import> for Collect {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Import> {
+        NextStep::none()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Part;
+#[async_trait]
+impl Step<Import> for Part {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Import> {
+        NextStep::none()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Long;
+#[async_trait]
+impl Step<Import> for Long {
+    async fn step(self, _db: &PgPool, ctx: &StepContext) -> StepResult<Import> {
+        tokio::time::timeout(Duration::from_secs(10), ctx.cancellation_token.cancelled()).await?;
+        NextStep::now(AfterLong)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AfterLong;
+#[async_trait]
+impl Step<Import> for AfterLong {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Import> {
+        panic!("the cancelled task is run")
+    }
+}
+
+#[sqlx::test(migrations = false)]
+async fn removes_a_pending_task(db: PgPool) -> anyhow::Result<()> {
+    pg_task::migrate(&db).await?;
+    let task = pg_task::enqueue(&db, &Tasks::Import(Part.into())).await?;
+
+    assert!(task.cancel(&db).await?);
+
+    assert!(task.status(&db).await?.is_none());
+    assert!(!task.cancel(&db).await?);
+    assert_eq!(Worker::<Tasks>::new(db.clone()).run_until_idle().await?, 0);
+    Ok(())
+}
+
+#[sqlx::test(migrations = false)]
+async fn removes_the_children(db: PgPool) -> anyhow::Result<()> {
+    pg_task::migrate(&db).await?;
+    let worker = Worker::<Tasks>::new(db.clone());
+    let task = pg_task::enqueue(&db, &Tasks::Import(FanOut.into())).await?;
+    assert_eq!(worker.tick().await?, Some(task.id()));
+
+    let cancelled = pg_task::cancel_tree(&db, task.id()).await?;
+
+    assert_eq!(
+        cancelled,
+        Some(CancelledTree {
+            removed: 3,
+            running: 0
+        })
+    );
+    assert_eq!(worker.run_until_idle().await?, 0);
+    Ok(())
+}
+
+#[sqlx::test(migrations = false)]
+async fn stops_a_running_task_after_its_step(db: PgPool) -> anyhow::Result<()> {
+    pg_task::migrate(&db).await?;
+    let worker = Worker::<Tasks>::new(db.clone()).with_lease(Duration::from_millis(300));
+    let task = pg_task::enqueue(&db, &Tasks::Import(Long.into())).await?;
+
+    let cancel = async {
+        while !task.status(&db).await?.is_some_and(|info| info.is_running) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        pg_task::cancel_tree(&db, task.id()).await
+    };
+    let (processed, cancelled) = tokio::join!(worker.run_until_idle(), cancel);
+
+    assert_eq!(processed?, 1);
+    assert_eq!(
+        cancelled?,
+        Some(CancelledTree {
+            removed: 0,
+            running: 1
+        })
+    );
+    assert!(task.status(&db).await?.is_none());
+    Ok(())
+}
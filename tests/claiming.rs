@@ -0,0 +1,92 @@
+use async_trait::async_trait;
+use pg_task::{
+    test::ManualClock, Clock, EnqueueOptions, NextStep, Step, StepContext, StepResult, Worker,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::time::Duration;
+
+pg_task::task!(Job { Record });
+pg_task::scheduler!(Tasks { Job });
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Record(i32);
+#[async_trait]
+impl Step<Job> for Record {
+    async fn step(self, db: &PgPool, _ctx: &StepContext) -> StepResult<Job> {
+        sqlx::query("INSERT INTO runs (n) VALUES ($1)")
+            .bind(self.0)
+            .execute(db)
+            .await?;
+        NextStep::none()
+    }
+}
+
+async fn setup(db: &PgPool) -> anyhow::Result<()> {
+    pg_task::migrate(db).await?;
+    sqlx::query("CREATE TABLE runs (id SERIAL, n INT NOT NULL)")
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+async fn runs(db: &PgPool) -> anyhow::Result<Vec<i32>> {
+    Ok(sqlx::query_scalar("SELECT n FROM runs ORDER BY id")
+        .fetch_all(db)
+        .await?)
+}
+
+fn record(n: i32) -> Tasks {
+    Tasks::Job(Record(n).into())
+}
+
+#[sqlx::test(migrations = false)]
+async fn runs_ready_tasks_by_priority(db: PgPool) -> anyhow::Result<()> {
+    setup(&db).await?;
+    pg_task::enqueue(&db, &record(1)).await?;
+    pg_task::enqueue_with_priority(&db, &record(2), 10).await?;
+    pg_task::enqueue_with_priority(&db, &record(3), -10).await?;
+
+    let processed = Worker::<Tasks>::new(db.clone()).run_until_idle().await?;
+
+    assert_eq!(processed, 3);
+    assert_eq!(runs(&db).await?, [2, 1, 3]);
+    Ok(())
+}
+
+#[sqlx::test(migrations = false)]
+async fn leaves_tasks_scheduled_later(db: PgPool) -> anyhow::Result<()> {
+    setup(&db).await?;
+    let clock = ManualClock::new();
+    let worker = Worker::<Tasks>::new(db.clone()).with_clock(clock.clone());
+    let options = EnqueueOptions::new()
+        .with_at(clock.now())
+        .with_delay(Duration::from_secs(60));
+    pg_task::enqueue_with(&db, &record(1), options).await?;
+
+    assert_eq!(worker.run_until_idle().await?, 0);
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(worker.run_until_idle().await?, 1);
+    assert_eq!(runs(&db).await?, [1]);
+    Ok(())
+}
+
+#[sqlx::test(migrations = false)]
+async fn claims_each_task_once(db: PgPool) -> anyhow::Result<()> {
+    setup(&db).await?;
+    pg_task::enqueue_many(&db, (1..=20).map(record)).await?;
+
+    let workers: Vec<_> = (0..3).map(|_| Worker::<Tasks>::new(db.clone())).collect();
+    let (a, b, c) = tokio::join!(
+        workers[0].run_until_idle(),
+        workers[1].run_until_idle(),
+        workers[2].run_until_idle(),
+    );
+    let processed = a? + b? + c?;
+
+    assert_eq!(processed, 20);
+    let mut runs = runs(&db).await?;
+    runs.sort_unstable();
+    assert_eq!(runs, (1..=20).collect::<Vec<_>>());
+    Ok(())
+}
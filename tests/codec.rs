@@ -0,0 +1,92 @@
+#![cfg(feature = "cbor")]
+
+use async_trait::async_trait;
+use pg_task::{Compensate, NextStep, Scheduler, Step, StepContext, StepError, StepResult, Worker};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::PgPool;
+
+pg_task::task!(Upload { Store, Index });
+pg_task::task!(Tasks { Upload });
+
+impl Scheduler for Tasks {
+    const CODEC: &'static dyn pg_task::Codec = &pg_task::Cbor;
+}
+
+/// Stores the file, the flag tells if it could be indexed
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Store(Vec<u8>, bool);
+#[async_trait]
+impl Step<Upload> for Store {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Upload> {
+        NextStep::now(Index(self.0.len(), self.1))
+    }
+}
+#[async_trait]
+impl Compensate<Upload> for Store {
+    async fn compensate(self, db: &PgPool, _ctx: &StepContext) -> Result<(), StepError> {
+        sqlx::query("INSERT INTO deleted (size) VALUES ($1)")
+            .bind(self.0.len() as i32)
+            .execute(db)
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Index(usize, bool);
+#[async_trait]
+impl Step<Upload> for Index {
+    const RETRY_LIMIT: i32 = 0;
+
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Upload> {
+        if !self.1 {
+            return Err("can't index".into());
+        }
+        NextStep::done_with(self.0)
+    }
+}
+
+#[sqlx::test(migrations = false)]
+async fn stores_encoded_steps_as_bytes(db: PgPool) -> anyhow::Result<()> {
+    pg_task::migrate(&db).await?;
+    let task = pg_task::enqueue(&db, &Tasks::Upload(Store(vec![7; 1024], true).into())).await?;
+
+    let (step, bytes): (serde_json::Value, Option<Vec<u8>>) =
+        sqlx::query_as("SELECT step, step_bytes FROM pg_task")
+            .fetch_one(&db)
+            .await?;
+    assert_eq!(step, json!({ "pg_task_cbor": null }));
+    assert!(bytes.is_some_and(|b| b.len() < 1100));
+    let info = task.status(&db).await?.expect("the task is pending");
+    assert!(matches!(
+        info.step::<Tasks>()?,
+        Tasks::Upload(Upload::Store(Store(data, true))) if data.len() == 1024
+    ));
+
+    Worker::<Tasks>::new(db.clone())
+        .with_keep_done(true)
+        .run_until_idle()
+        .await?;
+
+    let info = task.status(&db).await?.expect("the task is kept");
+    assert_eq!(info.result, Some(json!(1024)));
+    Ok(())
+}
+
+#[sqlx::test(migrations = false)]
+async fn compensates_encoded_steps(db: PgPool) -> anyhow::Result<()> {
+    pg_task::migrate(&db).await?;
+    sqlx::query("CREATE TABLE deleted (size INT NOT NULL)")
+        .execute(&db)
+        .await?;
+    pg_task::enqueue(&db, &Tasks::Upload(Store(vec![7; 1024], false).into())).await?;
+
+    Worker::<Tasks>::new(db.clone()).run_until_idle().await?;
+
+    let deleted: Vec<i32> = sqlx::query_scalar("SELECT size FROM deleted")
+        .fetch_all(&db)
+        .await?;
+    assert_eq!(deleted, [1024]);
+    Ok(())
+}
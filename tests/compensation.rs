@@ -0,0 +1,121 @@
+use async_trait::async_trait;
+use pg_task::{Compensate, NextStep, Step, StepContext, StepError, StepResult, Worker};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::time::Duration;
+
+pg_task::task!(Order {
+    Reserve,
+    Notify,
+    Charge,
+    Ship
+});
+pg_task::scheduler!(Tasks { Order });
+
+/// The flag tells if the order is in stock, so it could be shipped
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Reserve(bool);
+#[async_trait]
+impl Step<Order> for Reserve {
+    async fn step(self, db: &PgPool, _ctx: &StepContext) -> StepResult<Order> {
+        log(db, "reserve").await?;
+        NextStep::now(Notify(self.0))
+    }
+}
+#[async_trait]
+impl Compensate<Order> for Reserve {
+    async fn compensate(self, db: &PgPool, _ctx: &StepContext) -> Result<(), StepError> {
+        log(db, "release").await
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Notify(bool);
+#[async_trait]
+impl Step<Order> for Notify {
+    async fn step(self, db: &PgPool, _ctx: &StepContext) -> StepResult<Order> {
+        log(db, "notify").await?;
+        NextStep::now(Charge(self.0))
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Charge(bool);
+#[async_trait]
+impl Step<Order> for Charge {
+    async fn step(self, db: &PgPool, _ctx: &StepContext) -> StepResult<Order> {
+        log(db, "charge").await?;
+        NextStep::now(Ship(self.0))
+    }
+}
+#[async_trait]
+impl Compensate<Order> for Charge {
+    async fn compensate(self, db: &PgPool, _ctx: &StepContext) -> Result<(), StepError> {
+        log(db, "refund").await
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Ship(bool);
+#[async_trait]
+impl Step<Order> for Ship {
+    const RETRY_LIMIT: i32 = 1;
+    const RETRY_DELAY: Duration = Duration::ZERO;
+
+    async fn step(self, db: &PgPool, _ctx: &StepContext) -> StepResult<Order> {
+        log(db, "ship").await?;
+        if !self.0 {
+            return Err("out of stock".into());
+        }
+        NextStep::none()
+    }
+}
+
+async fn log(db: &PgPool, action: &str) -> Result<(), StepError> {
+    sqlx::query("INSERT INTO actions (action) VALUES ($1)")
+        .bind(action)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+async fn setup(db: &PgPool) -> anyhow::Result<()> {
+    pg_task::migrate(db).await?;
+    sqlx::query("CREATE TABLE actions (id SERIAL, action TEXT NOT NULL)")
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+async fn actions(db: &PgPool) -> anyhow::Result<Vec<String>> {
+    Ok(sqlx::query_scalar("SELECT action FROM actions ORDER BY id")
+        .fetch_all(db)
+        .await?)
+}
+
+#[sqlx::test(migrations = false)]
+async fn undoes_completed_steps_in_reverse_order(db: PgPool) -> anyhow::Result<()> {
+    setup(&db).await?;
+    let task = pg_task::enqueue(&db, &Tasks::Order(Reserve(false).into())).await?;
+
+    Worker::<Tasks>::new(db.clone()).run_until_idle().await?;
+
+    assert_eq!(
+        actions(&db).await?,
+        ["reserve", "notify", "charge", "ship", "ship", "refund", "release"]
+    );
+    let info = task.status(&db).await?.expect("the task is failed");
+    assert_eq!(info.error.as_deref(), Some("out of stock"));
+    Ok(())
+}
+
+#[sqlx::test(migrations = false)]
+async fn keeps_completed_steps_of_successful_tasks(db: PgPool) -> anyhow::Result<()> {
+    setup(&db).await?;
+    pg_task::enqueue(&db, &Tasks::Order(Reserve(true).into())).await?;
+
+    Worker::<Tasks>::new(db.clone()).run_until_idle().await?;
+
+    assert_eq!(actions(&db).await?, ["reserve", "notify", "charge", "ship"]);
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use async_trait::async_trait;
+use chrono::{SubsecRound, Utc};
+use pg_task::{test::ManualClock, NextStep, Step, StepContext, StepResult, Worker};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::time::Duration;
+
+pg_task::task!(Otp {
+    Generate,
+    Send,
+    WaitApproval
+});
+pg_task::scheduler!(Tasks { Otp });
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Generate(u64);
+#[async_trait]
+impl Step<Otp> for Generate {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Otp> {
+        NextStep::delay(Send, Duration::from_secs(self.0))
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Send;
+#[async_trait]
+impl Step<Otp> for Send {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Otp> {
+        NextStep::done_with("sent")
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WaitApproval;
+#[async_trait]
+impl Step<Otp> for WaitApproval {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Otp> {
+        NextStep::wait_signal(Send)
+    }
+}
+
+#[sqlx::test(migrations = false)]
+async fn finishes_before_the_deadline(db: PgPool) -> anyhow::Result<()> {
+    pg_task::migrate(&db).await?;
+    let start = Utc::now().trunc_subsecs(0);
+    let clock = ManualClock::starting_at(start);
+    let worker = Worker::<Tasks>::new(db.clone()).with_clock(clock.clone());
+    let deadline = start + chrono::Duration::minutes(5);
+    let task =
+        pg_task::schedule_with_deadline(&db, &Tasks::Otp(Generate(60).into()), start, deadline)
+            .await?;
+
+    assert_eq!(worker.run_until_idle().await?, 1);
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(worker.run_until_idle().await?, 1);
+
+    let info = task.status(&db).await?.expect("the task is done");
+    assert_eq!(info.result, Some(serde_json::json!("sent")));
+    assert_eq!(info.expired_at, None);
+    Ok(())
+}
+
+#[sqlx::test(migrations = false)]
+async fn expires_instead_of_running_a_late_step(db: PgPool) -> anyhow::Result<()> {
+    pg_task::migrate(&db).await?;
+    let start = Utc::now().trunc_subsecs(0);
+    let clock = ManualClock::starting_at(start);
+    let worker = Worker::<Tasks>::new(db.clone()).with_clock(clock.clone());
+    let deadline = start + chrono::Duration::minutes(5);
+    let task = pg_task::schedule_with_deadline(
+        &db,
+        &Tasks::Otp(Generate(10 * 60).into()),
+        start,
+        deadline,
+    )
+    .await?;
+
+    assert_eq!(worker.run_until_idle().await?, 1);
+    // The delayed step is woken up at the deadline
+    clock.advance(Duration::from_secs(5 * 60));
+    assert_eq!(worker.run_until_idle().await?, 1);
+
+    let info = task.status(&db).await?.expect("the task is expired");
+    assert!(info.expired_at.is_some());
+    assert_eq!(info.result, None);
+    assert_eq!(info.step_name, "Send");
+    clock.advance(Duration::from_secs(10 * 60));
+    assert_eq!(worker.run_until_idle().await?, 0);
+    Ok(())
+}
+
+#[sqlx::test(migrations = false)]
+async fn expires_a_task_waiting_for_a_signal(db: PgPool) -> anyhow::Result<()> {
+    pg_task::migrate(&db).await?;
+    let start = Utc::now().trunc_subsecs(0);
+    let clock = ManualClock::starting_at(start);
+    let worker = Worker::<Tasks>::new(db.clone()).with_clock(clock.clone());
+    let deadline = start + chrono::Duration::minutes(5);
+    let task =
+        pg_task::schedule_with_deadline(&db, &Tasks::Otp(WaitApproval.into()), start, deadline)
+            .await?;
+
+    assert_eq!(worker.run_until_idle().await?, 1);
+    clock.advance(Duration::from_secs(5 * 60));
+    assert_eq!(worker.run_until_idle().await?, 1);
+
+    let info = task.status(&db).await?.expect("the task is expired");
+    assert!(info.expired_at.is_some());
+    Ok(())
+}
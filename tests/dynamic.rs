@@ -0,0 +1,57 @@
+use async_trait::async_trait;
+use pg_task::{
+    DynStep, DynamicStep, NextStep, Step, StepContext, StepRegistry, StepResult, Worker,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+pg_task::task!(Order { Charge });
+pg_task::scheduler!(OrderTasks { Order });
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Resize;
+#[async_trait]
+impl Step<DynStep> for Resize {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<DynStep> {
+        NextStep::done_with("resized")
+    }
+}
+
+impl DynamicStep for Resize {
+    const NAME: &'static str = "Resize";
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Charge;
+#[async_trait]
+impl Step<Order> for Charge {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Order> {
+        NextStep::done_with("charged")
+    }
+}
+
+#[sqlx::test(migrations = false)]
+async fn keeps_dynamic_and_typed_tasks_apart(db: PgPool) -> anyhow::Result<()> {
+    pg_task::migrate(&db).await?;
+    pg_task::set_step_registry(StepRegistry::new().register::<Resize>())?;
+    let dynamic = Worker::<DynStep>::new(db.clone());
+    let orders = Worker::<OrderTasks>::new(db.clone());
+    let resize = pg_task::enqueue(&db, &DynStep::new(Resize)).await?;
+    let order = pg_task::enqueue(&db, &OrderTasks::Order(Charge.into())).await?;
+
+    assert_eq!(dynamic.run_until_idle().await?, 1);
+    let info = order.status(&db).await?.expect("the order is pending");
+    assert!(!info.is_unparseable);
+    assert_eq!(info.result, None);
+    let info = resize.status(&db).await?.expect("the resize is done");
+    assert_eq!(info.result, Some(serde_json::json!("resized")));
+
+    let resize = pg_task::enqueue(&db, &DynStep::new(Resize)).await?;
+    assert_eq!(orders.run_until_idle().await?, 1);
+    let info = resize.status(&db).await?.expect("the resize is pending");
+    assert!(!info.is_unparseable);
+    assert_eq!(info.result, None);
+    let info = order.status(&db).await?.expect("the order is charged");
+    assert_eq!(info.result, Some(serde_json::json!("charged")));
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use async_trait::async_trait;
+use pg_task::{NextStep, Step, StepContext, StepResult, Worker};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::HashMap;
+
+pg_task::task!(Import {
+    Split,
+    Part,
+    Broken
+});
+pg_task::scheduler!(Tasks { Import });
+
+/// Records the split in the step transaction and fans out into the parts
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Split(bool);
+#[async_trait]
+impl Step<Import> for Split {
+    async fn step(self, _db: &PgPool, ctx: &StepContext) -> StepResult<Import> {
+        let mut tx = ctx.transaction().await?;
+        sqlx::query("INSERT INTO splits DEFAULT VALUES")
+            .execute(&mut **tx)
+            .await?;
+        if self.0 {
+            NextStep::many([Part(1), Part(2)])
+        } else {
+            NextStep::many([Broken(HashMap::from([(vec![1], 1)]))])
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Part(i32);
+#[async_trait]
+impl Step<Import> for Part {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Import> {
+        NextStep::done_with(self.0)
+    }
+}
+
+/// JSON maps can't have non-string keys, so it can't be serialized
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Broken(HashMap<Vec<u8>, i32>);
+#[async_trait]
+impl Step<Import> for Broken {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Import> {
+        NextStep::none()
+    }
+}
+
+async fn setup(db: &PgPool) -> anyhow::Result<()> {
+    pg_task::migrate(db).await?;
+    sqlx::query("CREATE TABLE splits (id SERIAL)")
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+async fn count(db: &PgPool, table: &str) -> anyhow::Result<i64> {
+    let query = format!("SELECT COUNT(*) FROM {table}");
+    Ok(sqlx::query_scalar(&query).fetch_one(db).await?)
+}
+
+#[sqlx::test(migrations = false)]
+async fn runs_the_spawned_tasks(db: PgPool) -> anyhow::Result<()> {
+    setup(&db).await?;
+    pg_task::enqueue(&db, &Tasks::Import(Split(true).into())).await?;
+
+    assert_eq!(Worker::<Tasks>::new(db.clone()).run_until_idle().await?, 3);
+
+    assert_eq!(count(&db, "splits").await?, 1);
+    Ok(())
+}
+
+#[sqlx::test(migrations = false)]
+async fn rolls_back_the_step_spawning_unserializable_tasks(db: PgPool) -> anyhow::Result<()> {
+    setup(&db).await?;
+    let task = pg_task::enqueue(&db, &Tasks::Import(Split(false).into())).await?;
+
+    assert_eq!(Worker::<Tasks>::new(db.clone()).run_until_idle().await?, 1);
+
+    let info = task.status(&db).await?.expect("the task is failed");
+    assert!(info.error.is_some());
+    assert_eq!(info.tried, 1);
+    assert_eq!(count(&db, "splits").await?, 0);
+    assert_eq!(count(&db, "pg_task").await?, 1);
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use async_trait::async_trait;
+use chrono::{DurationRound, TimeDelta, Utc};
+use pg_task::{
+    test::ManualClock, CatchUp, CronSchedule, NextStep, Step, StepContext, StepResult, Worker,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::time::Duration;
+
+pg_task::task!(Report { Send });
+pg_task::scheduler!(Tasks { Report });
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Send;
+#[async_trait]
+impl Step<Report> for Send {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Report> {
+        NextStep::none()
+    }
+}
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+
+/// Schedules the report and returns a worker with a clock at the next hour,
+/// when the first run is due
+async fn schedule(
+    db: &PgPool,
+    cron: &str,
+    catch_up: CatchUp,
+) -> anyhow::Result<(Worker<Tasks>, ManualClock)> {
+    pg_task::migrate(db).await?;
+    let schedule = CronSchedule::new(cron)?.with_catch_up(catch_up);
+    pg_task::schedule_recurring(db, "report", &Tasks::Report(Send.into()), &schedule).await?;
+    let next_hour = Utc::now().duration_trunc(TimeDelta::hours(1))? + TimeDelta::hours(1);
+    let clock = ManualClock::starting_at(next_hour);
+    let worker = Worker::<Tasks>::new(db.clone()).with_clock(clock.clone());
+    Ok((worker, clock))
+}
+
+#[sqlx::test(migrations = false)]
+async fn runs_each_time_it_is_due(db: PgPool) -> anyhow::Result<()> {
+    let (worker, clock) = schedule(&db, "0 * * * *", CatchUp::RunOnce).await?;
+
+    assert_eq!(worker.run_until_idle().await?, 1);
+    assert_eq!(worker.run_until_idle().await?, 0);
+    for _ in 0..3 {
+        clock.advance(HOUR);
+        assert_eq!(worker.run_until_idle().await?, 1);
+    }
+    Ok(())
+}
+
+#[sqlx::test(migrations = false)]
+async fn runs_once_for_the_missed_runs(db: PgPool) -> anyhow::Result<()> {
+    let (worker, clock) = schedule(&db, "0 * * * *", CatchUp::RunOnce).await?;
+
+    // No worker was running for 5 hours, so 6 runs are missed
+    clock.advance(5 * HOUR);
+    assert_eq!(worker.run_until_idle().await?, 1);
+    assert_eq!(worker.run_until_idle().await?, 0);
+
+    clock.advance(HOUR);
+    assert_eq!(worker.run_until_idle().await?, 1);
+    Ok(())
+}
+
+#[sqlx::test(migrations = false)]
+async fn runs_each_missed_run(db: PgPool) -> anyhow::Result<()> {
+    let (worker, clock) = schedule(&db, "0 * * * *", CatchUp::RunAll).await?;
+
+    clock.advance(5 * HOUR);
+    assert_eq!(worker.run_until_idle().await?, 6);
+    assert_eq!(worker.run_until_idle().await?, 0);
+
+    clock.advance(HOUR);
+    assert_eq!(worker.run_until_idle().await?, 1);
+    Ok(())
+}
+
+#[sqlx::test(migrations = false)]
+async fn runs_up_to_100_missed_runs(db: PgPool) -> anyhow::Result<()> {
+    let (worker, clock) = schedule(&db, "* * * * *", CatchUp::RunAll).await?;
+
+    clock.advance(3 * HOUR);
+    assert_eq!(worker.run_until_idle().await?, 100);
+    // The rest of the missed runs are dropped
+    assert_eq!(worker.run_until_idle().await?, 0);
+
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(worker.run_until_idle().await?, 1);
+    Ok(())
+}
+
+#[sqlx::test(migrations = false)]
+async fn skips_the_missed_runs(db: PgPool) -> anyhow::Result<()> {
+    let (worker, clock) = schedule(&db, "0 * * * *", CatchUp::Skip).await?;
+
+    clock.advance(5 * HOUR);
+    assert_eq!(worker.run_until_idle().await?, 0);
+
+    clock.advance(HOUR);
+    assert_eq!(worker.run_until_idle().await?, 1);
+    Ok(())
+}
@@ -0,0 +1,156 @@
+use async_trait::async_trait;
+use chrono::{DateTime, SubsecRound, Utc};
+use pg_task::{
+    test::ManualClock, EnqueueOptions, FatalError, NextStep, RetryPolicy, RetryStrategy, Step,
+    StepContext, StepResult, Worker,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Uuid, PgPool};
+use std::time::Duration;
+
+pg_task::task!(Job {
+    Failing,
+    Flaky,
+    Invalid
+});
+pg_task::scheduler!(Tasks { Job });
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Failing;
+#[async_trait]
+impl Step<Job> for Failing {
+    const RETRY_LIMIT: i32 = 2;
+    const RETRY_STRATEGY: RetryStrategy = RetryStrategy::Exponential {
+        initial: Duration::from_secs(10),
+        factor: 2,
+        max: Duration::from_secs(60 * 60),
+    };
+
+    async fn step(self, _db: &PgPool, ctx: &StepContext) -> StepResult<Job> {
+        Err(format!("attempt {}", ctx.attempt).into())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Flaky;
+#[async_trait]
+impl Step<Job> for Flaky {
+    const RETRY_LIMIT: i32 = 3;
+    const RETRY_DELAY: Duration = Duration::ZERO;
+
+    async fn step(self, _db: &PgPool, ctx: &StepContext) -> StepResult<Job> {
+        if ctx.attempt < 3 {
+            return Err("not yet".into());
+        }
+        NextStep::done_with(ctx.attempt)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Invalid;
+#[async_trait]
+impl Step<Job> for Invalid {
+    const RETRY_LIMIT: i32 = 5;
+
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Job> {
+        Err(FatalError::new("invalid input").into())
+    }
+}
+
+/// A clock at a whole second, so the scheduled times are stored in the db
+/// without rounding
+fn clock() -> (ManualClock, DateTime<Utc>) {
+    let start = Utc::now().trunc_subsecs(0);
+    (ManualClock::starting_at(start), start)
+}
+
+async fn find(db: &PgPool, id: Uuid) -> anyhow::Result<pg_task::TaskInfo> {
+    Ok(pg_task::find(db, id).await?.expect("the task is removed"))
+}
+
+#[sqlx::test(migrations = false)]
+async fn backs_off_exponentially_then_fails(db: PgPool) -> anyhow::Result<()> {
+    pg_task::migrate(&db).await?;
+    let (clock, start) = clock();
+    let worker = Worker::<Tasks>::new(db.clone()).with_clock(clock.clone());
+    let task = pg_task::enqueue_with(
+        &db,
+        &Tasks::Job(Failing.into()),
+        EnqueueOptions::new().with_at(start),
+    )
+    .await?;
+
+    assert_eq!(worker.run_until_idle().await?, 1);
+    let info = find(&db, task.id()).await?;
+    assert_eq!((info.tried, info.error), (1, None));
+    assert_eq!(info.wakeup_at, start + chrono::Duration::seconds(10));
+
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(worker.run_until_idle().await?, 1);
+    let info = find(&db, task.id()).await?;
+    assert_eq!(info.tried, 2);
+    assert_eq!(info.wakeup_at, start + chrono::Duration::seconds(30));
+
+    clock.advance(Duration::from_secs(19));
+    assert_eq!(worker.run_until_idle().await?, 0);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(worker.run_until_idle().await?, 1);
+    let info = find(&db, task.id()).await?;
+    assert_eq!(info.error.as_deref(), Some("attempt 3"));
+    assert_eq!(info.errors.as_array().map(Vec::len), Some(3));
+
+    clock.advance(Duration::from_secs(60 * 60));
+    assert_eq!(worker.run_until_idle().await?, 0);
+    Ok(())
+}
+
+#[sqlx::test(migrations = false)]
+async fn succeeds_on_a_retry(db: PgPool) -> anyhow::Result<()> {
+    pg_task::migrate(&db).await?;
+    let task = pg_task::enqueue(&db, &Tasks::Job(Flaky.into())).await?;
+
+    let processed = Worker::<Tasks>::new(db.clone()).run_until_idle().await?;
+
+    assert_eq!(processed, 3);
+    let info = find(&db, task.id()).await?;
+    assert_eq!(info.error, None);
+    assert_eq!(info.result, Some(serde_json::json!(3)));
+    Ok(())
+}
+
+#[sqlx::test(migrations = false)]
+async fn fails_right_away_on_fatal_errors(db: PgPool) -> anyhow::Result<()> {
+    pg_task::migrate(&db).await?;
+    let task = pg_task::enqueue(&db, &Tasks::Job(Invalid.into())).await?;
+
+    let processed = Worker::<Tasks>::new(db.clone()).run_until_idle().await?;
+
+    assert_eq!(processed, 1);
+    let info = find(&db, task.id()).await?;
+    assert_eq!(info.error.as_deref(), Some("invalid input"));
+    assert_eq!(info.tried, 1);
+    Ok(())
+}
+
+#[sqlx::test(migrations = false)]
+async fn overrides_the_retry_settings_per_task(db: PgPool) -> anyhow::Result<()> {
+    pg_task::migrate(&db).await?;
+    let (clock, start) = clock();
+    let worker = Worker::<Tasks>::new(db.clone()).with_clock(clock.clone());
+    let policy = RetryPolicy {
+        limit: Some(1),
+        delay: Some(Duration::from_secs(5)),
+    };
+    let options = EnqueueOptions::new().with_at(start).with_retry(policy);
+    let task = pg_task::enqueue_with(&db, &Tasks::Job(Failing.into()), options).await?;
+
+    assert_eq!(worker.run_until_idle().await?, 1);
+    let info = find(&db, task.id()).await?;
+    assert_eq!(info.wakeup_at, start + chrono::Duration::seconds(5));
+
+    clock.advance(Duration::from_secs(5));
+    assert_eq!(worker.run_until_idle().await?, 1);
+    let info = find(&db, task.id()).await?;
+    assert_eq!(info.error.as_deref(), Some("attempt 2"));
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use async_trait::async_trait;
+use pg_task::{Listener, NextStep, Step, StepContext, StepResult, Worker};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+pg_task::task!(Order { Charge });
+pg_task::scheduler!(OrderTasks { Order });
+
+pg_task::task!(Email { Send });
+pg_task::scheduler!(EmailTasks { Email });
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Charge;
+#[async_trait]
+impl Step<Order> for Charge {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Order> {
+        NextStep::done_with("charged")
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Send;
+#[async_trait]
+impl Step<Email> for Send {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Email> {
+        NextStep::done_with("sent")
+    }
+}
+
+#[sqlx::test(migrations = false)]
+async fn runs_only_the_tasks_of_the_worker_enum(db: PgPool) -> anyhow::Result<()> {
+    pg_task::migrate(&db).await?;
+    let listener = Listener::new();
+    let orders = Worker::<OrderTasks>::new(db.clone()).with_listener(listener.clone());
+    let emails = Worker::<EmailTasks>::new(db.clone()).with_listener(listener);
+    let email = pg_task::enqueue(&db, &EmailTasks::Email(Send.into())).await?;
+    let order = pg_task::enqueue(&db, &OrderTasks::Order(Charge.into())).await?;
+
+    assert_eq!(orders.run_until_idle().await?, 1);
+    let info = email.status(&db).await?.expect("the email is pending");
+    assert!(!info.is_unparseable);
+    assert_eq!(info.result, None);
+
+    assert_eq!(emails.run_until_idle().await?, 1);
+    let info = email.status(&db).await?.expect("the email is sent");
+    assert_eq!(info.result, Some(serde_json::json!("sent")));
+    let info = order.status(&db).await?.expect("the order is charged");
+    assert_eq!(info.result, Some(serde_json::json!("charged")));
+    Ok(())
+}
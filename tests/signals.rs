@@ -0,0 +1,100 @@
+use async_trait::async_trait;
+use pg_task::{test::ManualClock, NextStep, Step, StepContext, StepResult, Worker};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::time::Duration;
+
+pg_task::task!(Approval {
+    Request,
+    RequestWithTimeout,
+    Approved,
+    TimedOut
+});
+pg_task::scheduler!(Tasks { Approval });
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Request;
+#[async_trait]
+impl Step<Approval> for Request {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Approval> {
+        NextStep::wait_signal(Approved)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RequestWithTimeout;
+#[async_trait]
+impl Step<Approval> for RequestWithTimeout {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Approval> {
+        NextStep::wait_signal_with_timeout(Approved, Duration::from_secs(60), TimedOut)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Approved;
+#[async_trait]
+impl Step<Approval> for Approved {
+    async fn step(self, _db: &PgPool, ctx: &StepContext) -> StepResult<Approval> {
+        let approver: Option<String> = ctx.signal().await?;
+        NextStep::done_with(approver)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TimedOut;
+#[async_trait]
+impl Step<Approval> for TimedOut {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Approval> {
+        NextStep::done_with("timed out")
+    }
+}
+
+#[sqlx::test(migrations = false)]
+async fn runs_the_next_step_on_a_signal(db: PgPool) -> anyhow::Result<()> {
+    pg_task::migrate(&db).await?;
+    let worker = Worker::<Tasks>::new(db.clone());
+    let task = pg_task::enqueue(&db, &Tasks::Approval(Request.into())).await?;
+
+    assert_eq!(worker.run_until_idle().await?, 1);
+    let info = task.status(&db).await?.expect("the task is waiting");
+    assert!(info.is_awaiting_signal);
+    assert_eq!(worker.run_until_idle().await?, 0);
+
+    assert!(pg_task::signal(&db, task.id(), &"alice").await?);
+    assert_eq!(worker.run_until_idle().await?, 1);
+    let info = task.status(&db).await?.expect("the task is done");
+    assert_eq!(info.result, Some(serde_json::json!("alice")));
+    Ok(())
+}
+
+#[sqlx::test(migrations = false)]
+async fn keeps_a_signal_received_before_waiting(db: PgPool) -> anyhow::Result<()> {
+    pg_task::migrate(&db).await?;
+    let task = pg_task::enqueue(&db, &Tasks::Approval(Request.into())).await?;
+
+    assert!(pg_task::signal(&db, task.id(), &"bob").await?);
+    assert_eq!(Worker::<Tasks>::new(db.clone()).run_until_idle().await?, 2);
+
+    let info = task.status(&db).await?.expect("the task is done");
+    assert_eq!(info.result, Some(serde_json::json!("bob")));
+    Ok(())
+}
+
+#[sqlx::test(migrations = false)]
+async fn runs_the_timeout_step_without_a_signal(db: PgPool) -> anyhow::Result<()> {
+    pg_task::migrate(&db).await?;
+    let task = pg_task::enqueue(&db, &Tasks::Approval(RequestWithTimeout.into())).await?;
+    let clock = ManualClock::new();
+    let worker = Worker::<Tasks>::new(db.clone()).with_clock(clock.clone());
+
+    assert_eq!(worker.run_until_idle().await?, 1);
+    clock.advance(Duration::from_secs(59));
+    assert_eq!(worker.run_until_idle().await?, 0);
+    clock.advance(Duration::from_secs(1));
+    // Switching to the timeout step and running it
+    assert_eq!(worker.run_until_idle().await?, 2);
+
+    let info = task.status(&db).await?.expect("the task is done");
+    assert_eq!(info.result, Some(serde_json::json!("timed out")));
+    Ok(())
+}
@@ -0,0 +1,87 @@
+use async_trait::async_trait;
+use pg_task::{EnqueueOptions, NextStep, Step, StepContext, StepResult, Worker};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+pg_task::task!(Sync { Fetch, Fail });
+pg_task::scheduler!(Tasks { Sync });
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Fetch(i32);
+#[async_trait]
+impl Step<Sync> for Fetch {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Sync> {
+        NextStep::none()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Fail;
+#[async_trait]
+impl Step<Sync> for Fail {
+    async fn step(self, _db: &PgPool, _ctx: &StepContext) -> StepResult<Sync> {
+        Err("boom".into())
+    }
+}
+
+fn fetch(user: i32) -> Tasks {
+    Tasks::Sync(Fetch(user).into())
+}
+
+#[sqlx::test(migrations = false)]
+async fn deduplicates_pending_tasks(db: PgPool) -> anyhow::Result<()> {
+    pg_task::migrate(&db).await?;
+
+    let first = pg_task::enqueue_unique(&db, &fetch(1), "sync-user-1").await?;
+    let same = pg_task::enqueue_unique(&db, &fetch(2), "sync-user-1").await?;
+    let other = pg_task::enqueue_unique(&db, &fetch(3), "sync-user-3").await?;
+
+    assert_eq!(first.id(), same.id());
+    assert_ne!(first.id(), other.id());
+    let pending = pg_task::test::enqueued_tasks::<Tasks>(&db, None).await?;
+    assert!(matches!(
+        pending[..],
+        [
+            Tasks::Sync(Sync::Fetch(Fetch(1))),
+            Tasks::Sync(Sync::Fetch(Fetch(3)))
+        ]
+    ));
+    Ok(())
+}
+
+#[sqlx::test(migrations = false)]
+async fn combines_the_key_with_other_options(db: PgPool) -> anyhow::Result<()> {
+    pg_task::migrate(&db).await?;
+
+    let options = || {
+        EnqueueOptions::new()
+            .with_unique_key("sync-user-1")
+            .with_priority(5)
+    };
+    let first = pg_task::enqueue_with(&db, &fetch(1), options()).await?;
+    let same = pg_task::enqueue_with(&db, &fetch(1), options()).await?;
+
+    assert_eq!(first.id(), same.id());
+    let info = first.status(&db).await?.expect("the task is pending");
+    assert_eq!(info.priority, 5);
+    Ok(())
+}
+
+#[sqlx::test(migrations = false)]
+async fn releases_the_key_of_finished_tasks(db: PgPool) -> anyhow::Result<()> {
+    pg_task::migrate(&db).await?;
+    let worker = Worker::<Tasks>::new(db.clone());
+
+    let done = pg_task::enqueue_unique(&db, &fetch(1), "sync-user-1").await?;
+    worker.run_until_idle().await?;
+    let next = pg_task::enqueue_unique(&db, &fetch(1), "sync-user-1").await?;
+    assert_ne!(done.id(), next.id());
+    worker.run_until_idle().await?;
+
+    let failed = pg_task::enqueue_unique(&db, &Tasks::Sync(Fail.into()), "sync-user-1").await?;
+    worker.run_until_idle().await?;
+    assert!(failed.status(&db).await?.is_some_and(|t| t.error.is_some()));
+    let next = pg_task::enqueue_unique(&db, &fetch(1), "sync-user-1").await?;
+    assert_ne!(failed.id(), next.id());
+    Ok(())
+}